@@ -3,14 +3,37 @@
 pub struct XrpcUri<'a> {
   base_uri: &'a str,
   nsid: &'a str,
+  secure: bool,
 }
 impl<'a> XrpcUri<'a> {
+  #[must_use]
   pub const fn new(base_uri: &'a str, nsid: &'a str) -> Self {
-    Self { base_uri, nsid }
+    Self {
+      base_uri,
+      nsid,
+      secure: true,
+    }
   }
 
+  /// Same as [`XrpcUri::new`], but connecting through `ws://` instead of `wss://`.
+  /// This should only be used for local servers, like in tests.
+  #[must_use]
+  pub const fn insecure(base_uri: &'a str, nsid: &'a str) -> Self {
+    Self {
+      base_uri,
+      nsid,
+      secure: false,
+    }
+  }
+
+  #[must_use]
   pub fn to_uri(&self) -> String {
-    let XrpcUri { base_uri, nsid } = self;
-    format!("wss://{base_uri}/xrpc/{nsid}")
+    let XrpcUri {
+      base_uri,
+      nsid,
+      secure,
+    } = self;
+    let scheme = if *secure { "wss" } else { "ws" };
+    format!("{scheme}://{base_uri}/xrpc/{nsid}")
   }
 }
@@ -1,14 +1,18 @@
-//! This file defines the [`FrameHeader`] and [`Frame`] types, which are used to parse the payloads sent by the subscription through the event stream.
+//! This file defines the [`FrameHeader`] and [`Frame`] types.
+//!
+//! They are used to parse the payloads sent by the subscription through the event stream,
+//! and to encode them back into binary messages, like a server would.
 //! You can read more about the specs for these types in the [`ATProto documentation`](https://atproto.com/specs/event-stream)
 
 #[cfg(test)]
 mod tests;
 
+use bytes::Bytes;
 use cbor4ii::core::utils::IoReader;
 use ipld_core::ipld::Ipld;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_ipld_dagcbor::de::Deserializer;
-use std::io::Cursor;
+use std::{collections::TryReserveError, io::Cursor};
 
 /// An error type for this crate.
 #[derive(Debug, thiserror::Error)]
@@ -19,6 +23,8 @@ pub enum Error {
   EmptyPayload(Ipld),
   #[error("Ipld Decoding error: {0}")]
   IpldDecoding(#[from] serde_ipld_dagcbor::DecodeError<std::io::Error>),
+  #[error("Ipld Encoding error: {0}")]
+  IpldEncoding(#[from] serde_ipld_dagcbor::EncodeError<TryReserveError>),
 }
 
 /// Represents the header of a frame. It's the first [`Ipld`] object in a Binary payload sent by a subscription.
@@ -49,13 +55,30 @@ impl TryFrom<Ipld> for FrameHeader {
   }
 }
 
+impl From<FrameHeader> for Ipld {
+  fn from(header: FrameHeader) -> Self {
+    let mut map = std::collections::BTreeMap::new();
+    match header {
+      FrameHeader::Message { t } => {
+        map.insert(String::from("op"), Self::Integer(1));
+        map.insert(String::from("t"), Self::String(t));
+      }
+      FrameHeader::Error => {
+        map.insert(String::from("op"), Self::Integer(-1));
+      }
+    }
+    Self::Map(map)
+  }
+}
+
 /// Represents a frame sent by a subscription. It's the second [`Ipld`] object in a Binary payload sent by a subscription.
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
-#[serde(untagged)]
+///
+/// The `data` of a message frame is a slice of the received binary payload, so no bytes are copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Frame {
   Message {
     t: String,
-    data: Vec<u8>,
+    data: Bytes,
   },
   // Error payloads all have the following fields:
   // - error (string, required): the error type name, with no namespace or # prefix
@@ -67,26 +90,93 @@ pub enum Frame {
   },
 }
 
-impl TryFrom<Vec<u8>> for Frame {
+/// The body of an error frame.
+#[derive(Deserialize, Serialize)]
+struct ErrorPayload<S = String> {
+  error: S,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  message: Option<S>,
+}
+
+impl Frame {
+  /// Builds a message frame of type `t`, encoding the `payload` as DAG-CBOR.
+  ///
+  /// # Errors
+  /// Returns an error if the payload could not be encoded.
+  pub fn message<T: Serialize>(t: impl Into<String>, payload: &T) -> Result<Self, Error> {
+    Ok(Self::Message {
+      t: t.into(),
+      data: serde_ipld_dagcbor::to_vec(payload)?.into(),
+    })
+  }
+
+  /// Reads the `seq` field of a message frame's payload, if it has one.
+  ///
+  /// This only decodes that field, so it's cheaper than fully decoding the payload.
+  #[must_use]
+  pub fn seq(&self) -> Option<i64> {
+    #[derive(Deserialize)]
+    struct Seq {
+      seq: Option<i64>,
+    }
+
+    match self {
+      Self::Message { data, .. } => serde_ipld_dagcbor::from_slice::<Seq>(data).ok()?.seq,
+      Self::Error { .. } => None,
+    }
+  }
+
+  /// Encodes the frame into a binary payload, which is the header followed by the frame body.
+  ///
+  /// # Errors
+  /// Returns an error if the header or the error frame body could not be encoded.
+  pub fn encode(&self) -> Result<Bytes, Error> {
+    match self {
+      Self::Message { t, data } => {
+        let header = Ipld::from(FrameHeader::Message { t: t.clone() });
+        let mut buf = serde_ipld_dagcbor::to_vec(&header)?;
+        buf.extend_from_slice(data);
+        Ok(buf.into())
+      }
+      Self::Error { error, message } => {
+        let mut buf = serde_ipld_dagcbor::to_vec(&Ipld::from(FrameHeader::Error))?;
+        buf.extend(serde_ipld_dagcbor::to_vec(&ErrorPayload {
+          error: error.as_str(),
+          message: message.as_deref(),
+        })?);
+        Ok(buf.into())
+      }
+    }
+  }
+}
+
+impl TryFrom<Bytes> for Frame {
   type Error = self::Error;
 
-  fn try_from(value: Vec<u8>) -> Result<Self, <Self as TryFrom<Vec<u8>>>::Error> {
-    let mut cursor = Cursor::new(value);
+  fn try_from(value: Bytes) -> Result<Self, <Self as TryFrom<Bytes>>::Error> {
+    let mut cursor = Cursor::new(&*value);
     let mut deserializer = Deserializer::from_reader(IoReader::new(&mut cursor));
     let header = Deserialize::deserialize(&mut deserializer)?;
 
     // Error means the stream did not end (trailing data), which implies a second IPLD (in this case, the payload).
     // If the stream ended, the payload is empty, in which case we error.
     let data = if deserializer.end().is_err() {
+      #[expect(
+        clippy::cast_possible_truncation,
+        reason = "The position is within a `Bytes`."
+      )]
       let pos = cursor.position() as usize;
-      cursor.get_mut().drain(pos..).collect()
+      value.slice(pos..)
     } else {
       return Err(Error::EmptyPayload(header));
     };
 
     match FrameHeader::try_from(header)? {
       FrameHeader::Message { t } => Ok(Self::Message { t, data }),
-      FrameHeader::Error => Ok(serde_ipld_dagcbor::from_reader(data.as_slice())?),
+      FrameHeader::Error => {
+        let ErrorPayload { error, message } = serde_ipld_dagcbor::from_reader(&*data)?;
+        Ok(Self::Error { error, message })
+      }
     }
   }
 }
@@ -1,7 +1,7 @@
 use super::*;
 
 fn serialized_data(s: &str) -> Vec<u8> {
-  assert!(s.len() % 2 == 0);
+  assert!(s.len().is_multiple_of(2));
   let b2u = |b: u8| match b {
     b'0'..=b'9' => b - b'0',
     b'a'..=b'f' => b - b'a' + 10,
@@ -43,19 +43,65 @@ fn deserialize_invalid_frame_header() {
     let data = serialized_data("a2626f700261746723636f6d6d6974");
     let ipld = serde_ipld_dagcbor::from_slice::<Ipld>(&data).expect("failed to deserialize");
     let result = FrameHeader::try_from(ipld);
-    assert_eq!(
-      result.expect_err("must be failed").to_string(),
-      "invalid frame type"
-    );
+    assert!(matches!(
+      result.expect_err("must be failed"),
+      Error::UnknownFrameType(_)
+    ));
   }
   {
     // {"op": -2}
     let data = serialized_data("a1626f7021");
     let ipld = serde_ipld_dagcbor::from_slice::<Ipld>(&data).expect("failed to deserialize");
     let result = FrameHeader::try_from(ipld);
-    assert_eq!(
-      result.expect_err("must be failed").to_string(),
-      "invalid frame type"
-    );
+    assert!(matches!(
+      result.expect_err("must be failed"),
+      Error::UnknownFrameType(_)
+    ));
+  }
+}
+
+#[test]
+fn deserialize_message_frame_without_copying() {
+  // {"op": 1, "t": "#commit"}, followed by {"a": 1}
+  let data = Bytes::from(serialized_data("a2626f700161746723636f6d6d6974a1616101"));
+  let frame = Frame::try_from(data.clone()).expect("failed to deserialize");
+  let Frame::Message { t, data: payload } = frame else {
+    panic!("expected a message frame");
+  };
+  assert_eq!(t, "#commit");
+  assert_eq!(payload, serialized_data("a1616101"));
+  assert!(data.as_ptr_range().contains(&payload.as_ptr()));
+}
+
+#[test]
+fn encode_message_frame() {
+  let frame = Frame::Message {
+    t: String::from("#commit"),
+    data: Bytes::from(serialized_data("a1616101")),
+  };
+  let data = frame.encode().expect("failed to serialize");
+  // {"t": "#commit", "op": 1}, followed by {"a": 1}, as DAG-CBOR sorts keys by length first.
+  assert_eq!(
+    data,
+    serialized_data("a261746723636f6d6d6974626f7001a1616101")
+  );
+  assert_eq!(Frame::try_from(data).expect("failed to deserialize"), frame);
+}
+
+#[test]
+fn encode_error_frame() {
+  let frames = [
+    Frame::Error {
+      error: String::from("FutureCursor"),
+      message: Some(String::from("Cursor in the future.")),
+    },
+    Frame::Error {
+      error: String::from("ConsumerTooSlow"),
+      message: None,
+    },
+  ];
+  for frame in frames {
+    let data = frame.encode().expect("failed to serialize");
+    assert_eq!(Frame::try_from(data).expect("failed to deserialize"), frame);
   }
 }
@@ -0,0 +1,108 @@
+//! This file provides the [`CountLayer`], which counts the payloads handled for each type.
+
+use std::{
+  collections::BTreeMap,
+  sync::{Arc, Mutex, PoisonError},
+};
+
+use bytes::Bytes;
+
+use super::Layer;
+use crate::atrium_xrpc_wss::subscriptions::{ConnectionHandler, ProcessedPayload};
+
+/// How many payload types are counted separately, which is well above the amount of types
+/// defined by the lexicons.
+pub const MAX_TYPES: usize = 32;
+/// The type under which the payloads of any other type are counted, once [`MAX_TYPES`] is reached.
+pub const OTHER: &str = "other";
+
+/// The counts of payloads of a single type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+  /// Payloads that were processed.
+  pub processed: u64,
+  /// Payloads that were ignored by the handler.
+  pub ignored: u64,
+  /// Payloads that failed to be handled.
+  pub failed: u64,
+}
+
+/// Shared counters for each payload type, updated by the [`CountLayer`].
+///
+/// The types come from the frames, so only the first [`MAX_TYPES`] are counted separately,
+/// and the payloads of the types seen afterwards are counted as [`OTHER`].
+#[derive(Debug, Clone, Default)]
+pub struct Counters(Arc<Mutex<BTreeMap<String, Counts>>>);
+
+impl Counters {
+  /// Returns the current counts for each payload type.
+  #[must_use]
+  pub fn snapshot(&self) -> BTreeMap<String, Counts> {
+    self
+      .0
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+      .clone()
+  }
+
+  fn update(&self, t: &str, f: impl FnOnce(&mut Counts)) {
+    let mut map = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+    let t = if map.len() < MAX_TYPES || map.contains_key(t) {
+      t
+    } else {
+      OTHER
+    };
+    match map.get_mut(t) {
+      Some(counts) => f(counts),
+      None => f(map.entry(t.to_owned()).or_default()),
+    }
+  }
+}
+
+/// A [`Layer`] that counts the payloads handled for each type in the given [`Counters`].
+#[derive(Debug, Clone, Default)]
+pub struct CountLayer(Counters);
+
+impl CountLayer {
+  #[must_use]
+  pub const fn new(counters: Counters) -> Self {
+    Self(counters)
+  }
+}
+
+impl<H: ConnectionHandler + Sync> Layer<H> for CountLayer {
+  type Handler = Count<H>;
+
+  fn layer(&self, inner: H) -> Self::Handler {
+    Count {
+      inner,
+      counters: self.0.clone(),
+    }
+  }
+}
+
+/// The handler wrapped by the [`CountLayer`].
+#[derive(Debug, Clone)]
+pub struct Count<H> {
+  inner: H,
+  counters: Counters,
+}
+
+impl<H: ConnectionHandler + Sync> ConnectionHandler for Count<H> {
+  type HandledData = H::HandledData;
+  type HandlingError = H::HandlingError;
+
+  async fn handle_payload(
+    &self,
+    t: &str,
+    payload: Bytes,
+  ) -> Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError> {
+    let res = self.inner.handle_payload(t, payload).await;
+    self.counters.update(t, |counts| match &res {
+      Ok(Some(_)) => counts.processed += 1,
+      Ok(None) => counts.ignored += 1,
+      Err(_) => counts.failed += 1,
+    });
+    res
+  }
+}
@@ -0,0 +1,99 @@
+//! This file defines the [`Layer`] abstraction, used to wrap a [`ConnectionHandler`] with middleware.
+//!
+//! Since every [`Handler`](super::repositories::Handler) is a [`ConnectionHandler`], layers also apply
+//! to the processing hooks of the handlers. Layers are composed with [`Layers`], like so:
+//! ```no_run
+//! # use std::time::Duration;
+//! # use firehose_client::{
+//! #   atrium_xrpc_wss::subscriptions::layers::{CountLayer, Counters, Layers, TimeoutLayer, TraceLayer},
+//! #   atrium_xrpc_wss_client::subscriptions::repositories::firehose::Firehose,
+//! # };
+//! let counters = Counters::default();
+//! let handler = Layers::new()
+//!   .layer(TraceLayer)
+//!   .layer(CountLayer::new(counters.clone()))
+//!   .layer(TimeoutLayer::new(Duration::from_secs(5)))
+//!   .handler(Firehose::default());
+//! ```
+//! The resulting handler can then be given to `Repositories::builder()`, or the stack itself can be
+//! given to `Repositories::layered()` along with the handler.
+
+#[cfg(test)]
+mod tests;
+
+mod count;
+mod timeout;
+mod trace;
+pub use count::{Count, CountLayer, Counters, Counts, MAX_TYPES, OTHER};
+pub use timeout::{Timeout, TimeoutError, TimeoutLayer};
+pub use trace::{Trace, TraceLayer};
+
+use super::ConnectionHandler;
+
+/// A trait that defines a layer, which wraps a [`ConnectionHandler`] into another one.
+pub trait Layer<H> {
+  /// The wrapped handler.
+  type Handler: ConnectionHandler;
+
+  /// Wraps the `inner` handler.
+  fn layer(&self, inner: H) -> Self::Handler;
+}
+
+/// A [`Layer`] that doesn't wrap the handler.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl<H: ConnectionHandler> Layer<H> for Identity {
+  type Handler = H;
+
+  fn layer(&self, inner: H) -> Self::Handler {
+    inner
+  }
+}
+
+/// Two [`Layer`]s, where `outer` wraps the handler wrapped by `inner`.
+#[derive(Debug, Clone, Copy)]
+pub struct Stack<Inner, Outer> {
+  inner: Inner,
+  outer: Outer,
+}
+
+impl<H, Inner: Layer<H>, Outer: Layer<Inner::Handler>> Layer<H> for Stack<Inner, Outer> {
+  type Handler = Outer::Handler;
+
+  fn layer(&self, inner: H) -> Self::Handler {
+    self.outer.layer(self.inner.layer(inner))
+  }
+}
+
+/// A builder for a stack of [`Layer`]s.
+///
+/// The first layer added is the outermost one, so it sees the payloads first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Layers<L>(L);
+
+impl Layers<Identity> {
+  /// Starts an empty stack of layers.
+  #[must_use]
+  pub const fn new() -> Self {
+    Self(Identity)
+  }
+}
+
+impl<L> Layers<L> {
+  /// Adds a layer to the stack, inside the ones already added.
+  pub fn layer<N>(self, layer: N) -> Layers<Stack<N, L>> {
+    Layers(Stack {
+      inner: layer,
+      outer: self.0,
+    })
+  }
+
+  /// Wraps the `handler` with all the layers of the stack.
+  pub fn handler<H>(self, handler: H) -> L::Handler
+  where
+    L: Layer<H>,
+  {
+    self.0.layer(handler)
+  }
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::StreamExt;
+
+use super::*;
+use crate::atrium_xrpc_wss::subscriptions::{
+  frames::Frame, repositories::Repositories, ProcessedPayload, SubscriptionError,
+};
+
+/// A handler that ignores `#skip` payloads and takes a second to handle `#slow` ones.
+struct SlowHandler;
+impl ConnectionHandler for SlowHandler {
+  type HandledData = String;
+  type HandlingError = std::convert::Infallible;
+
+  async fn handle_payload(
+    &self,
+    t: &str,
+    _payload: Bytes,
+  ) -> Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError> {
+    match t {
+      "#skip" => Ok(None),
+      "#slow" => {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Ok(Some(ProcessedPayload {
+          seq: None,
+          data: t.to_owned(),
+        }))
+      }
+      _ => Ok(Some(ProcessedPayload {
+        seq: None,
+        data: t.to_owned(),
+      })),
+    }
+  }
+}
+
+#[tokio::test(start_paused = true)]
+async fn count_and_time_out_payloads() {
+  let counters = Counters::default();
+  let handler = Layers::new()
+    .layer(TraceLayer)
+    .layer(CountLayer::new(counters.clone()))
+    .layer(TimeoutLayer::new(Duration::from_millis(100)))
+    .handler(SlowHandler);
+
+  for t in ["#commit", "#commit", "#skip"] {
+    handler
+      .handle_payload(t, Bytes::new())
+      .await
+      .expect("failed to handle");
+  }
+  let result = handler.handle_payload("#slow", Bytes::new()).await;
+  assert!(matches!(result, Err(TimeoutError::Elapsed(t)) if t == "#slow"));
+
+  let snapshot = counters.snapshot();
+  assert_eq!(
+    snapshot["#commit"],
+    Counts {
+      processed: 2,
+      ..Counts::default()
+    }
+  );
+  assert_eq!(
+    snapshot["#skip"],
+    Counts {
+      ignored: 1,
+      ..Counts::default()
+    }
+  );
+  assert_eq!(
+    snapshot["#slow"],
+    Counts {
+      failed: 1,
+      ..Counts::default()
+    }
+  );
+}
+
+#[tokio::test]
+async fn bound_counted_types() {
+  let counters = Counters::default();
+  let handler = Layers::new()
+    .layer(CountLayer::new(counters.clone()))
+    .handler(SlowHandler);
+
+  for i in 0..MAX_TYPES * 2 {
+    handler
+      .handle_payload(&format!("#type{i}"), Bytes::new())
+      .await
+      .expect("failed to handle");
+  }
+  handler
+    .handle_payload("#type0", Bytes::new())
+    .await
+    .expect("failed to handle");
+
+  let snapshot = counters.snapshot();
+  assert_eq!(snapshot.len(), MAX_TYPES + 1);
+  assert_eq!(snapshot["#type0"].processed, 2);
+  assert_eq!(snapshot[OTHER].processed, MAX_TYPES as u64);
+}
+
+#[tokio::test(start_paused = true)]
+async fn subscribe_with_layers() {
+  let frames = ["#commit", "#slow", "#commit"].map(|t| {
+    Ok::<_, std::convert::Infallible>(
+      Frame::message(t, &())
+        .and_then(|f| f.encode())
+        .expect("failed to serialize"),
+    )
+  });
+
+  let results = Repositories::layered()
+    .connection(futures::stream::iter(frames))
+    .layers(Layers::new().layer(TimeoutLayer::new(Duration::from_millis(100))))
+    .handler(SlowHandler)
+    .build()
+    .collect::<Vec<_>>()
+    .await;
+
+  assert_eq!(results.len(), 2);
+  assert!(matches!(&results[0], Ok(ProcessedPayload { data, .. }) if data == "#commit"));
+  assert!(matches!(&results[1], Err(SubscriptionError::Handler(_))));
+}
@@ -0,0 +1,62 @@
+//! This file provides the [`TimeoutLayer`], which limits how long a payload can take to be handled.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use super::Layer;
+use crate::atrium_xrpc_wss::subscriptions::{ConnectionHandler, ProcessedPayload};
+
+/// The error returned by the handler wrapped by the [`TimeoutLayer`].
+#[derive(Debug, thiserror::Error)]
+pub enum TimeoutError<E> {
+  #[error("Handling a payload of type {0} timed out")]
+  Elapsed(String),
+  #[error(transparent)]
+  Inner(E),
+}
+
+/// A [`Layer`] that fails the handling of a payload if it takes longer than the given duration.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutLayer(Duration);
+
+impl TimeoutLayer {
+  #[must_use]
+  pub const fn new(timeout: Duration) -> Self {
+    Self(timeout)
+  }
+}
+
+impl<H: ConnectionHandler + Sync> Layer<H> for TimeoutLayer {
+  type Handler = Timeout<H>;
+
+  fn layer(&self, inner: H) -> Self::Handler {
+    Timeout {
+      inner,
+      timeout: self.0,
+    }
+  }
+}
+
+/// The handler wrapped by the [`TimeoutLayer`].
+#[derive(Debug, Clone)]
+pub struct Timeout<H> {
+  inner: H,
+  timeout: Duration,
+}
+
+impl<H: ConnectionHandler + Sync> ConnectionHandler for Timeout<H> {
+  type HandledData = H::HandledData;
+  type HandlingError = TimeoutError<H::HandlingError>;
+
+  async fn handle_payload(
+    &self,
+    t: &str,
+    payload: Bytes,
+  ) -> Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError> {
+    tokio::time::timeout(self.timeout, self.inner.handle_payload(t, payload))
+      .await
+      .map_err(|_| TimeoutError::Elapsed(t.to_owned()))?
+      .map_err(TimeoutError::Inner)
+  }
+}
@@ -0,0 +1,48 @@
+//! This file provides the [`TraceLayer`], which instruments the handling of each payload with a span.
+
+use bytes::Bytes;
+use tracing::Instrument;
+
+use super::Layer;
+use crate::atrium_xrpc_wss::subscriptions::{ConnectionHandler, ProcessedPayload};
+
+/// A [`Layer`] that wraps the handling of each payload in a `handle_payload` span,
+/// recording its type and size, and logs handling errors.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceLayer;
+
+impl<H: ConnectionHandler + Sync> Layer<H> for TraceLayer {
+  type Handler = Trace<H>;
+
+  fn layer(&self, inner: H) -> Self::Handler {
+    Trace(inner)
+  }
+}
+
+/// The handler wrapped by the [`TraceLayer`].
+#[derive(Debug, Clone)]
+pub struct Trace<H>(H);
+
+impl<H: ConnectionHandler + Sync> ConnectionHandler for Trace<H> {
+  type HandledData = H::HandledData;
+  type HandlingError = H::HandlingError;
+
+  async fn handle_payload(
+    &self,
+    t: &str,
+    payload: Bytes,
+  ) -> Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError> {
+    let span = tracing::debug_span!("handle_payload", t, len = payload.len());
+    async {
+      let res = self.0.handle_payload(t, payload).await;
+      match &res {
+        Ok(Some(processed)) => tracing::trace!(seq = processed.seq, "processed"),
+        Ok(None) => tracing::trace!("ignored"),
+        Err(e) => tracing::warn!(error = %e, "failed"),
+      }
+      res
+    }
+    .instrument(span)
+    .await
+  }
+}
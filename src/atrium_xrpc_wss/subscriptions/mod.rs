@@ -1,8 +1,12 @@
 pub mod frames;
+pub mod layers;
+pub mod read_ahead;
 pub mod repositories;
+pub mod shards;
 
-use std::{fmt::Debug, future::Future};
+use std::{error::Error as StdError, future::Future};
 
+use bytes::Bytes;
 use futures::Stream;
 
 /// A trait that defines the connection handler.
@@ -10,11 +14,13 @@ pub trait ConnectionHandler {
   /// The [`Self::HandledData`](ConnectionHandler::HandledData) type should be used to define the returned processed data type.
   type HandledData;
   /// The [`Self::HandlingError`](ConnectionHandler::HandlingError) type should be used to define the processing error type.
-  type HandlingError: 'static + Send + Sync + Debug;
+  type HandlingError: 'static + Send + Sync + StdError;
 
   /// Handles binary data coming from the connection. This function will deserialize the payload body and call the appropriate
   /// handler for each payload type.
   ///
+  /// The `payload` shares the allocation of the received frame, so it can be sliced without copying.
+  ///
   /// # Returns
   /// [`Result<Option<T>>`] like:
   /// - `Ok(Some(processedPayload))` where `processedPayload` is [`ProcessedPayload<ConnectionHandler::HandledData>`](ProcessedPayload)
@@ -23,16 +29,18 @@ pub trait ConnectionHandler {
   /// - `Err(e)` where `e` is [`ConnectionHandler::HandlingError`] if an error occurred while processing the payload.
   fn handle_payload(
     &self,
-    t: String,
-    payload: Vec<u8>,
-  ) -> impl Future<Output = Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError>>;
+    t: &str,
+    payload: Bytes,
+  ) -> impl Future<Output = Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError>>
+       + Send;
 }
 
 /// A trait that defines a subscription.
+///
 /// It should be implemented by any struct that wants to handle a connection.
 /// The `ConnectionPayload` type parameter is the type of the payload that will be received through the connection stream.
 /// The `Error` type parameter is the type of the error that the specific subscription can return, following the lexicon.
-pub trait Subscription<ConnectionPayload, Error: 'static + Send + Sync + Debug> {
+pub trait Subscription<ConnectionPayload, Error: 'static + Send + Sync + StdError> {
   /// The `handle_connection` method should be implemented to handle the connection.
   ///
   /// # Returns
@@ -45,6 +53,7 @@ pub trait Subscription<ConnectionPayload, Error: 'static + Send + Sync + Debug>
 
 /// This struct represents a processed payload.
 /// It contains the sequence number (cursor) and the final processed data.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProcessedPayload<Kind> {
   pub seq: Option<i64>, // Option to allow for the absence of a sequence number, like in the case of #info.
   pub data: Kind,
@@ -60,22 +69,70 @@ impl<Kind> ProcessedPayload<Kind> {
   }
 }
 
+/// A type-erased error, used to carry the source of errors whose concrete type is
+/// not known by the subscription (e.g. the transport or the handler errors).
+pub type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
 /// An error type that represents a subscription error.
 ///
-/// `Abort` is a hard error, and the subscription should cancel.
-/// This follows the [`ATProto Specs`](https://atproto.com/specs/event-stream).
+/// Every variant except [`Other`](SubscriptionError::Other) is a hard error, after which the
+/// subscription stream ends. This follows the [`ATProto Specs`](https://atproto.com/specs/event-stream).
+///
+/// `Transport` is an error coming from the underlying connection (e.g. `WebSocket` or IO errors).
+///
+/// `Framing` means a frame was malformed, like a header without a payload.
 ///
-/// `Unknown` is an error that is not recognized by the subscription.
-/// This can be used to handle unexpected errors.
+/// `Decoding` means a frame had invalid DAG-CBOR encoding.
+///
+/// `Handler` is an error returned by the [`ConnectionHandler`] while processing a payload.
+///
+/// `Server` is an error frame sent by the server that is not recognized by the subscription.
 ///
 /// `Other` is an error specific to the subscription type.
 /// This can be used to handle different kinds of errors, following the lexicon.
 #[derive(Debug, thiserror::Error)]
 pub enum SubscriptionError<T> {
-  #[error("Critical Subscription Error: {0}")]
-  Abort(String),
-  #[error("Unknown Subscription Error: {0}")]
-  Unknown(String),
+  #[error("Transport error: {0}")]
+  Transport(#[source] BoxError),
+  #[error("Invalid framing: {0}")]
+  Framing(#[source] frames::Error),
+  #[error("Invalid DAG-CBOR encoding: {0}")]
+  Decoding(#[source] serde_ipld_dagcbor::DecodeError<std::io::Error>),
+  #[error("Handler error: {0}")]
+  Handler(#[source] BoxError),
+  #[error("Server error: {error}. Message: {message:?}")]
+  Server {
+    error: String,
+    message: Option<String>,
+  },
   #[error(transparent)]
   Other(T),
 }
+
+impl<T> From<frames::Error> for SubscriptionError<T> {
+  fn from(e: frames::Error) -> Self {
+    match e {
+      frames::Error::IpldDecoding(e) => Self::Decoding(e),
+      e => Self::Framing(e),
+    }
+  }
+}
+
+/// A trait for errors that can tell whether the subscription is worth reconnecting after them.
+pub trait Retryable {
+  /// Returns `true` if reconnecting (possibly after a delay) may succeed.
+  fn is_retryable(&self) -> bool;
+}
+
+impl<T: Retryable> Retryable for SubscriptionError<T> {
+  /// Transport errors are retryable, since the connection might have just been dropped.
+  /// Framing, decoding and handler errors are not, as the same frame would be received again.
+  /// Unknown server errors are not retryable either, since their meaning is unknown.
+  fn is_retryable(&self) -> bool {
+    match self {
+      Self::Transport(_) => true,
+      Self::Framing(_) | Self::Decoding(_) | Self::Handler(_) | Self::Server { .. } => false,
+      Self::Other(e) => e.is_retryable(),
+    }
+  }
+}
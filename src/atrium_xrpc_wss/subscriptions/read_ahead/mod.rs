@@ -0,0 +1,226 @@
+//! This file defines the [`ReadAhead`] buffer, which reads the frames of a connection on a spawned task,
+//! so that short hiccups in the handling of the payloads don't stall the connection.
+//!
+//! Servers drop consumers that don't keep up (with `ConsumerTooSlow` in `com.atproto.sync.subscribeRepos`),
+//! so the buffer keeps reading the connection while the subscription is busy handling a payload.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+  pin::Pin,
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+  },
+  task::{Context, Poll},
+};
+
+use bon::bon;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::{
+  sync::mpsc::{self, error::TrySendError},
+  task::JoinHandle,
+};
+
+use super::frames::Frame;
+
+/// An error yielded by the [`ReadAhead`] buffer.
+#[derive(Debug, thiserror::Error)]
+pub enum ReadAheadError<E> {
+  /// An error from the underlying connection.
+  #[error(transparent)]
+  Transport(E),
+  /// The buffer was full with the [`OverflowPolicy::Abort`] policy.
+  #[error("The read-ahead buffer of {0} frames overflowed")]
+  Overflow(usize),
+}
+
+/// What the [`ReadAhead`] buffer does with a frame when it is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+  /// Waits for room in the buffer, which stops reading the connection.
+  #[default]
+  Block,
+  /// Drops the frame if it's a message other than a `#commit`, otherwise waits for room in the buffer.
+  ///
+  /// The dropped events are lost, so this should only be used when they are not needed.
+  DropNonCommit,
+  /// Ends the stream with an [`ReadAheadError::Overflow`] error, after the frames already buffered.
+  Abort,
+}
+
+/// The occupancy of the [`ReadAhead`] buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferStats {
+  /// The number of frames the buffer can hold.
+  pub capacity: usize,
+  /// The number of frames currently in the buffer.
+  pub occupancy: usize,
+  /// The highest occupancy reached.
+  pub high_water_mark: usize,
+  /// The number of times a frame was read while the buffer was full.
+  pub overflows: u64,
+  /// The number of frames dropped with the [`OverflowPolicy::DropNonCommit`] policy.
+  pub dropped: u64,
+}
+
+#[derive(Debug)]
+struct Counters {
+  capacity: usize,
+  occupancy: AtomicUsize,
+  high_water_mark: AtomicUsize,
+  overflows: AtomicU64,
+  dropped: AtomicU64,
+}
+
+/// A handle on the occupancy of a [`ReadAhead`] buffer.
+#[derive(Debug, Clone)]
+pub struct BufferMetrics(Arc<Counters>);
+
+impl BufferMetrics {
+  /// Returns the current occupancy of the buffer.
+  #[must_use]
+  pub fn stats(&self) -> BufferStats {
+    BufferStats {
+      capacity: self.0.capacity,
+      occupancy: self.0.occupancy.load(Ordering::Relaxed),
+      high_water_mark: self.0.high_water_mark.load(Ordering::Relaxed),
+      overflows: self.0.overflows.load(Ordering::Relaxed),
+      dropped: self.0.dropped.load(Ordering::Relaxed),
+    }
+  }
+
+  fn pushed(&self) {
+    let occupancy = self.0.occupancy.fetch_add(1, Ordering::Relaxed) + 1;
+    self
+      .0
+      .high_water_mark
+      .fetch_max(occupancy, Ordering::Relaxed);
+  }
+}
+
+/// A stream of frames read ahead from a connection by a spawned task, into a bounded buffer.
+///
+/// It yields the same frames as the connection, so it can be used as the connection of the
+/// subscription. The reading task is stopped when this is dropped.
+#[derive(Debug)]
+pub struct ReadAhead<E> {
+  receiver: mpsc::Receiver<Result<Bytes, ReadAheadError<E>>>,
+  metrics: BufferMetrics,
+  reader: JoinHandle<()>,
+}
+
+#[bon]
+impl<E: 'static + Send> ReadAhead<E> {
+  /// Spawns a task reading the frames of the `connection` into a buffer of `capacity` frames
+  /// (defaults to 8192), applying the `policy` when it's full.
+  ///
+  /// # Panics
+  /// Panics if `capacity` is zero.
+  #[builder]
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The frames and queue permits of the reading loop can be dropped in any order."
+  )]
+  pub fn new(
+    connection: impl 'static + Send + Stream<Item = Result<Bytes, E>>,
+    #[builder(default = 8192)] capacity: usize,
+    #[builder(default)] policy: OverflowPolicy,
+  ) -> Self {
+    let (sender, receiver) = mpsc::channel(capacity);
+    let metrics = BufferMetrics(Arc::new(Counters {
+      capacity,
+      occupancy: AtomicUsize::new(0),
+      high_water_mark: AtomicUsize::new(0),
+      overflows: AtomicU64::new(0),
+      dropped: AtomicU64::new(0),
+    }));
+
+    let reader_metrics = metrics.clone();
+    let reader = tokio::spawn(async move {
+      let mut connection = Box::pin(connection);
+      while let Some(message) = connection.next().await {
+        let failed = message.is_err();
+        let permit = match sender.try_reserve() {
+          Ok(permit) => permit,
+          Err(TrySendError::Closed(())) => break,
+          Err(TrySendError::Full(())) => {
+            reader_metrics.0.overflows.fetch_add(1, Ordering::Relaxed);
+            match policy {
+              OverflowPolicy::DropNonCommit if is_droppable(&message) => {
+                reader_metrics.0.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+              }
+              OverflowPolicy::Abort => {
+                if let Ok(permit) = sender.reserve().await {
+                  reader_metrics.pushed();
+                  permit.send(Err(ReadAheadError::Overflow(capacity)));
+                }
+                break;
+              }
+              OverflowPolicy::Block | OverflowPolicy::DropNonCommit => {
+                let Ok(permit) = sender.reserve().await else {
+                  break;
+                };
+                permit
+              }
+            }
+          }
+        };
+        reader_metrics.pushed();
+        permit.send(message.map_err(ReadAheadError::Transport));
+        if failed {
+          // The subscription ends on transport errors, so there is nothing left to read.
+          break;
+        }
+      }
+    });
+
+    Self {
+      receiver,
+      metrics,
+      reader,
+    }
+  }
+}
+
+impl<E> ReadAhead<E> {
+  /// Returns a handle on the occupancy of the buffer, which can be kept after the stream is consumed.
+  #[must_use]
+  pub fn metrics(&self) -> BufferMetrics {
+    self.metrics.clone()
+  }
+}
+
+impl<E> Stream for ReadAhead<E> {
+  type Item = Result<Bytes, ReadAheadError<E>>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let poll = self.receiver.poll_recv(cx);
+    if let Poll::Ready(Some(_)) = poll {
+      self.metrics.0.occupancy.fetch_sub(1, Ordering::Relaxed);
+    }
+    poll
+  }
+}
+
+impl<E> Drop for ReadAhead<E> {
+  fn drop(&mut self) {
+    self.reader.abort();
+  }
+}
+
+/// Returns `true` if the frame can be dropped with the [`OverflowPolicy::DropNonCommit`] policy.
+///
+/// Only the header is decoded, and anything that isn't a valid message frame is kept,
+/// so that errors still reach the subscription.
+fn is_droppable<E>(message: &Result<Bytes, E>) -> bool {
+  message.as_ref().is_ok_and(|data| {
+    matches!(
+      Frame::try_from(data.clone()),
+      Ok(Frame::Message { t, .. }) if t != "#commit"
+    )
+  })
+}
@@ -0,0 +1,83 @@
+use std::{convert::Infallible, time::Duration};
+
+use super::*;
+
+/// Fills a buffer of 2 frames with the given message types, before reading it.
+async fn read(
+  types: &[&str],
+  policy: OverflowPolicy,
+) -> (Vec<Result<String, ReadAheadError<Infallible>>>, BufferStats) {
+  let frames = types
+    .iter()
+    .map(|&t| {
+      Ok::<_, Infallible>(
+        Frame::message(t, &())
+          .and_then(|f| f.encode())
+          .expect("failed to serialize"),
+      )
+    })
+    .collect::<Vec<_>>();
+  let read_ahead = ReadAhead::builder()
+    .connection(futures::stream::iter(frames))
+    .capacity(2)
+    .policy(policy)
+    .build();
+  let metrics = read_ahead.metrics();
+
+  // Lets the reader fill the buffer.
+  tokio::time::sleep(Duration::from_secs(1)).await;
+  let results = read_ahead
+    .map(|res| {
+      res.map(|data| match Frame::try_from(data) {
+        Ok(Frame::Message { t, .. }) => t,
+        _ => panic!("not a message frame"),
+      })
+    })
+    .collect()
+    .await;
+  (results, metrics.stats())
+}
+
+#[tokio::test(start_paused = true)]
+async fn block_when_full() {
+  let (results, stats) = read(&["#commit", "#identity", "#commit"], OverflowPolicy::Block).await;
+  let types = results.into_iter().collect::<Result<Vec<_>, _>>();
+  assert_eq!(
+    types.expect("failed to read"),
+    ["#commit", "#identity", "#commit"]
+  );
+  assert_eq!(
+    stats,
+    BufferStats {
+      capacity: 2,
+      occupancy: 0,
+      high_water_mark: 2,
+      overflows: 1,
+      dropped: 0,
+    }
+  );
+}
+
+#[tokio::test(start_paused = true)]
+async fn drop_non_commit_when_full() {
+  let (results, stats) = read(
+    &["#commit", "#identity", "#account", "#commit"],
+    OverflowPolicy::DropNonCommit,
+  )
+  .await;
+  let types = results.into_iter().collect::<Result<Vec<_>, _>>();
+  assert_eq!(
+    types.expect("failed to read"),
+    ["#commit", "#identity", "#commit"]
+  );
+  assert_eq!(stats.dropped, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn abort_when_full() {
+  let (results, stats) = read(&["#commit", "#commit", "#commit"], OverflowPolicy::Abort).await;
+  assert_eq!(results.len(), 3);
+  assert!(matches!(&results[..2], [Ok(_), Ok(_)]));
+  assert!(matches!(results[2], Err(ReadAheadError::Overflow(2))));
+  assert_eq!(stats.overflows, 1);
+}
@@ -0,0 +1,133 @@
+//! This file defines the [`Commit`] type, a zero-copy version of [`subscribe_repos::Commit`].
+
+use std::convert::Infallible;
+
+use atrium_api::{
+  com::atproto::sync::subscribe_repos::{self, RepoOp},
+  types::{
+    string::{Datetime, Did},
+    CidLink,
+  },
+};
+use bytes::Bytes;
+use serde::Deserialize;
+
+/// A payload of type `#commit`.
+///
+/// This mirrors [`subscribe_repos::CommitData`], except that the CAR `blocks` are sliced
+/// out of the frame they were received in, instead of being copied into a new buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+  pub blobs: Vec<CidLink>,
+  /// CAR file containing relevant blocks, as a diff since the previous repo state.
+  pub blocks: Bytes,
+  /// Repo commit object CID.
+  pub commit: CidLink,
+  pub ops: Vec<RepoOp>,
+  /// DEPRECATED -- unused.
+  pub prev: Option<CidLink>,
+  /// DEPRECATED -- unused.
+  pub rebase: bool,
+  /// The repo this event comes from.
+  pub repo: Did,
+  /// The rev of the emitted commit.
+  pub rev: String,
+  /// The stream sequence number of this message.
+  pub seq: i64,
+  /// The rev of the last emitted commit from this repo (if any).
+  pub since: Option<String>,
+  /// Timestamp of when this message was originally broadcast.
+  pub time: Datetime,
+  /// Indicates that this commit contained too many ops, or data size was too large.
+  pub too_big: bool,
+}
+
+/// The same as [`Commit`], but borrowing the `blocks` from the payload being deserialized.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BorrowedCommit<'a> {
+  blobs: Vec<CidLink>,
+  blocks: &'a [u8],
+  commit: CidLink,
+  ops: Vec<RepoOp>,
+  prev: Option<CidLink>,
+  rebase: bool,
+  repo: Did,
+  rev: String,
+  seq: i64,
+  since: Option<String>,
+  time: Datetime,
+  too_big: bool,
+}
+
+impl Commit {
+  /// Decodes a `#commit` payload, sharing the allocation of `payload` for the `blocks`.
+  ///
+  /// # Errors
+  /// Returns an error if the payload is not a valid DAG-CBOR encoded commit.
+  pub fn decode(payload: &Bytes) -> Result<Self, serde_ipld_dagcbor::DecodeError<Infallible>> {
+    let BorrowedCommit {
+      blobs,
+      blocks,
+      commit,
+      ops,
+      prev,
+      rebase,
+      repo,
+      rev,
+      seq,
+      since,
+      time,
+      too_big,
+    } = serde_ipld_dagcbor::from_slice(payload)?;
+
+    Ok(Self {
+      blobs,
+      blocks: payload.slice_ref(blocks),
+      commit,
+      ops,
+      prev,
+      rebase,
+      repo,
+      rev,
+      seq,
+      since,
+      time,
+      too_big,
+    })
+  }
+}
+
+impl From<Commit> for subscribe_repos::Commit {
+  fn from(commit: Commit) -> Self {
+    let Commit {
+      blobs,
+      blocks,
+      commit,
+      ops,
+      prev,
+      rebase,
+      repo,
+      rev,
+      seq,
+      since,
+      time,
+      too_big,
+    } = commit;
+    subscribe_repos::CommitData {
+      blobs,
+      blocks: blocks.into(),
+      commit,
+      ops,
+      prev,
+      rebase,
+      repo,
+      rev,
+      seq,
+      since,
+      time,
+      too_big,
+    }
+    .into()
+  }
+}
@@ -0,0 +1,250 @@
+//! This file defines the concurrent implementation of the repositories subscription,
+//! which handles payloads on a bounded pool of tasks.
+
+use std::{
+  collections::HashMap,
+  error::Error as StdError,
+  future::Future,
+  pin::Pin,
+  sync::Arc,
+  task::{Context, Poll},
+};
+
+use async_stream::stream;
+use bon::bon;
+use bytes::Bytes;
+use futures::{
+  stream::{FuturesOrdered, FuturesUnordered},
+  Stream, StreamExt,
+};
+use serde::Deserialize;
+use tokio::{
+  sync::{
+    oneshot::{self, error::TryRecvError},
+    Semaphore,
+  },
+  task::{JoinError, JoinHandle},
+};
+
+use super::{read_frame, Error, Repositories};
+use crate::atrium_xrpc_wss::subscriptions::{
+  ConnectionHandler, ProcessedPayload, SubscriptionError,
+};
+
+/// The order in which the concurrently handled payloads are yielded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeliveryOrder {
+  /// Payloads are yielded in the order they were received, which is the `seq` order.
+  #[default]
+  Seq,
+  /// Payloads of the same repository are yielded in the order they were received,
+  /// but may be overtaken by payloads of other repositories that were handled faster.
+  /// A payload is only handled once the previous one of its repository was yielded.
+  PerRepo,
+}
+
+/// The fields identifying the repository of a payload, which is `repo` in `#commit`
+/// payloads, and `did` in the others. Every other field is skipped.
+#[derive(Deserialize)]
+struct RepoKey {
+  repo: Option<String>,
+  did: Option<String>,
+}
+
+impl RepoKey {
+  fn read(payload: &[u8]) -> Option<String> {
+    let key = serde_ipld_dagcbor::from_slice::<Self>(payload).ok()?;
+    key.repo.or(key.did)
+  }
+}
+
+/// A spawned task, which is aborted if it's dropped before completing.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Future for AbortOnDrop<T> {
+  type Output = Result<T, JoinError>;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    Pin::new(&mut self.0).poll(cx)
+  }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+  fn drop(&mut self) {
+    self.0.abort();
+  }
+}
+
+/// The payloads being handled, in the [`DeliveryOrder`] they should be yielded.
+enum InFlight<F: Future> {
+  Ordered(FuturesOrdered<F>),
+  Unordered(FuturesUnordered<F>),
+}
+
+impl<F: Future> InFlight<F> {
+  fn new(order: DeliveryOrder) -> Self {
+    match order {
+      DeliveryOrder::Seq => Self::Ordered(FuturesOrdered::new()),
+      DeliveryOrder::PerRepo => Self::Unordered(FuturesUnordered::new()),
+    }
+  }
+
+  fn push(&mut self, future: F) {
+    match self {
+      Self::Ordered(futures) => futures.push_back(future),
+      Self::Unordered(futures) => futures.push(future),
+    }
+  }
+
+  fn len(&self) -> usize {
+    match self {
+      Self::Ordered(futures) => futures.len(),
+      Self::Unordered(futures) => futures.len(),
+    }
+  }
+
+  async fn next(&mut self) -> Option<F::Output> {
+    match self {
+      Self::Ordered(futures) => futures.next().await,
+      Self::Unordered(futures) => futures.next().await,
+    }
+  }
+}
+
+/// What happened while waiting on the connection and the payloads being handled.
+enum Event<R, H> {
+  Received(Option<R>),
+  Handled(Option<H>),
+}
+
+#[bon]
+impl<E> Repositories<Result<Bytes, E>>
+where
+  E: 'static + Send + Sync + StdError,
+{
+  /// Builds a subscription that handles payloads concurrently, on spawned tasks.
+  ///
+  /// - `workers` is the number of payloads handled at the same time, which defaults to the
+  ///   available parallelism.
+  /// - `queue_depth` is the number of payloads read ahead from the connection, including the ones
+  ///   being handled and the ones waiting to be yielded. It defaults to 4 times the `workers`.
+  /// - `order` is the [`DeliveryOrder`] of the results, which defaults to the `seq` order.
+  ///
+  /// Error frames and transport errors are yielded after the payloads received before them.
+  /// After a handler error, or when the stream is dropped, the payloads still being handled are aborted.
+  /// Other connections, like the `tungstenite` one, must be adapted into a stream of binary frames first.
+  #[builder(finish_fn = build)]
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The permits and frames dropped at the end of the blocks don't depend on each other, and `done` is dropped explicitly."
+  )]
+  pub fn concurrent<H>(
+    mut connection: impl Stream<Item = Result<Bytes, E>> + Unpin,
+    handler: H,
+    workers: Option<usize>,
+    queue_depth: Option<usize>,
+    #[builder(default)] order: DeliveryOrder,
+  ) -> impl Stream<Item = Result<ProcessedPayload<H::HandledData>, SubscriptionError<Error>>>
+  where
+    H: 'static + ConnectionHandler + Send + Sync,
+    H::HandledData: 'static + Send,
+  {
+    let workers = workers
+      .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from))
+      .max(1);
+    let queue_depth = queue_depth.unwrap_or(workers * 4).max(workers);
+    let handler = Arc::new(handler);
+    let semaphore = Arc::new(Semaphore::new(workers));
+
+    let stream = stream! {
+      let mut in_flight = InFlight::new(order);
+      // The delivery of the last payload of each repository, for the `PerRepo` order.
+      let mut last_by_repo = HashMap::<String, oneshot::Receiver<()>>::new();
+      let mut reading = true;
+      let mut error = None;
+      loop {
+        let event = if reading && in_flight.len() < queue_depth {
+          tokio::select! {
+            biased;
+            result = in_flight.next(), if in_flight.len() > 0 => Event::Handled(result),
+            message = connection.next() => Event::Received(message),
+          }
+        } else {
+          Event::Handled(in_flight.next().await)
+        };
+
+        match event {
+          Event::Received(None) => reading = false, // Server dropped connection
+          Event::Received(Some(Err(e))) => { // Transport error
+            error = Some(SubscriptionError::Transport(Box::new(e)));
+            reading = false;
+          }
+          Event::Received(Some(Ok(data))) => {
+            let (t, payload) = match read_frame(data) {
+              Ok(Some(message)) => message,
+              Ok(None) => continue,
+              Err(e) => {
+                error = Some(e);
+                reading = false;
+                continue;
+              }
+            };
+
+            // In the `PerRepo` order, the payload waits for the previous one of its repository
+            // to be yielded, so that it can't overtake it.
+            let (done, previous) = match (order, RepoKey::read(&payload)) {
+              (DeliveryOrder::PerRepo, Some(repo)) => {
+                let (done, completed) = oneshot::channel::<()>();
+                let previous = last_by_repo.insert(repo, completed);
+                if last_by_repo.len() > queue_depth * 2 {
+                  last_by_repo.retain(|_, completed| matches!(completed.try_recv(), Err(TryRecvError::Empty)));
+                }
+                (Some(done), previous)
+              }
+              _ => (None, None),
+            };
+
+            let handler = Arc::clone(&handler);
+            let semaphore = Arc::clone(&semaphore);
+            let task = AbortOnDrop(tokio::spawn(async move {
+              if let Some(previous) = previous {
+                previous.await.ok();
+              }
+              let _permit = semaphore.acquire_owned().await.ok();
+              handler.handle_payload(&t, payload).await
+            }));
+            in_flight.push(async move { (task.await, done) });
+          }
+          Event::Handled(None) => break, // Nothing left to handle.
+          Event::Handled(Some((res, done))) => {
+            match res {
+              Ok(Ok(Some(res))) => yield Ok(res), // Payload was successfully handled.
+              Ok(Ok(None)) => {}, // Payload was ignored by Handler.
+              Ok(Err(e)) => {
+                // "Invalid framing or invalid DAG-CBOR encoding are hard errors,
+                //  and the client should drop the entire connection instead of skipping the frame."
+                // https://atproto.com/specs/event-stream
+                error = Some(SubscriptionError::Handler(Box::new(e)));
+                break;
+              }
+              Err(e) => { // The task panicked.
+                error = Some(SubscriptionError::Handler(Box::new(e)));
+                break;
+              }
+            }
+            // Releases the next payload of the repository, now that this one was yielded.
+            drop(done);
+          }
+        }
+      }
+
+      // The payloads still being handled after an error are aborted before it's yielded.
+      drop(in_flight);
+      if let Some(e) = error {
+        yield Err(e);
+      }
+    };
+
+    Box::pin(stream)
+  }
+}
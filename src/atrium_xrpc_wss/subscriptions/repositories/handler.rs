@@ -1,10 +1,11 @@
-use std::future::Future;
+use std::{convert::Infallible, error::Error as StdError, future::Future};
 
 use atrium_api::com::atproto::sync::subscribe_repos;
+use bytes::Bytes;
 
 use crate::atrium_xrpc_wss::subscriptions::ProcessedPayload;
 
-use super::ConnectionHandler;
+use super::{Commit, ConnectionHandler};
 
 /// This type should be used to define [`ConnectionHandler::HandledData`](ConnectionHandler::HandledData)
 /// for the [`Repositories`](super::Repositories) subscription type.
@@ -19,7 +20,14 @@ pub type HandledData<H> = ProcessedData<
 >;
 
 /// Wrapper around all the possible types of processed data.
+///
+/// With the `serde` feature, it's serialized as `{"kind": "commit", "data": ...}`.
 #[derive(Debug)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(tag = "kind", content = "data", rename_all = "camelCase")
+)]
 pub enum ProcessedData<C, I0, A, H, M, T, I1> {
   Commit(C),
   Identity(I0),
@@ -38,15 +46,27 @@ pub enum ProcessedData<C, I0, A, H, M, T, I1> {
 /// trait is generic, and the implementor must define the data type for each
 /// payload they pretend to use. The same goes for the implementations of
 /// each processing method, as the algorithm may vary.
-pub trait Handler: ConnectionHandler {
+///
+/// Every implementor is also a [`ConnectionHandler`], which decodes each payload and
+/// dispatches it to the matching processing method, ignoring unknown payload types.
+/// The processing methods that are not overridden ignore their payloads.
+pub trait Handler: Send + Sync {
+  /// The error type returned by the processing methods.
+  /// It must be convertible from the errors found while decoding the payloads.
+  type HandlingError: 'static
+    + Send
+    + Sync
+    + StdError
+    + From<serde_ipld_dagcbor::DecodeError<Infallible>>;
+
   type ProcessedCommitData;
   /// Processes a payload of type `#commit`.
   fn process_commit(
     &self,
-    payload: subscribe_repos::Commit,
+    _payload: Commit,
   ) -> impl Future<
     Output = Result<Option<ProcessedPayload<Self::ProcessedCommitData>>, Self::HandlingError>,
-  > {
+  > + Send {
     // Default implementation always returns `None`, meaning the implementation decided to ignore the payload.
     async { Ok(None) }
   }
@@ -55,10 +75,10 @@ pub trait Handler: ConnectionHandler {
   /// Processes a payload of type `#identity`.
   fn process_identity(
     &self,
-    payload: subscribe_repos::Identity,
+    _payload: subscribe_repos::Identity,
   ) -> impl Future<
     Output = Result<Option<ProcessedPayload<Self::ProcessedIdentityData>>, Self::HandlingError>,
-  > {
+  > + Send {
     // Default implementation always returns `None`, meaning the implementation decided to ignore the payload.
     async { Ok(None) }
   }
@@ -67,10 +87,10 @@ pub trait Handler: ConnectionHandler {
   /// Processes a payload of type `#account`.
   fn process_account(
     &self,
-    payload: subscribe_repos::Account,
+    _payload: subscribe_repos::Account,
   ) -> impl Future<
     Output = Result<Option<ProcessedPayload<Self::ProcessedAccountData>>, Self::HandlingError>,
-  > {
+  > + Send {
     // Default implementation always returns `None`, meaning the implementation decided to ignore the payload.
     async { Ok(None) }
   }
@@ -79,10 +99,10 @@ pub trait Handler: ConnectionHandler {
   /// Processes a payload of type `#handle`.
   fn process_handle(
     &self,
-    payload: subscribe_repos::Handle,
+    _payload: subscribe_repos::Handle,
   ) -> impl Future<
     Output = Result<Option<ProcessedPayload<Self::ProcessedHandleData>>, Self::HandlingError>,
-  > {
+  > + Send {
     // Default implementation always returns `None`, meaning the implementation decided to ignore the payload.
     async { Ok(None) }
   }
@@ -91,10 +111,10 @@ pub trait Handler: ConnectionHandler {
   /// Processes a payload of type `#migrate`.
   fn process_migrate(
     &self,
-    payload: subscribe_repos::Migrate,
+    _payload: subscribe_repos::Migrate,
   ) -> impl Future<
     Output = Result<Option<ProcessedPayload<Self::ProcessedMigrateData>>, Self::HandlingError>,
-  > {
+  > + Send {
     // Default implementation always returns `None`, meaning the implementation decided to ignore the payload.
     async { Ok(None) }
   }
@@ -103,10 +123,10 @@ pub trait Handler: ConnectionHandler {
   /// Processes a payload of type `#tombstone`.
   fn process_tombstone(
     &self,
-    payload: subscribe_repos::Tombstone,
+    _payload: subscribe_repos::Tombstone,
   ) -> impl Future<
     Output = Result<Option<ProcessedPayload<Self::ProcessedTombstoneData>>, Self::HandlingError>,
-  > {
+  > + Send {
     // Default implementation always returns `None`, meaning the implementation decided to ignore the payload.
     async { Ok(None) }
   }
@@ -115,10 +135,61 @@ pub trait Handler: ConnectionHandler {
   /// Processes a payload of type `#info`.
   fn process_info(
     &self,
-    payload: subscribe_repos::Info,
-  ) -> impl Future<Output = Result<Option<ProcessedPayload<Self::ProcessedInfoData>>, Self::HandlingError>>
-  {
+    _payload: subscribe_repos::Info,
+  ) -> impl Future<
+    Output = Result<Option<ProcessedPayload<Self::ProcessedInfoData>>, Self::HandlingError>,
+  > + Send {
     // Default implementation always returns `None`, meaning the implementation decided to ignore the payload.
     async { Ok(None) }
   }
 }
+
+impl<H: Handler> ConnectionHandler for H {
+  type HandledData = HandledData<Self>;
+  type HandlingError = <Self as Handler>::HandlingError;
+
+  async fn handle_payload(
+    &self,
+    t: &str,
+    payload: Bytes,
+  ) -> Result<Option<ProcessedPayload<Self::HandledData>>, <Self as Handler>::HandlingError> {
+    let res = match t {
+      "#commit" => self
+        .process_commit(Commit::decode(&payload)?)
+        .await?
+        .map(|data| data.map(ProcessedData::Commit)),
+      "#identity" => self
+        .process_identity(serde_ipld_dagcbor::from_slice(&payload)?)
+        .await?
+        .map(|data| data.map(ProcessedData::Identity)),
+      "#account" => self
+        .process_account(serde_ipld_dagcbor::from_slice(&payload)?)
+        .await?
+        .map(|data| data.map(ProcessedData::Account)),
+      "#handle" => self
+        .process_handle(serde_ipld_dagcbor::from_slice(&payload)?)
+        .await?
+        .map(|data| data.map(ProcessedData::Handle)),
+      "#migrate" => self
+        .process_migrate(serde_ipld_dagcbor::from_slice(&payload)?)
+        .await?
+        .map(|data| data.map(ProcessedData::Migrate)),
+      "#tombstone" => self
+        .process_tombstone(serde_ipld_dagcbor::from_slice(&payload)?)
+        .await?
+        .map(|data| data.map(ProcessedData::Tombstone)),
+      "#info" => self
+        .process_info(serde_ipld_dagcbor::from_slice(&payload)?)
+        .await?
+        .map(|data| data.map(ProcessedData::Info)),
+      _ => {
+        // "Clients should ignore frames with headers that have unknown op or t values.
+        //  Unknown fields in both headers and payloads should be ignored."
+        // https://atproto.com/specs/event-stream
+        return Ok(None);
+      }
+    };
+
+    Ok(res)
+  }
+}
@@ -1,10 +1,23 @@
+#[cfg(test)]
+mod tests;
+
+use async_stream::stream;
 use bon::bon;
-use futures::Stream;
-use std::marker::PhantomData;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use std::{error::Error as StdError, marker::PhantomData};
 
-use super::{ConnectionHandler, ProcessedPayload, Subscription};
+use super::{
+  frames::{self, Frame},
+  layers::{Layer, Layers},
+  ConnectionHandler, ProcessedPayload, Retryable, Subscription, SubscriptionError,
+};
 
+mod commit;
+mod concurrent;
 mod handler;
+pub use commit::Commit;
+pub use concurrent::DeliveryOrder;
 pub use handler::{HandledData, Handler, ProcessedData};
 
 /// A struct that represents the repositories subscription, used in `com.atproto.sync.subscribeRepos`.
@@ -14,12 +27,164 @@ pub struct Repositories<ConnectionPayload> {
 }
 
 /// An error type for this crate.
+///
+/// These follow the lexicon for the `com.atproto.sync.subscribeRepos` XRPC,
+/// and carry the optional message sent along with the error frame.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-  #[error("The cursor was in the future.")]
-  FutureCursor,
-  #[error("The consumer is too slow.")]
-  ConsumerTooSlow,
+  #[error("The cursor was in the future. Message: {0:?}")]
+  FutureCursor(Option<String>),
+  #[error("The consumer is too slow. Message: {0:?}")]
+  ConsumerTooSlow(Option<String>),
+}
+
+impl Retryable for Error {
+  /// `ConsumerTooSlow` is retryable, as the server just dropped a consumer that fell behind.
+  /// `FutureCursor` is not, since reconnecting with the same cursor will fail again.
+  fn is_retryable(&self) -> bool {
+    match self {
+      Self::FutureCursor(_) => false,
+      Self::ConsumerTooSlow(_) => true,
+    }
+  }
+}
+
+/// The transport-agnostic implementation of the subscription, over any stream of binary frames.
+///
+/// Other kinds of connections (like `WebSocket` libraries, files or channels) only need to be
+/// adapted into a stream of `Result<Bytes, E>`, where `E` is the transport error. Infallible
+/// sources can use [`std::convert::Infallible`] as the error type.
+impl<E> Subscription<Result<Bytes, E>, Error> for Repositories<Result<Bytes, E>>
+where
+  E: 'static + Send + Sync + StdError,
+{
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The frame is dropped after its handling either way, only the order with the handler's result changes."
+  )]
+  fn handle_connection<H: ConnectionHandler + Sync>(
+    mut connection: impl Stream<Item = Result<Bytes, E>> + Unpin,
+    handler: H,
+  ) -> impl Stream<Item = Result<ProcessedPayload<H::HandledData>, SubscriptionError<Error>>> {
+    // Builds a new async stream that will deserialize the frames sent through the
+    // connection and then yield the results processed by the handler back to the caller.
+    let stream = stream! {
+      loop {
+        let message = connection.next().await;
+        let data = match message {
+          None => break, // Server dropped connection
+          Some(Err(e)) => { // Transport error
+            yield Err(SubscriptionError::Transport(Box::new(e)));
+            break;
+          }
+          Some(Ok(data)) => data,
+        };
+
+        let (t, payload) = match read_frame(data) {
+          Ok(Some(message)) => message,
+          Ok(None) => continue,
+          Err(e) => {
+            yield Err(e);
+            break;
+          }
+        };
+
+        match handler.handle_payload(&t, payload).await {
+          Ok(Some(res)) => yield Ok(res), // Payload was successfully handled.
+          Ok(None) => {}, // Payload was ignored by Handler.
+          Err(e) => {
+            // "Invalid framing or invalid DAG-CBOR encoding are hard errors,
+            //  and the client should drop the entire connection instead of skipping the frame."
+            // https://atproto.com/specs/event-stream
+            yield Err(SubscriptionError::Handler(Box::new(e)));
+            break;
+          },
+        }
+      }
+    };
+
+    Box::pin(stream)
+  }
+}
+
+/// Reads a binary frame, returning the type and payload of message frames.
+///
+/// This is what the subscription does with each frame, so it's useful to consume the frames
+/// without handling them, like when recording them.
+///
+/// # Errors
+/// Returns `Ok(None)` for frames that should be ignored, and an error for frames
+/// after which the connection should be dropped, including error frames.
+pub fn read_frame(data: Bytes) -> Result<Option<(String, Bytes)>, SubscriptionError<Error>> {
+  let frame = match Frame::try_from(data) {
+    Ok(frame) => frame,
+    Err(frames::Error::UnknownFrameType(_)) => {
+      // "Clients should ignore frames with headers that have unknown op or t values.
+      //  Unknown fields in both headers and payloads should be ignored."
+      // https://atproto.com/specs/event-stream
+      return Ok(None);
+    }
+    Err(e) => {
+      // "Invalid framing or invalid DAG-CBOR encoding are hard errors,
+      //  and the client should drop the entire connection instead of skipping the frame."
+      // https://atproto.com/specs/event-stream
+      return Err(e.into());
+    }
+  };
+
+  match frame {
+    Frame::Message { t, data } => Ok(Some((t, data))),
+    Frame::Error { error, message } => {
+      // These follow the lexicon for the `com.atproto.sync.subscribeRepos` XRPC.
+      Err(match &*error {
+        "FutureCursor" => SubscriptionError::Other(Error::FutureCursor(message)),
+        "ConsumerTooSlow" => SubscriptionError::Other(Error::ConsumerTooSlow(message)),
+        _ => SubscriptionError::Server { error, message },
+      })
+    }
+  }
+}
+
+/// The name of an `#info` frame.
+///
+/// `OutdatedCursor` means the requested cursor was older than the server's backfill window,
+/// so some events were skipped and the consumer should treat it as data loss.
+/// `Other` holds any name not defined by the lexicon, which should be ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(from = "String", into = "String")
+)]
+pub enum InfoName {
+  OutdatedCursor,
+  Other(String),
+}
+
+impl From<String> for InfoName {
+  fn from(name: String) -> Self {
+    match &*name {
+      "OutdatedCursor" => Self::OutdatedCursor,
+      _ => Self::Other(name),
+    }
+  }
+}
+
+impl From<InfoName> for String {
+  fn from(name: InfoName) -> Self {
+    match name {
+      InfoName::OutdatedCursor => Self::from("OutdatedCursor"),
+      InfoName::Other(name) => name,
+    }
+  }
+}
+
+impl InfoName {
+  /// Returns `true` if this info means that events were skipped.
+  #[must_use]
+  pub const fn is_data_loss(&self) -> bool {
+    matches!(self, Self::OutdatedCursor)
+  }
 }
 
 /// Defines the builder for any generic `Repositories` struct that implements [`Subscription`](super::Subscription).
@@ -36,4 +201,22 @@ where
   {
     Self::handle_connection(connection, handler)
   }
+
+  /// Like [`Repositories::builder`], starting with `Repositories::layered()`, but the `handler` is wrapped with a stack of [`Layers`] first.
+  #[builder(finish_fn = build)]
+  pub fn layered<L: Layer<H>, H>(
+    connection: impl Stream<Item = ConnectionPayload> + Unpin,
+    layers: Layers<L>,
+    handler: H,
+  ) -> impl Stream<
+    Item = Result<
+      ProcessedPayload<<L::Handler as ConnectionHandler>::HandledData>,
+      super::SubscriptionError<Error>,
+    >,
+  >
+  where
+    L::Handler: Sync,
+  {
+    Self::handle_connection(connection, layers.handler(handler))
+  }
 }
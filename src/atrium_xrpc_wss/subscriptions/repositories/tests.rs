@@ -0,0 +1,357 @@
+use atrium_api::{
+  com::atproto::sync::subscribe_repos::{self, CommitData, RepoOpData},
+  types::{string::Datetime, CidLink},
+};
+use std::{
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use ipld_core::cid::{multihash::Multihash, Cid};
+use tokio::time::Instant;
+
+use super::*;
+
+fn cid_link() -> CidLink {
+  CidLink(Cid::new_v1(
+    0x71,
+    Multihash::wrap(0x12, &[0; 32]).expect("invalid multihash"),
+  ))
+}
+
+#[test]
+fn decode_commit_without_copying_blocks() {
+  let data = CommitData {
+    blobs: vec![],
+    blocks: vec![1, 2, 3, 4],
+    commit: cid_link(),
+    ops: vec![RepoOpData {
+      action: String::from("create"),
+      cid: Some(cid_link()),
+      path: String::from("app.bsky.feed.post/3k"),
+    }
+    .into()],
+    prev: None,
+    rebase: false,
+    repo: "did:plc:abc".parse().expect("invalid did"),
+    rev: String::from("3k"),
+    seq: 42,
+    since: None,
+    time: Datetime::now(),
+    too_big: false,
+  };
+  let payload = Bytes::from(serde_ipld_dagcbor::to_vec(&data).expect("failed to serialize"));
+
+  let commit = Commit::decode(&payload).expect("failed to decode");
+  assert_eq!(commit.blocks, data.blocks);
+  assert!(payload.as_ptr_range().contains(&commit.blocks.as_ptr()));
+  assert_eq!(subscribe_repos::Commit::from(commit).data, data);
+}
+
+#[test]
+fn parse_info_names() {
+  let name = InfoName::from(String::from("OutdatedCursor"));
+  assert_eq!(name, InfoName::OutdatedCursor);
+  assert!(name.is_data_loss());
+  assert_eq!(String::from(name), "OutdatedCursor");
+
+  let name = InfoName::from(String::from("Unknown"));
+  assert_eq!(name, InfoName::Other(String::from("Unknown")));
+  assert!(!name.is_data_loss());
+  assert_eq!(String::from(name), "Unknown");
+}
+
+/// A handler that yields the type of every message frame.
+struct TypeHandler;
+impl ConnectionHandler for TypeHandler {
+  type HandledData = String;
+  type HandlingError = std::convert::Infallible;
+
+  async fn handle_payload(
+    &self,
+    t: &str,
+    _payload: Bytes,
+  ) -> Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError> {
+    Ok(Some(ProcessedPayload {
+      seq: None,
+      data: t.to_owned(),
+    }))
+  }
+}
+
+#[tokio::test]
+async fn subscribe_to_byte_frames() {
+  let frames = [
+    Frame::message("#commit", &1).and_then(|f| f.encode()),
+    // {"op": 2, "t": "#commit"}, followed by {}, which must be ignored.
+    Ok(Bytes::from_static(&[
+      0xa2, 0x62, 0x6f, 0x70, 0x02, 0x61, 0x74, 0x67, 0x23, 0x63, 0x6f, 0x6d, 0x6d, 0x69, 0x74,
+      0xa0,
+    ])),
+    Frame::message("#info", &2).and_then(|f| f.encode()),
+    Frame::Error {
+      error: String::from("FutureCursor"),
+      message: None,
+    }
+    .encode(),
+    Frame::message("#commit", &3).and_then(|f| f.encode()),
+  ]
+  .map(|f| Ok::<_, std::convert::Infallible>(f.expect("failed to serialize")));
+
+  let results = Repositories::builder()
+    .connection(futures::stream::iter(frames))
+    .handler(TypeHandler)
+    .build()
+    .collect::<Vec<_>>()
+    .await;
+
+  assert_eq!(results.len(), 3);
+  assert!(matches!(&results[0], Ok(ProcessedPayload { data, .. }) if data == "#commit"));
+  assert!(matches!(&results[1], Ok(ProcessedPayload { data, .. }) if data == "#info"));
+  assert!(matches!(
+    &results[2],
+    Err(SubscriptionError::Other(Error::FutureCursor(None)))
+  ));
+}
+
+/// A handler that only processes `#info` payloads.
+struct InfoHandler;
+impl Handler for InfoHandler {
+  type HandlingError = serde_ipld_dagcbor::DecodeError<std::convert::Infallible>;
+
+  type ProcessedCommitData = ();
+  type ProcessedIdentityData = ();
+  type ProcessedAccountData = ();
+  type ProcessedHandleData = ();
+  type ProcessedMigrateData = ();
+  type ProcessedTombstoneData = ();
+  type ProcessedInfoData = String;
+  async fn process_info(
+    &self,
+    payload: subscribe_repos::Info,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedInfoData>>, Self::HandlingError> {
+    Ok(Some(ProcessedPayload {
+      seq: None,
+      data: payload.data.name,
+    }))
+  }
+}
+
+#[tokio::test]
+async fn dispatch_to_handler() {
+  let info = subscribe_repos::InfoData {
+    message: None,
+    name: String::from("OutdatedCursor"),
+  };
+  let handler = InfoHandler;
+
+  let result = handler
+    .handle_payload(
+      "#info",
+      serde_ipld_dagcbor::to_vec(&info)
+        .expect("failed to serialize")
+        .into(),
+    )
+    .await;
+  assert!(matches!(
+    result,
+    Ok(Some(ProcessedPayload { data: ProcessedData::Info(name), .. })) if name == "OutdatedCursor"
+  ));
+
+  // `process_account` is not overridden, so the payload is ignored.
+  let account = subscribe_repos::AccountData {
+    active: true,
+    did: "did:plc:abc".parse().expect("invalid did"),
+    seq: 1,
+    status: None,
+    time: Datetime::now(),
+  };
+  let result = handler
+    .handle_payload(
+      "#account",
+      serde_ipld_dagcbor::to_vec(&account)
+        .expect("failed to serialize")
+        .into(),
+    )
+    .await;
+  assert!(matches!(result, Ok(None)));
+
+  let result = handler.handle_payload("#unknown", Bytes::new()).await;
+  assert!(matches!(result, Ok(None)));
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Delayed {
+  repo: String,
+  id: u64,
+  delay: u64,
+}
+
+/// A handler that sleeps for the `delay` of each payload, in milliseconds, and yields its `id`.
+/// It counts the payloads it finished handling.
+#[derive(Default)]
+struct DelayHandler {
+  handled: Arc<AtomicUsize>,
+}
+
+impl ConnectionHandler for DelayHandler {
+  type HandledData = u64;
+  type HandlingError = serde_ipld_dagcbor::DecodeError<std::convert::Infallible>;
+
+  async fn handle_payload(
+    &self,
+    _t: &str,
+    payload: Bytes,
+  ) -> Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError> {
+    let Delayed { id, delay, .. } = serde_ipld_dagcbor::from_slice(&payload)?;
+    tokio::time::sleep(Duration::from_millis(delay)).await;
+    self.handled.fetch_add(1, Ordering::SeqCst);
+    Ok(Some(ProcessedPayload {
+      seq: None,
+      data: id,
+    }))
+  }
+}
+
+fn delayed(repo: &str, id: u64, delay: u64) -> Bytes {
+  Frame::message(
+    "#commit",
+    &Delayed {
+      repo: repo.to_owned(),
+      id,
+      delay,
+    },
+  )
+  .and_then(|f| f.encode())
+  .expect("failed to serialize")
+}
+
+async fn subscribe_concurrently(
+  handler: DelayHandler,
+  frames: Vec<Bytes>,
+  order: DeliveryOrder,
+) -> Vec<Result<ProcessedPayload<u64>, SubscriptionError<Error>>> {
+  Repositories::concurrent()
+    .connection(futures::stream::iter(
+      frames.into_iter().map(Ok::<_, std::convert::Infallible>),
+    ))
+    .handler(handler)
+    .workers(4)
+    .order(order)
+    .build()
+    .collect()
+    .await
+}
+
+/// Subscribes to payloads of the `(repo, delay)`, ending with a `FutureCursor` error frame.
+async fn subscribe_delayed(
+  payloads: &[(&str, u64)],
+  order: DeliveryOrder,
+) -> Vec<Result<ProcessedPayload<u64>, SubscriptionError<Error>>> {
+  let error = Frame::Error {
+    error: String::from("FutureCursor"),
+    message: None,
+  }
+  .encode()
+  .expect("failed to serialize");
+  let frames = payloads
+    .iter()
+    .zip(1..)
+    .map(|(&(repo, delay), id)| delayed(repo, id, delay))
+    .chain([error])
+    .collect();
+  subscribe_concurrently(DelayHandler::default(), frames, order).await
+}
+
+fn ids(results: &[Result<ProcessedPayload<u64>, SubscriptionError<Error>>]) -> Vec<u64> {
+  results
+    .iter()
+    .filter_map(|res| res.as_ref().ok().map(|p| p.data))
+    .collect()
+}
+
+#[tokio::test(start_paused = true)]
+async fn handle_concurrently_in_seq_order() {
+  let start = Instant::now();
+  let results = subscribe_delayed(
+    &[("a", 40), ("b", 30), ("a", 20), ("b", 10)],
+    DeliveryOrder::Seq,
+  )
+  .await;
+  assert_eq!(ids(&results), [1, 2, 3, 4]);
+  assert!(matches!(
+    results.last(),
+    Some(Err(SubscriptionError::Other(Error::FutureCursor(None))))
+  ));
+  // The payloads were handled at the same time, so it took as long as the slowest one.
+  assert_eq!(start.elapsed(), Duration::from_millis(40));
+}
+
+#[tokio::test(start_paused = true)]
+async fn handle_concurrently_in_repo_order() {
+  let start = Instant::now();
+  let results = subscribe_delayed(
+    &[("a", 40), ("b", 10), ("a", 0), ("b", 0)],
+    DeliveryOrder::PerRepo,
+  )
+  .await;
+  // The payloads of `b` overtake the slow one of `a`, which still comes before the next one of `a`.
+  assert_eq!(ids(&results), [2, 4, 1, 3]);
+  assert!(matches!(
+    results.last(),
+    Some(Err(SubscriptionError::Other(Error::FutureCursor(None))))
+  ));
+  assert_eq!(start.elapsed(), Duration::from_millis(40));
+}
+
+#[tokio::test(start_paused = true)]
+async fn release_repo_payloads_once_yielded() {
+  let handler = DelayHandler::default();
+  let count = Arc::clone(&handler.handled);
+  let mut results = Repositories::concurrent()
+    .connection(futures::stream::iter(
+      [delayed("a", 1, 10), delayed("a", 2, 0)].map(Ok::<_, std::convert::Infallible>),
+    ))
+    .handler(handler)
+    .workers(4)
+    .order(DeliveryOrder::PerRepo)
+    .build();
+
+  // The payloads are read, and the first one is handled while the results aren't polled.
+  let pending = tokio::time::timeout(Duration::from_millis(1), results.next()).await;
+  assert!(pending.is_err());
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  // The fast payload of the same repository waits for the slow one to be yielded.
+  assert_eq!(count.load(Ordering::SeqCst), 1);
+
+  let results = results.collect::<Vec<_>>().await;
+  assert_eq!(ids(&results), [1, 2]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn abort_in_flight_payloads_after_error() {
+  let handler = DelayHandler::default();
+  let count = Arc::clone(&handler.handled);
+  let invalid = Frame::message("#commit", &"not a payload")
+    .and_then(|f| f.encode())
+    .expect("failed to serialize");
+  let results = subscribe_concurrently(
+    handler,
+    vec![invalid, delayed("a", 2, 10), delayed("b", 3, 20)],
+    DeliveryOrder::Seq,
+  )
+  .await;
+  assert!(matches!(
+    results.as_slice(),
+    [Err(SubscriptionError::Handler(_))]
+  ));
+
+  // The payloads being handled when the error was yielded never finish.
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  assert_eq!(count.load(Ordering::SeqCst), 0);
+}
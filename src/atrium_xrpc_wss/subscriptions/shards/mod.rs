@@ -0,0 +1,270 @@
+//! This file defines the [`Shards`], which fan out the payloads of a subscription to multiple consumers,
+//! partitioned by repository.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+  collections::VecDeque,
+  error::Error as StdError,
+  hash::{DefaultHasher, Hash, Hasher},
+  sync::{Arc, Mutex, PoisonError},
+};
+
+use bon::bon;
+use futures::{Stream, StreamExt};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::{repositories::ProcessedData, ProcessedPayload, SubscriptionError};
+
+/// A trait for the processed data that can be partitioned by repository.
+pub trait Partition {
+  /// Returns the DID of the repository the data comes from, if any.
+  fn repo(&self) -> Option<&str>;
+}
+
+impl Partition for () {
+  fn repo(&self) -> Option<&str> {
+    None
+  }
+}
+
+impl<C, I0, A, H, M, T, I1> Partition for ProcessedData<C, I0, A, H, M, T, I1>
+where
+  C: Partition,
+  I0: Partition,
+  A: Partition,
+  H: Partition,
+  M: Partition,
+  T: Partition,
+  I1: Partition,
+{
+  fn repo(&self) -> Option<&str> {
+    match self {
+      Self::Commit(data) => data.repo(),
+      Self::Identity(data) => data.repo(),
+      Self::Account(data) => data.repo(),
+      Self::Handle(data) => data.repo(),
+      Self::Migrate(data) => data.repo(),
+      Self::Tombstone(data) => data.repo(),
+      Self::Info(data) => data.repo(),
+    }
+  }
+}
+
+/// The progress of a single shard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardStats {
+  /// The number of payloads routed to the shard, but not processed yet.
+  pub lag: usize,
+  /// The `seq` of the last payload processed by the shard.
+  pub last_processed: Option<i64>,
+  /// Whether the [`Shard`] was dropped, or its consumer panicked. The payloads it didn't process
+  /// keep the [`Checkpoint`] from moving past them, and the next ones routed to it are discarded,
+  /// the first of which is also left pending.
+  pub closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+  /// The `seq` of the last payload routed to any shard.
+  last_routed: Option<i64>,
+  /// The `seq` of the payloads routed to each shard, but not processed yet, in order.
+  pending: Vec<VecDeque<Option<i64>>>,
+  last_processed: Vec<Option<i64>>,
+  closed: Vec<bool>,
+}
+
+/// A handle on the progress of all the shards.
+#[derive(Debug, Clone)]
+pub struct Checkpoint(Arc<Mutex<State>>);
+
+impl Checkpoint {
+  fn new(shards: usize) -> Self {
+    Self(Arc::new(Mutex::new(State {
+      last_routed: None,
+      pending: vec![VecDeque::new(); shards],
+      last_processed: vec![None; shards],
+      closed: vec![false; shards],
+    })))
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+    self.0.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// Returns the cursor that is safe to checkpoint, which is the last `seq` such that
+  /// every payload up to it has been processed by its shard.
+  ///
+  /// Resuming the subscription from it may process some payloads again, but never skips any.
+  /// So it doesn't move past the payloads a closed shard didn't process, see [`ShardStats::closed`].
+  #[must_use]
+  pub fn cursor(&self) -> Option<i64> {
+    let state = self.lock();
+    // Each shard processes its payloads in order, so the first one pending in each shard
+    // is the oldest one not processed yet.
+    state
+      .pending
+      .iter()
+      .filter_map(|pending| pending.iter().find_map(|seq| *seq))
+      .min()
+      .map_or(state.last_routed, |seq| Some(seq - 1))
+  }
+
+  /// Returns the progress of each shard.
+  #[must_use]
+  pub fn stats(&self) -> Vec<ShardStats> {
+    let state = self.lock();
+    state
+      .pending
+      .iter()
+      .zip(&state.last_processed)
+      .zip(&state.closed)
+      .map(|((pending, last_processed), closed)| ShardStats {
+        lag: pending.len(),
+        last_processed: *last_processed,
+        closed: *closed,
+      })
+      .collect()
+  }
+}
+
+/// A consumer of the payloads routed to one shard.
+#[derive(Debug)]
+pub struct Shard<D> {
+  index: usize,
+  receiver: mpsc::Receiver<ProcessedPayload<D>>,
+  checkpoint: Checkpoint,
+  /// Whether a payload was received and not marked as processed yet.
+  in_progress: bool,
+}
+
+impl<D> Shard<D> {
+  /// Returns the index of the shard.
+  #[must_use]
+  pub const fn index(&self) -> usize {
+    self.index
+  }
+
+  /// Receives the next payload of the shard, returning `None` once the subscription has ended.
+  ///
+  /// The previously received payload is marked as processed, as if [`Shard::done`] was called.
+  pub async fn recv(&mut self) -> Option<ProcessedPayload<D>> {
+    self.done();
+    let payload = self.receiver.recv().await?;
+    self.in_progress = true;
+    Some(payload)
+  }
+
+  /// Marks the last received payload as processed, so that the [`Checkpoint`] can move past it.
+  pub fn done(&mut self) {
+    if !std::mem::take(&mut self.in_progress) {
+      return;
+    }
+    let mut state = self.checkpoint.lock();
+    if let Some(Some(seq)) = state.pending[self.index].pop_front() {
+      state.last_processed[self.index] = Some(seq);
+    }
+  }
+}
+
+impl<D> Drop for Shard<D> {
+  fn drop(&mut self) {
+    // The payload being processed, if any, is left pending since it may not have been processed.
+    self.checkpoint.lock().closed[self.index] = true;
+  }
+}
+
+/// The payloads of a subscription, fanned out to multiple [`Shard`]s.
+///
+/// Each payload is routed to a shard by the hash of its repository, so the payloads of a repository
+/// are always processed in order, by the same shard. Payloads without a repository go to the first shard.
+#[derive(Debug)]
+pub struct Shards<D, E> {
+  /// The consumers of each shard.
+  pub shards: Vec<Shard<D>>,
+  /// The progress of the shards.
+  pub checkpoint: Checkpoint,
+  /// The task routing the payloads, which returns the error that ended the subscription, if any.
+  pub router: JoinHandle<Result<(), SubscriptionError<E>>>,
+}
+
+#[bon]
+impl<D, E> Shards<D, E>
+where
+  D: 'static + Send + Partition,
+  E: 'static + Send + Sync + StdError,
+{
+  /// Spawns a task that routes the payloads of the `subscription` to `shards` shards,
+  /// each one buffering up to `capacity` payloads (defaults to 1024).
+  ///
+  /// # Panics
+  /// Panics if `shards` or `capacity` is zero.
+  #[builder]
+  #[expect(
+    tail_expr_drop_order,
+    reason = "Only the payloads of the routing loop are dropped in a different order, after the checkpoint is updated."
+  )]
+  pub fn new(
+    subscription: impl 'static + Send + Stream<Item = Result<ProcessedPayload<D>, SubscriptionError<E>>>,
+    shards: usize,
+    #[builder(default = 1024)] capacity: usize,
+  ) -> Self {
+    assert!(shards > 0, "there must be at least one shard");
+    let checkpoint = Checkpoint::new(shards);
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+      (0..shards).map(|_| mpsc::channel(capacity)).unzip();
+
+    let router_checkpoint = checkpoint.clone();
+    let router = tokio::spawn(async move {
+      let mut subscription = Box::pin(subscription);
+      while let Some(payload) = subscription.next().await {
+        let payload = payload?;
+        let index = payload.data.repo().map_or(0, |repo| shard_of(repo, shards));
+        {
+          let mut state = router_checkpoint.lock();
+          state.pending[index].push_back(payload.seq);
+          state.last_routed = payload.seq.or(state.last_routed);
+        }
+        if senders[index].send(payload).await.is_err() {
+          // The shard was closed, so the payload will never be processed. It's left pending to
+          // keep the checkpoint from moving past it, unless an earlier one already does.
+          let mut state = router_checkpoint.lock();
+          let pending = &mut state.pending[index];
+          if pending.back() == Some(&None) || pending.iter().flatten().nth(1).is_some() {
+            pending.pop_back();
+          }
+          drop(state);
+        }
+      }
+      Ok(())
+    });
+
+    Self {
+      shards: receivers
+        .into_iter()
+        .enumerate()
+        .map(|(index, receiver)| Shard {
+          index,
+          receiver,
+          checkpoint: checkpoint.clone(),
+          in_progress: false,
+        })
+        .collect(),
+      checkpoint,
+      router,
+    }
+  }
+}
+
+/// Returns the shard of the `repo`.
+fn shard_of(repo: &str, shards: usize) -> usize {
+  let mut hasher = DefaultHasher::new();
+  repo.hash(&mut hasher);
+  #[expect(
+    clippy::cast_possible_truncation,
+    reason = "The result is less than `shards`, which is a `usize`."
+  )]
+  let index = (hasher.finish() % shards as u64) as usize;
+  index
+}
@@ -0,0 +1,155 @@
+use super::*;
+use crate::atrium_xrpc_wss::subscriptions::repositories;
+
+struct Repo(&'static str);
+impl Partition for Repo {
+  fn repo(&self) -> Option<&str> {
+    Some(self.0)
+  }
+}
+
+type Item = Result<ProcessedPayload<Repo>, SubscriptionError<repositories::Error>>;
+
+fn payloads(repos: &[&'static str]) -> Vec<Item> {
+  repos
+    .iter()
+    .zip(1..)
+    .map(|(&repo, seq)| {
+      Ok(ProcessedPayload {
+        seq: Some(seq),
+        data: Repo(repo),
+      })
+    })
+    .collect()
+}
+
+#[tokio::test]
+#[expect(
+  tail_expr_drop_order,
+  reason = "The received payloads are only compared, so they can be freed in any order."
+)]
+async fn route_by_repo() {
+  let Shards {
+    mut shards,
+    checkpoint,
+    router,
+  } = Shards::builder()
+    .subscription(futures::stream::iter(payloads(&["a", "b", "a", "c", "b"])))
+    .shards(2)
+    .build();
+  router
+    .await
+    .expect("router panicked")
+    .expect("subscription failed");
+  assert_eq!(checkpoint.cursor(), Some(0));
+
+  // Processing the first payload lets the checkpoint move past it.
+  let shard = &mut shards[shard_of("a", 2)];
+  let first = shard.recv().await.expect("missing payload");
+  assert_eq!(first.seq, Some(1));
+  assert_eq!(checkpoint.cursor(), Some(0));
+  shard.done();
+  assert_eq!(checkpoint.cursor(), Some(1));
+  assert_eq!(checkpoint.stats()[shard.index()].last_processed, Some(1));
+
+  for shard in &mut shards {
+    let mut received = Vec::new();
+    while let Some(payload) = shard.recv().await {
+      assert_eq!(shard_of(payload.data.0, 2), shard.index());
+      received.push(payload.seq.expect("missing seq"));
+    }
+    assert!(received.is_sorted());
+  }
+  assert_eq!(checkpoint.cursor(), Some(5));
+  assert!(checkpoint.stats().iter().all(|stats| stats.lag == 0));
+}
+
+#[tokio::test]
+async fn close_dropped_shards() {
+  let Shards {
+    mut shards,
+    checkpoint,
+    router,
+  } = Shards::builder()
+    .subscription(futures::stream::iter(payloads(&["a", "b", "a", "b"])))
+    .shards(2)
+    .build();
+  router
+    .await
+    .expect("router panicked")
+    .expect("subscription failed");
+
+  // The shard of `a` is dropped while processing its first payload.
+  let a_index = shard_of("a", 2);
+  assert_ne!(a_index, shard_of("b", 2));
+  let mut a = shards.swap_remove(a_index);
+  let mut b = shards.pop().expect("missing shard");
+  a.recv().await.expect("missing payload");
+  drop(a);
+  while b.recv().await.is_some() {}
+
+  let stats = checkpoint.stats();
+  assert!(stats[a_index].closed);
+  assert_eq!(stats[a_index].lag, 2);
+  assert!(!stats[b.index()].closed);
+  // The payloads of `a` were never processed, so the cursor stays before them.
+  assert_eq!(checkpoint.cursor(), Some(0));
+}
+
+#[tokio::test]
+async fn hold_checkpoint_on_discarded_payloads() {
+  let (sender, receiver) = futures::channel::mpsc::unbounded();
+  let Shards {
+    mut shards,
+    checkpoint,
+    router,
+  } = Shards::builder().subscription(receiver).shards(2).build();
+  let mut items = payloads(&["a", "a", "b", "a"]).into_iter();
+  let mut send = |n| {
+    for item in items.by_ref().take(n) {
+      sender.unbounded_send(item).expect("failed to send");
+    }
+  };
+
+  // The shard of `a` is dropped after processing its first payload.
+  let a_index = shard_of("a", 2);
+  let mut a = shards.swap_remove(a_index);
+  let mut b = shards.pop().expect("missing shard");
+  send(1);
+  a.recv().await.expect("missing payload");
+  a.done();
+  drop(a);
+
+  // The next payloads of `a` are discarded.
+  send(3);
+  drop(sender);
+  router
+    .await
+    .expect("router panicked")
+    .expect("subscription failed");
+  while b.recv().await.is_some() {}
+
+  let stats = checkpoint.stats();
+  assert!(stats[a_index].closed);
+  // Only the first discarded payload is kept pending, which is enough to hold the checkpoint.
+  assert_eq!(stats[a_index].lag, 1);
+  assert_eq!(checkpoint.cursor(), Some(1));
+}
+
+#[tokio::test]
+async fn return_subscription_error() {
+  let mut items = payloads(&["a"]);
+  items.push(Err(SubscriptionError::Other(
+    repositories::Error::ConsumerTooSlow(None),
+  )));
+  let Shards { router, .. } = Shards::builder()
+    .subscription(futures::stream::iter(items))
+    .shards(1)
+    .build();
+  assert!(matches!(
+    router.await.expect("router panicked"),
+    Err(SubscriptionError::Other(
+      repositories::Error::ConsumerTooSlow(None)
+    ))
+  ));
+}
@@ -0,0 +1,127 @@
+//! This file defines the format of the frame archives, used to record and replay raw firehose frames.
+//!
+//! An archive is a sequence of segment files. Each segment starts with the [`MAGIC`] bytes,
+//! followed by any number of length-prefixed [`ArchiveRecord`]s. All integers are little-endian:
+//!
+//! | Field         | Type   | Description                                            |
+//! |---------------|--------|--------------------------------------------------------|
+//! | `len`         | `u32`  | The length of the frame, up to [`MAX_FRAME_SIZE`].     |
+//! | `received_at` | `i64`  | When the frame was received, in microseconds (UTC).    |
+//! | `has_seq`     | `u8`   | `1` if the `seq` is known, `0` otherwise.              |
+//! | `seq`         | `i64`  | The `seq` of the frame, or `0` if unknown.             |
+//! | `frame`       | `[u8]` | The raw binary frame, exactly as received.             |
+//!
+//! Segments may be compressed with zstd (with the `zstd` feature), in which case the whole file,
+//! including the magic bytes, is a single zstd stream.
+
+#[cfg(test)]
+mod tests;
+
+mod recorder;
+mod replay;
+pub use recorder::Recorder;
+pub use replay::Replay;
+
+use std::io::{self, Read, Write};
+
+use bytes::Bytes;
+
+/// The magic bytes at the start of every segment, including the format version.
+pub const MAGIC: &[u8; 8] = b"FHARCH\x00\x01";
+
+/// The maximum length of a frame, which is also the default limit of the `tungstenite` messages.
+pub const MAX_FRAME_SIZE: usize = 64 << 20;
+
+/// The extension of uncompressed segment files.
+pub const EXTENSION: &str = "far";
+/// The extension of zstd compressed segment files.
+pub const ZSTD_EXTENSION: &str = "far.zst";
+
+/// A single frame stored in an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveRecord {
+  /// When the frame was received, in microseconds since the Unix epoch.
+  pub received_at: i64,
+  /// The `seq` of the frame, if it has one.
+  pub seq: Option<i64>,
+  /// The raw binary frame.
+  pub frame: Bytes,
+}
+
+impl ArchiveRecord {
+  /// Writes the record, returning the amount of bytes written.
+  ///
+  /// # Errors
+  /// Returns an error if writing fails, or if the frame is larger than [`MAX_FRAME_SIZE`].
+  pub fn write_to(&self, mut writer: impl Write) -> io::Result<usize> {
+    let len = u32::try_from(self.frame.len())
+      .ok()
+      .filter(|_| self.frame.len() <= MAX_FRAME_SIZE)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The frame is too large"))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&self.received_at.to_le_bytes())?;
+    writer.write_all(&[u8::from(self.seq.is_some())])?;
+    writer.write_all(&self.seq.unwrap_or_default().to_le_bytes())?;
+    writer.write_all(&self.frame)?;
+    Ok(4 + 8 + 1 + 8 + self.frame.len())
+  }
+
+  /// Reads the next record, returning `None` at the end of the segment.
+  ///
+  /// # Errors
+  /// Returns an error if reading fails, if the segment is truncated, or if the length of the frame
+  /// is larger than [`MAX_FRAME_SIZE`], which is checked before allocating it.
+  pub fn read_from(mut reader: impl Read) -> io::Result<Option<Self>> {
+    let mut len = [0; 4];
+    // A clean end of file can only happen before a new record starts, a torn header is truncated.
+    let read = loop {
+      match reader.read(&mut len[..1]) {
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        res => break res?,
+      }
+    };
+    if read == 0 {
+      return Ok(None);
+    }
+    reader.read_exact(&mut len[1..])?;
+
+    let mut received_at = [0; 8];
+    reader.read_exact(&mut received_at)?;
+    let mut has_seq = [0; 1];
+    reader.read_exact(&mut has_seq)?;
+    let mut seq = [0; 8];
+    reader.read_exact(&mut seq)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The frame is too large",
+      ));
+    }
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame)?;
+
+    Ok(Some(Self {
+      received_at: i64::from_le_bytes(received_at),
+      seq: (has_seq[0] != 0).then(|| i64::from_le_bytes(seq)),
+      frame: frame.into(),
+    }))
+  }
+}
+
+/// Checks the magic bytes at the start of a segment.
+///
+/// # Errors
+/// Returns an error if reading fails or if the segment does not start with [`MAGIC`].
+pub fn read_magic(mut reader: impl Read) -> io::Result<()> {
+  let mut magic = [0; MAGIC.len()];
+  reader.read_exact(&mut magic)?;
+  if &magic == MAGIC {
+    Ok(())
+  } else {
+    Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Not a frame archive segment",
+    ))
+  }
+}
@@ -0,0 +1,264 @@
+//! This file provides the [`Recorder`], which tees the raw frames of a connection into an archive.
+
+use std::{
+  fs::{File, OpenOptions},
+  io::{self, BufWriter, Write},
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use async_stream::stream;
+use bon::bon;
+use bytes::Bytes;
+use chrono::Utc;
+use futures::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use super::{ArchiveRecord, EXTENSION, MAGIC};
+use crate::atrium_xrpc_wss::subscriptions::frames::Frame;
+
+/// How many frames [`Recorder::tee`] queues for the blocking thread writing them.
+const QUEUE_SIZE: usize = 1024;
+
+/// Builds the record of a frame, with its `seq` if it's a message frame that could be parsed.
+fn received(frame: Bytes, received_at: i64) -> ArchiveRecord {
+  let seq = Frame::try_from(frame.clone()).ok().and_then(|f| f.seq());
+  ArchiveRecord {
+    received_at,
+    seq,
+    frame,
+  }
+}
+
+/// The segment currently being written.
+enum Segment {
+  Plain(BufWriter<File>),
+  #[cfg(feature = "zstd")]
+  Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Write for Segment {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      Self::Plain(w) => w.write(buf),
+      #[cfg(feature = "zstd")]
+      Self::Zstd(w) => w.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      Self::Plain(w) => w.flush(),
+      #[cfg(feature = "zstd")]
+      Self::Zstd(w) => w.flush(),
+    }
+  }
+}
+
+/// Records raw binary frames into an append-only archive, made of segment files.
+///
+/// A new segment is started when the current one reaches `max_segment_size` bytes (before
+/// compression), or when its first frame is older than `max_segment_age`. Segments are named
+/// after the time their first frame was received, so they sort chronologically, followed by a
+/// counter keeping the names unique.
+///
+/// The current segment is closed when the recorder is dropped.
+/// See the [`archive`](super) module for the format of the segments.
+pub struct Recorder {
+  directory: PathBuf,
+  prefix: String,
+  max_segment_size: Option<u64>,
+  max_segment_age: Option<Duration>,
+  compress: bool,
+  /// The segment being written, along with its size, the time its first frame was received,
+  /// and its path.
+  segment: Option<(Segment, u64, i64, PathBuf)>,
+  /// The number of segments opened so far.
+  opened: u64,
+}
+
+#[bon]
+impl Recorder {
+  /// Builds a new recorder writing to `directory`, which is created if it doesn't exist.
+  ///
+  /// # Errors
+  /// Returns an error if the directory could not be created, or if `compress` was
+  /// requested without the `zstd` feature.
+  #[builder]
+  pub fn new(
+    #[builder(into)] directory: PathBuf,
+    #[builder(into, default = String::from("firehose"))] prefix: String,
+    max_segment_size: Option<u64>,
+    max_segment_age: Option<Duration>,
+    #[builder(default)] compress: bool,
+  ) -> io::Result<Self> {
+    if compress && cfg!(not(feature = "zstd")) {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Compression requires the `zstd` feature",
+      ));
+    }
+    std::fs::create_dir_all(&directory)?;
+    Ok(Self {
+      directory,
+      prefix,
+      max_segment_size,
+      max_segment_age,
+      compress,
+      segment: None,
+      opened: 0,
+    })
+  }
+}
+
+impl Recorder {
+  /// Records a single binary frame, received now.
+  ///
+  /// # Errors
+  /// Returns an error if the frame could not be written.
+  pub fn record_frame(&mut self, frame: Bytes) -> io::Result<()> {
+    self.record(&received(frame, Utc::now().timestamp_micros()))
+  }
+
+  /// Records an [`ArchiveRecord`], rotating the segment if needed.
+  ///
+  /// # Errors
+  /// Returns an error if the record could not be written.
+  pub fn record(&mut self, record: &ArchiveRecord) -> io::Result<()> {
+    if self.should_rotate(record.received_at) {
+      self.finish()?;
+    }
+    if self.segment.is_none() {
+      self.segment = Some(self.open(record.received_at)?);
+    }
+    if let Some((segment, size, ..)) = &mut self.segment {
+      *size += record.write_to(segment)? as u64;
+    }
+    Ok(())
+  }
+
+  /// Tees the binary messages of the `connection` into the archive, passing them through.
+  ///
+  /// The frames are recorded on a blocking thread, so the connection keeps being read while the
+  /// segments are written, up to 1024 frames ahead. The current segment is closed when
+  /// the connection ends. If a frame could not be recorded, or the segment could not be closed,
+  /// the IO error is yielded and the stream ends.
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The frames are plain buffers, which can be freed in any order."
+  )]
+  pub fn tee(
+    mut self,
+    mut connection: impl Stream<Item = tungstenite::Result<Message>> + Unpin,
+  ) -> impl Stream<Item = tungstenite::Result<Message>> + Unpin {
+    let (sender, mut receiver) = mpsc::channel::<(Bytes, i64)>(QUEUE_SIZE);
+    let writer = tokio::task::spawn_blocking(move || {
+      while let Some((frame, received_at)) = receiver.blocking_recv() {
+        self.record(&received(frame, received_at))?;
+      }
+      self.finish()
+    });
+
+    Box::pin(stream! {
+      while let Some(message) = connection.next().await {
+        if let Ok(Message::Binary(data)) = &message {
+          let frame = Bytes::copy_from_slice(data);
+          // The writer only stops early after an error, which is yielded below.
+          if sender.send((frame, Utc::now().timestamp_micros())).await.is_err() {
+            break;
+          }
+        }
+        yield message;
+      }
+      drop(sender);
+      match writer.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => yield Err(e.into()),
+        Err(e) => yield Err(io::Error::other(e).into()),
+      }
+    })
+  }
+
+  /// Flushes the data written so far to the current segment.
+  ///
+  /// # Errors
+  /// Returns an error if flushing fails.
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.segment.as_mut().map_or(Ok(()), |(s, ..)| s.flush())
+  }
+
+  /// The path of the segment being written, if any.
+  #[must_use]
+  pub fn segment(&self) -> Option<&Path> {
+    self.segment.as_ref().map(|(.., path)| path.as_path())
+  }
+
+  /// Closes the current segment. The next frame will be written to a new one.
+  ///
+  /// # Errors
+  /// Returns an error if the segment could not be flushed.
+  pub fn finish(&mut self) -> io::Result<()> {
+    match self.segment.take() {
+      Some((Segment::Plain(mut w), ..)) => w.flush(),
+      #[cfg(feature = "zstd")]
+      Some((Segment::Zstd(w), ..)) => w.finish()?.flush(),
+      None => Ok(()),
+    }
+  }
+
+  fn should_rotate(&self, received_at: i64) -> bool {
+    let Some((_, size, started_at, _)) = &self.segment else {
+      return false;
+    };
+    let too_big = self.max_segment_size.is_some_and(|max| *size >= max);
+    let too_old = self.max_segment_age.is_some_and(|max| {
+      let age = received_at.saturating_sub(*started_at);
+      u128::try_from(age).is_ok_and(|age| age >= max.as_micros())
+    });
+    too_big || too_old
+  }
+
+  fn open(&mut self, started_at: i64) -> io::Result<(Segment, u64, i64, PathBuf)> {
+    let extension = if self.compress {
+      super::ZSTD_EXTENSION
+    } else {
+      EXTENSION
+    };
+    let (file, path) = loop {
+      let path = self.directory.join(format!(
+        "{}-{started_at:020}-{:06}.{extension}",
+        self.prefix, self.opened
+      ));
+      self.opened += 1;
+      let file = OpenOptions::new().append(true).create_new(true).open(&path);
+      // The name may have been taken by another recorder, or before a restart.
+      if !file
+        .as_ref()
+        .is_err_and(|e| e.kind() == io::ErrorKind::AlreadyExists)
+      {
+        break (BufWriter::new(file?), path);
+      }
+    };
+
+    let mut segment = if self.compress {
+      #[cfg(feature = "zstd")]
+      {
+        Segment::Zstd(zstd::Encoder::new(file, 0)?)
+      }
+      #[cfg(not(feature = "zstd"))]
+      unreachable!("Checked when building the recorder.")
+    } else {
+      Segment::Plain(file)
+    };
+    segment.write_all(MAGIC)?;
+    Ok((segment, MAGIC.len() as u64, started_at, path))
+  }
+}
+
+impl Drop for Recorder {
+  fn drop(&mut self) {
+    // Errors can't be reported here, call `finish` beforehand to handle them.
+    drop(self.finish());
+  }
+}
@@ -0,0 +1,181 @@
+//! This file provides the [`Replay`], which reads an archive back as a connection stream.
+
+use std::{
+  fs::File,
+  io::{self, BufReader, Read},
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use bon::bon;
+use futures::Stream;
+use tokio::{sync::mpsc, time::Instant};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use super::{read_magic, ArchiveRecord, EXTENSION, ZSTD_EXTENSION};
+
+/// How many records are read ahead of the stream.
+const READ_AHEAD: usize = 1024;
+
+/// Replays the frames of an archive, as if they were being received from a connection.
+///
+/// Since it yields [`tungstenite::Result<Message>`] items, it can be used in place of a
+/// connection for any [`Subscription`](crate::atrium_xrpc_wss::subscriptions::Subscription).
+pub struct Replay {
+  segments: Vec<PathBuf>,
+  from_seq: Option<i64>,
+  speed: Option<f64>,
+}
+
+#[bon]
+impl Replay {
+  /// Builds a new replay of the archive at `path`, which is either a single segment file or a
+  /// directory of segments, replayed in the order of their names.
+  ///
+  /// If `from_seq` is set, every frame before the first one with a `seq` greater than or equal
+  /// to it is skipped. If `speed` is set, the frames are paced by their original receive times,
+  /// scaled by it (`2.0` replays twice as fast). Otherwise, they are replayed as fast as possible.
+  ///
+  /// # Errors
+  /// Returns an error if the `speed` is not a finite number greater than 0, or if the directory
+  /// could not be read.
+  #[builder]
+  pub fn new(
+    #[builder(into)] path: PathBuf,
+    from_seq: Option<i64>,
+    speed: Option<f64>,
+  ) -> io::Result<Self> {
+    if speed.is_some_and(|speed| !(speed.is_finite() && speed > 0.0)) {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The replay speed must be a finite number greater than 0",
+      ));
+    }
+    let segments = if path.is_dir() {
+      let mut segments = std::fs::read_dir(&path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .filter(|path| path.as_ref().map_or(true, |p| is_segment(p)))
+        .collect::<io::Result<Vec<_>>>()?;
+      segments.sort();
+      segments
+    } else {
+      vec![path]
+    };
+    Ok(Self {
+      segments,
+      from_seq,
+      speed,
+    })
+  }
+}
+
+impl Replay {
+  /// Starts replaying the archive.
+  ///
+  /// The segments are read on a blocking thread. If reading fails, the IO error is yielded
+  /// and the stream ends.
+  #[expect(
+    tail_expr_drop_order,
+    reason = "Nothing is flushed when the records or the channel are dropped, so their order doesn't matter."
+  )]
+  pub fn into_stream(self) -> impl Stream<Item = tungstenite::Result<Message>> + Unpin {
+    let Self {
+      segments,
+      from_seq,
+      speed,
+    } = self;
+
+    let (tx, mut rx) = mpsc::channel(READ_AHEAD);
+    tokio::task::spawn_blocking(move || {
+      let mut started = from_seq.is_none();
+      for path in segments {
+        let mut reader = match open(&path) {
+          Ok(reader) => reader,
+          Err(e) => {
+            drop(tx.blocking_send(Err(e)));
+            return;
+          }
+        };
+        loop {
+          let record = match ArchiveRecord::read_from(&mut reader) {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) => {
+              drop(tx.blocking_send(Err(e)));
+              return;
+            }
+          };
+          started = started
+            || record
+              .seq
+              .zip(from_seq)
+              .is_some_and(|(seq, from)| seq >= from);
+          if started && tx.blocking_send(Ok(record)).is_err() {
+            return; // The stream was dropped.
+          }
+        }
+      }
+    });
+
+    let stream = async_stream::stream! {
+      // The instant and receive time of the first frame, used to pace the following ones.
+      let mut origin = None;
+      while let Some(record) = rx.recv().await {
+        let record = match record {
+          Ok(record) => record,
+          Err(e) => {
+            yield Err(tungstenite::Error::Io(e));
+            break;
+          }
+        };
+        if let Some(speed) = speed {
+          let (instant, received_at) = *origin.get_or_insert_with(|| (Instant::now(), record.received_at));
+          tokio::time::sleep_until(instant + delay(record.received_at - received_at, speed)).await;
+        }
+        yield Ok(Message::Binary(record.frame.into()));
+      }
+    };
+
+    Box::pin(stream)
+  }
+}
+
+/// Scales the time elapsed between two frames, in microseconds, by the replay speed.
+fn delay(elapsed: i64, speed: f64) -> Duration {
+  #[expect(
+    clippy::cast_precision_loss,
+    reason = "Microsecond precision loss is irrelevant."
+  )]
+  let secs = elapsed.max(0) as f64 / 1_000_000.0 / speed;
+  Duration::try_from_secs_f64(secs).unwrap_or_default()
+}
+
+fn is_segment(path: &Path) -> bool {
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  name.ends_with(&format!(".{EXTENSION}")) || name.ends_with(&format!(".{ZSTD_EXTENSION}"))
+}
+
+/// Opens a segment, checking its magic bytes.
+fn open(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+  let file = BufReader::new(File::open(path)?);
+  let mut reader: Box<dyn Read + Send> = if path
+    .to_string_lossy()
+    .ends_with(&format!(".{ZSTD_EXTENSION}"))
+  {
+    #[cfg(feature = "zstd")]
+    {
+      Box::new(zstd::Decoder::with_buffer(file)?)
+    }
+    #[cfg(not(feature = "zstd"))]
+    {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Compressed segments require the `zstd` feature",
+      ));
+    }
+  } else {
+    Box::new(file)
+  };
+  read_magic(&mut reader)?;
+  Ok(reader)
+}
@@ -0,0 +1,285 @@
+use std::{
+  fs,
+  io::{self, Cursor},
+  path::Path,
+  time::Duration,
+};
+
+use futures::StreamExt;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+
+use super::*;
+use crate::atrium_xrpc_wss::subscriptions::frames::Frame;
+
+fn frame(seq: i64) -> Bytes {
+  #[derive(serde::Serialize)]
+  struct Payload {
+    seq: i64,
+  }
+
+  Frame::message("#commit", &Payload { seq })
+    .and_then(|f| f.encode())
+    .expect("failed to serialize")
+}
+
+fn read_segment(path: &Path) -> Vec<ArchiveRecord> {
+  let data = fs::read(path).expect("failed to read segment");
+  #[cfg(feature = "zstd")]
+  let data = if path.to_string_lossy().ends_with(ZSTD_EXTENSION) {
+    zstd::decode_all(data.as_slice()).expect("failed to decompress")
+  } else {
+    data
+  };
+  let mut reader = Cursor::new(data);
+  read_magic(&mut reader).expect("invalid magic");
+  std::iter::from_fn(|| ArchiveRecord::read_from(&mut reader).expect("failed to read record"))
+    .collect()
+}
+
+fn segments(directory: &Path) -> Vec<std::path::PathBuf> {
+  let mut paths = fs::read_dir(directory)
+    .expect("failed to read directory")
+    .map(|e| e.expect("failed to read entry").path())
+    .collect::<Vec<_>>();
+  paths.sort();
+  paths
+}
+
+#[test]
+fn record_round_trip() {
+  let records = [
+    ArchiveRecord {
+      received_at: 1,
+      seq: Some(42),
+      frame: frame(42),
+    },
+    ArchiveRecord {
+      received_at: 2,
+      seq: None,
+      frame: Bytes::from_static(b"not a frame"),
+    },
+  ];
+  let mut buf = Vec::new();
+  for record in &records {
+    record.write_to(&mut buf).expect("failed to write");
+  }
+
+  let mut reader = buf.as_slice();
+  for record in records {
+    let read = ArchiveRecord::read_from(&mut reader).expect("failed to read");
+    assert_eq!(read, Some(record));
+  }
+  assert_eq!(ArchiveRecord::read_from(&mut reader).ok(), Some(None));
+}
+
+#[test]
+fn read_torn_record() {
+  let mut buf = Vec::new();
+  ArchiveRecord {
+    received_at: 1,
+    seq: None,
+    frame: frame(1),
+  }
+  .write_to(&mut buf)
+  .expect("failed to write");
+
+  // The segment is only cleanly ended before the length of a record.
+  for len in [2, 10, buf.len() - 1] {
+    let err = ArchiveRecord::read_from(&buf[..len]).expect_err("read a torn record");
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+  }
+}
+
+#[test]
+fn reject_oversized_record() {
+  // The length prefix of a corrupt record, followed by its header.
+  let mut buf = u32::MAX.to_le_bytes().to_vec();
+  buf.extend([0; 17]);
+  let err = ArchiveRecord::read_from(&buf[..]).expect_err("read an oversized record");
+  assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+  let record = ArchiveRecord {
+    received_at: 1,
+    seq: None,
+    frame: vec![0; MAX_FRAME_SIZE + 1].into(),
+  };
+  let err = record
+    .write_to(io::sink())
+    .expect_err("wrote an oversized record");
+  assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn rotate_segments_by_size() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let record = |recorder: &mut Recorder, seq| {
+    // Every frame is received at the same time, so that the segments get the same timestamp.
+    let record = ArchiveRecord {
+      received_at: 0,
+      seq: Some(seq),
+      frame: frame(seq),
+    };
+    recorder.record(&record).expect("failed to record");
+  };
+  let build = || {
+    Recorder::builder()
+      .directory(directory.path())
+      .max_segment_size(1)
+      .build()
+      .expect("failed to build recorder")
+  };
+  let mut recorder = build();
+  record(&mut recorder, 1);
+  record(&mut recorder, 2);
+  drop(recorder);
+  // A new recorder doesn't overwrite the segments of the previous one.
+  record(&mut build(), 3);
+
+  let segments = segments(directory.path());
+  assert_eq!(segments.len(), 3);
+  for (path, seq) in segments.iter().zip(1..) {
+    let records = read_segment(path);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].seq, Some(seq));
+    assert_eq!(records[0].frame, frame(seq));
+  }
+}
+
+#[test]
+fn rotate_segments_by_age() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let mut recorder = Recorder::builder()
+    .directory(directory.path())
+    .max_segment_age(Duration::from_micros(10))
+    .build()
+    .expect("failed to build recorder");
+  for (received_at, seq) in [(0, 1), (5, 2), (10, 3)] {
+    let record = ArchiveRecord {
+      received_at,
+      seq: Some(seq),
+      frame: frame(seq),
+    };
+    recorder.record(&record).expect("failed to record");
+  }
+  drop(recorder);
+
+  let segments = segments(directory.path());
+  assert_eq!(segments.len(), 2);
+  assert_eq!(read_segment(&segments[0]).len(), 2);
+  assert_eq!(read_segment(&segments[1]).len(), 1);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn record_compressed_segments() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let mut recorder = Recorder::builder()
+    .directory(directory.path())
+    .compress(true)
+    .build()
+    .expect("failed to build recorder");
+  recorder.record_frame(frame(1)).expect("failed to record");
+  recorder.finish().expect("failed to finish");
+
+  let segments = segments(directory.path());
+  assert_eq!(segments.len(), 1);
+  assert!(segments[0].to_string_lossy().ends_with(ZSTD_EXTENSION));
+  assert_eq!(read_segment(&segments[0])[0].frame, frame(1));
+}
+
+#[tokio::test]
+async fn tee_connection() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let recorder = Recorder::builder()
+    .directory(directory.path())
+    .build()
+    .expect("failed to build recorder");
+  let connection = futures::stream::iter([
+    Ok(Message::Binary(frame(1).to_vec())),
+    Ok(Message::Ping(Vec::new())),
+    Ok(Message::Binary(frame(2).to_vec())),
+  ]);
+  let messages = recorder.tee(connection).collect::<Vec<_>>().await;
+  assert_eq!(messages.len(), 3);
+  assert!(messages.iter().all(Result::is_ok));
+
+  // The segment was closed when the connection ended.
+  let segments = segments(directory.path());
+  let seqs = read_segment(&segments[0])
+    .into_iter()
+    .map(|record| record.seq)
+    .collect::<Vec<_>>();
+  assert_eq!(seqs, [Some(1), Some(2)]);
+}
+
+/// Records frames with seqs 1 to 4, received one second apart, in segments of 2 frames.
+fn record_archive(directory: &Path) {
+  let mut recorder = Recorder::builder()
+    .directory(directory)
+    .max_segment_age(Duration::from_secs(2))
+    .build()
+    .expect("failed to build recorder");
+  for seq in 1..=4 {
+    let record = ArchiveRecord {
+      received_at: seq * 1_000_000,
+      seq: Some(seq),
+      frame: frame(seq),
+    };
+    recorder.record(&record).expect("failed to record");
+  }
+}
+
+#[tokio::test]
+async fn replay_from_seq() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  record_archive(directory.path());
+  assert_eq!(segments(directory.path()).len(), 2);
+
+  let replay = Replay::builder()
+    .path(directory.path())
+    .from_seq(2)
+    .build()
+    .expect("failed to build replay");
+  let messages = replay.into_stream().collect::<Vec<_>>().await;
+  let frames = messages
+    .into_iter()
+    .map(|m| match m {
+      Ok(Message::Binary(data)) => data,
+      _ => panic!("expected a binary message"),
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(frames, [frame(2), frame(3), frame(4)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn replay_with_speed() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  record_archive(directory.path());
+
+  let replay = Replay::builder()
+    .path(directory.path())
+    .speed(2.0)
+    .build()
+    .expect("failed to build replay");
+  let start = Instant::now();
+  let count = replay.into_stream().count().await;
+  assert_eq!(count, 4);
+  // The 3 seconds between the first and last frames are replayed twice as fast.
+  assert_eq!(start.elapsed().as_millis(), 1500);
+}
+
+#[test]
+fn reject_invalid_speed() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+    let result = Replay::builder()
+      .path(directory.path())
+      .speed(speed)
+      .build();
+    assert!(
+      matches!(&result, Err(e) if e.kind() == io::ErrorKind::InvalidInput),
+      "{speed}"
+    );
+  }
+}
@@ -48,7 +48,7 @@ impl<P: Serialize + Send + Sync> WssClient<<StreamKind as Stream>::Item, Error>
     if let Some(p) = &params {
       uri.push('?');
       uri += &serde_html_form::to_string(p)?;
-    };
+    }
     ////
 
     //// Request
@@ -0,0 +1,270 @@
+//! This file provides a kit to build feed generators on top of the [`Firehose`].
+//!
+//! A feed is made of a [`FeedFilter`], deciding whether a newly created post belongs in it, and a
+//! [`FeedStore`] of the URIs of the matching posts. The [`FeedGenerator`] evaluates the filters of
+//! its feeds on the `app.bsky.feed.post` creations of the firehose, and the [`FeedServer`] serves
+//! the stores with the `app.bsky.feed.getFeedSkeleton` and `app.bsky.feed.describeFeedGenerator`
+//! XRPC methods.
+//!
+//! ```no_run
+//! # use atrium_api::com::atproto::sync::subscribe_repos;
+//! # use firehose_client::{
+//! #   atrium_xrpc_wss::{
+//! #     client::{WssClient, XrpcUri},
+//! #     subscriptions::repositories::Repositories,
+//! #   },
+//! #   atrium_xrpc_wss_client::{
+//! #     feed_generator::{Feed, FeedGenerator, FeedServer, Post},
+//! #     subscriptions::repositories::firehose::Firehose,
+//! #     XrpcWssClient,
+//! #   },
+//! # };
+//! # async fn f() -> Result<(), Box<dyn std::error::Error>> {
+//! let generator = FeedGenerator::new(vec![Feed::builder()
+//!   .rkey("rust")
+//!   .filter(|post: &Post<'_>| post.record.text.contains("#rustlang"))
+//!   .build()]);
+//! let _server = FeedServer::builder()
+//!   .addr("0.0.0.0:3000".parse()?)
+//!   .service_did("did:web:feeds.example.com".parse()?)
+//!   .publisher_did("did:plc:ewvi7nxzyoun6zhxrhs64oiz".parse()?)
+//!   .generator(generator.clone())
+//!   .bind()
+//!   .await?;
+//!
+//! let client = XrpcWssClient::builder()
+//!   .xrpc_uri(XrpcUri::new("bsky.network", subscribe_repos::NSID))
+//!   .params(subscribe_repos::ParametersData { cursor: None })
+//!   .build();
+//! let connection = client.connect().await?;
+//! let subscription = Repositories::builder()
+//!   .connection(connection)
+//!   .handler(Firehose::default())
+//!   .build();
+//! generator.consume(std::pin::pin!(subscription)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(test)]
+mod tests;
+
+mod server;
+mod store;
+pub use server::FeedServer;
+pub use store::{Cursor, FeedStore, InvalidCursor};
+
+use std::{fmt, sync::Arc};
+
+use atrium_api::{
+  app::bsky::feed::post,
+  record::KnownRecord,
+  types::{
+    string::{Datetime, Did},
+    CidLink,
+  },
+};
+use bon::bon;
+use futures::{Stream, StreamExt};
+
+use crate::{
+  atrium_xrpc_wss::subscriptions::{
+    repositories::{HandledData, ProcessedData},
+    ProcessedPayload, SubscriptionError,
+  },
+  atrium_xrpc_wss_client::subscriptions::repositories::{
+    firehose::Firehose,
+    type_defs::{ProcessedAccountData, ProcessedCommitData},
+  },
+};
+
+type Payload = ProcessedPayload<HandledData<Firehose>>;
+
+/// The collection of the posts.
+const POST: &str = "app.bsky.feed.post";
+
+/// A newly created post, as given to the [`FeedFilter`]s.
+#[derive(Debug, Clone, Copy)]
+pub struct Post<'a> {
+  /// The AT-URI of the post.
+  pub uri: &'a str,
+  /// The DID of the author.
+  pub did: &'a Did,
+  pub rkey: &'a str,
+  pub cid: &'a CidLink,
+  pub record: &'a post::Record,
+  /// The time of the commit creating the post.
+  pub time: &'a Datetime,
+}
+
+/// Decides whether a post belongs in a feed.
+///
+/// It's implemented for closures taking a [`Post`], for the filters that don't hold any state.
+pub trait FeedFilter: Send + Sync {
+  /// Returns `true` if the `post` belongs in the feed.
+  fn matches(&self, post: &Post<'_>) -> bool;
+}
+
+impl<F> FeedFilter for F
+where
+  F: Fn(&Post<'_>) -> bool + Send + Sync,
+{
+  fn matches(&self, post: &Post<'_>) -> bool {
+    self(post)
+  }
+}
+
+/// A feed of a [`FeedGenerator`], published at `at://{publisher}/app.bsky.feed.generator/{rkey}`.
+///
+/// Cloning a feed is cheap, and the clones share the same store.
+#[derive(Clone)]
+pub struct Feed {
+  rkey: String,
+  filter: Arc<dyn FeedFilter>,
+  store: FeedStore,
+}
+
+#[bon]
+impl Feed {
+  /// Builds a new feed of the posts matching the `filter`, kept in the `store`
+  /// (defaults to an empty [`FeedStore`] with the default capacity).
+  #[builder]
+  pub fn new(
+    #[builder(into)] rkey: String,
+    filter: impl FeedFilter + 'static,
+    #[builder(default)] store: FeedStore,
+  ) -> Self {
+    Self {
+      rkey,
+      filter: Arc::new(filter),
+      store,
+    }
+  }
+}
+
+impl Feed {
+  /// The record key of the `app.bsky.feed.generator` record of the feed.
+  #[must_use]
+  pub fn rkey(&self) -> &str {
+    &self.rkey
+  }
+
+  /// The store of the posts of the feed.
+  #[must_use]
+  pub const fn store(&self) -> &FeedStore {
+    &self.store
+  }
+}
+
+impl fmt::Debug for Feed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Feed")
+      .field("rkey", &self.rkey)
+      .field("store", &self.store)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Maintains the stores of its feeds from the payloads processed by the [`Firehose`].
+///
+/// Every created post is added to the store of each feed whose filter it matches, ordered by the
+/// time of its commit. Deleted posts are removed from all the stores, as well as every post of a
+/// deleted account, but not of an account that is only deactivated, suspended or taken down.
+/// Updates are ignored, since posts can't be edited.
+///
+/// Cloning a generator is cheap, and the clones share the same feeds, so one can be given to a
+/// [`FeedServer`] while another consumes the subscription.
+#[derive(Debug, Clone)]
+pub struct FeedGenerator {
+  feeds: Arc<[Feed]>,
+}
+
+impl FeedGenerator {
+  /// Creates a new generator of the `feeds`.
+  #[must_use]
+  pub fn new(feeds: Vec<Feed>) -> Self {
+    Self {
+      feeds: feeds.into(),
+    }
+  }
+
+  /// The feeds of the generator.
+  #[must_use]
+  pub fn feeds(&self) -> &[Feed] {
+    &self.feeds
+  }
+
+  /// The feed with the record key `rkey`, if any.
+  #[must_use]
+  pub fn feed(&self, rkey: &str) -> Option<&Feed> {
+    self.feeds.iter().find(|feed| feed.rkey == rkey)
+  }
+
+  /// Updates the stores of the feeds with a payload.
+  pub fn index(&self, payload: &Payload) {
+    match &payload.data {
+      ProcessedData::Commit(commit) => self.index_commit(commit),
+      ProcessedData::Account(account) => self.index_account(account),
+      _ => {}
+    }
+  }
+
+  /// Indexes every payload of a subscription, until it ends or yields an error.
+  ///
+  /// # Errors
+  /// Returns the error yielded by the subscription.
+  #[expect(
+    tail_expr_drop_order,
+    reason = "Each payload is indexed before the next one is received, whatever the order they are freed in."
+  )]
+  pub async fn consume<E>(
+    &self,
+    mut subscription: impl Stream<Item = Result<Payload, SubscriptionError<E>>> + Unpin,
+  ) -> Result<(), SubscriptionError<E>> {
+    while let Some(payload) = subscription.next().await {
+      self.index(&payload?);
+    }
+    Ok(())
+  }
+
+  fn index_commit(&self, commit: &ProcessedCommitData) {
+    let time = commit.time.as_ref().timestamp_micros();
+    for op in commit.ops.iter().flatten() {
+      let Some((POST, rkey)) = op.path.split_once('/') else {
+        continue;
+      };
+      let uri = format!("at://{}/{}", commit.repo.as_str(), op.path);
+      match (op.action.as_str(), &op.record, &op.cid) {
+        ("create", Some(KnownRecord::AppBskyFeedPost(record)), Some(cid)) => {
+          let post = Post {
+            uri: &uri,
+            did: &commit.repo,
+            rkey,
+            cid,
+            record,
+            time: &commit.time,
+          };
+          for feed in self.feeds.iter() {
+            if feed.filter.matches(&post) {
+              feed.store.insert(uri.clone(), time);
+            }
+          }
+        }
+        ("delete", ..) => {
+          for feed in self.feeds.iter() {
+            feed.store.remove(&uri);
+          }
+        }
+        _ => {}
+      }
+    }
+  }
+
+  fn index_account(&self, account: &ProcessedAccountData) {
+    if account.status.as_deref() == Some("deleted") {
+      for feed in self.feeds.iter() {
+        feed.store.remove_repo(&account.did);
+      }
+    }
+  }
+}
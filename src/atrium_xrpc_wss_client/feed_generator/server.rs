@@ -0,0 +1,198 @@
+//! This file provides the [`FeedServer`], which serves the feeds of a [`FeedGenerator`] over XRPC.
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use atrium_api::{
+  app::bsky::feed::{defs::SkeletonFeedPostData, describe_feed_generator, get_feed_skeleton},
+  types::string::Did,
+};
+use axum::{
+  extract::{RawQuery, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  routing::get,
+  Json, Router,
+};
+use bon::bon;
+use serde::Serialize;
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use super::{Cursor, FeedGenerator};
+
+/// The number of posts of a skeleton when the `limit` isn't given, as defined by the lexicon.
+const DEFAULT_LIMIT: u8 = 50;
+
+/// The collection of the records declaring the feeds.
+const GENERATOR: &str = "app.bsky.feed.generator";
+
+/// The state shared by the handlers.
+#[derive(Debug)]
+struct Shared {
+  service_did: Did,
+  publisher_did: Did,
+  generator: FeedGenerator,
+}
+
+impl Shared {
+  fn feed_uri(&self, rkey: &str) -> String {
+    format!("at://{}/{GENERATOR}/{rkey}", self.publisher_did.as_str())
+  }
+}
+
+/// The body of an XRPC error response.
+#[derive(Debug, Serialize)]
+struct XrpcError {
+  error: &'static str,
+  message: String,
+}
+
+impl XrpcError {
+  fn invalid_request(error: &impl ToString) -> Self {
+    Self {
+      error: "InvalidRequest",
+      message: error.to_string(),
+    }
+  }
+}
+
+impl IntoResponse for XrpcError {
+  fn into_response(self) -> Response {
+    (StatusCode::BAD_REQUEST, Json(self)).into_response()
+  }
+}
+
+/// An HTTP server implementing the XRPC methods of a feed generator, for the feeds of a
+/// [`FeedGenerator`]:
+/// - `app.bsky.feed.getFeedSkeleton`: the URIs of the posts of a feed, from the most recent one,
+///   paginated with the `limit` (defaults to 50) and `cursor` parameters.
+/// - `app.bsky.feed.describeFeedGenerator`: the DID of the service, and the URIs of its feeds.
+///
+/// The feeds are identified by the URIs of their `app.bsky.feed.generator` records in the
+/// repository of the `publisher_did`, which should reference the `service_did` of the server.
+/// The requests aren't authenticated, so the skeletons can't depend on the viewer.
+///
+/// The server stops when dropped.
+#[derive(Debug)]
+pub struct FeedServer {
+  local_addr: SocketAddr,
+  task: JoinHandle<()>,
+}
+
+#[bon]
+impl FeedServer {
+  /// Binds the server to the `addr`.
+  ///
+  /// # Errors
+  /// Returns an error if the server could not be bound.
+  #[builder(finish_fn = bind)]
+  pub async fn new(
+    addr: SocketAddr,
+    service_did: Did,
+    publisher_did: Did,
+    generator: FeedGenerator,
+  ) -> io::Result<Self> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let shared = Arc::new(Shared {
+      service_did,
+      publisher_did,
+      generator,
+    });
+    let router = Router::new()
+      .route(
+        &format!("/xrpc/{}", get_feed_skeleton::NSID),
+        get(get_feed_skeleton),
+      )
+      .route(
+        &format!("/xrpc/{}", describe_feed_generator::NSID),
+        get(describe_feed_generator),
+      )
+      .with_state(shared);
+
+    let task = tokio::spawn(async move {
+      axum::serve(listener, router).await.ok();
+    });
+
+    Ok(Self { local_addr, task })
+  }
+}
+
+impl FeedServer {
+  /// The address the server is listening on.
+  #[must_use]
+  pub const fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+}
+
+impl Drop for FeedServer {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
+
+async fn get_feed_skeleton(
+  State(shared): State<Arc<Shared>>,
+  RawQuery(query): RawQuery,
+) -> Result<Json<get_feed_skeleton::Output>, XrpcError> {
+  let params: get_feed_skeleton::ParametersData =
+    serde_html_form::from_str(query.as_deref().unwrap_or_default())
+      .map_err(|e| XrpcError::invalid_request(&e))?;
+  let feed = params
+    .feed
+    .strip_prefix(&shared.feed_uri(""))
+    .and_then(|rkey| shared.generator.feed(rkey))
+    .ok_or_else(|| XrpcError {
+      error: "UnknownFeed",
+      message: format!("Unknown feed: {}", params.feed),
+    })?;
+  let cursor = params
+    .cursor
+    .as_deref()
+    .map(str::parse::<Cursor>)
+    .transpose()
+    .map_err(|e| XrpcError::invalid_request(&e))?;
+  let limit = params.limit.map_or(DEFAULT_LIMIT, u8::from);
+
+  let (uris, cursor) = feed.store().page(cursor.as_ref(), limit.into());
+  Ok(Json(
+    get_feed_skeleton::OutputData {
+      cursor: cursor.map(|cursor| cursor.to_string()),
+      feed: uris
+        .into_iter()
+        .map(|post| {
+          SkeletonFeedPostData {
+            feed_context: None,
+            post,
+            reason: None,
+          }
+          .into()
+        })
+        .collect(),
+    }
+    .into(),
+  ))
+}
+
+async fn describe_feed_generator(
+  State(shared): State<Arc<Shared>>,
+) -> Json<describe_feed_generator::Output> {
+  Json(
+    describe_feed_generator::OutputData {
+      did: shared.service_did.clone(),
+      feeds: shared
+        .generator
+        .feeds()
+        .iter()
+        .map(|feed| {
+          describe_feed_generator::FeedData {
+            uri: shared.feed_uri(feed.rkey()),
+          }
+          .into()
+        })
+        .collect(),
+      links: None,
+    }
+    .into(),
+  )
+}
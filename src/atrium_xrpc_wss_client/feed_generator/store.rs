@@ -0,0 +1,172 @@
+//! This file provides the [`FeedStore`], which keeps the URIs of the posts of a feed in memory.
+
+use std::{
+  collections::{BTreeSet, HashMap},
+  fmt,
+  ops::Bound,
+  str::FromStr,
+  sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use atrium_api::types::string::Did;
+
+/// The number of posts kept by the stores built with [`FeedStore::default`].
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// The position of a post in a feed, which is the time of its commit in microseconds
+/// and its AT-URI to break ties.
+///
+/// It's formatted like `{time}::{uri}` in the `cursor` of the feed skeletons.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cursor {
+  pub time: i64,
+  pub uri: String,
+}
+
+impl fmt::Display for Cursor {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}::{}", self.time, self.uri)
+  }
+}
+
+/// An error while parsing a [`Cursor`].
+#[derive(Debug, thiserror::Error)]
+#[error("Malformed cursor")]
+pub struct InvalidCursor;
+
+impl FromStr for Cursor {
+  type Err = InvalidCursor;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (time, uri) = s.split_once("::").ok_or(InvalidCursor)?;
+    Ok(Self {
+      time: time.parse().map_err(|_| InvalidCursor)?,
+      uri: uri.to_owned(),
+    })
+  }
+}
+
+#[derive(Debug)]
+struct Inner {
+  posts: BTreeSet<Cursor>,
+  /// The time of each post, to find it from its URI.
+  times: HashMap<String, i64>,
+  capacity: usize,
+}
+
+impl Inner {
+  fn insert(&mut self, uri: String, time: i64) {
+    if self.capacity == 0 {
+      return;
+    }
+    if let Some(time) = self.times.insert(uri.clone(), time) {
+      self.posts.remove(&Cursor {
+        time,
+        uri: uri.clone(),
+      });
+    }
+    self.posts.insert(Cursor { time, uri });
+    while self.posts.len() > self.capacity {
+      if let Some(oldest) = self.posts.pop_first() {
+        self.times.remove(&oldest.uri);
+      }
+    }
+  }
+
+  fn remove(&mut self, uri: &str) {
+    if let Some(time) = self.times.remove(uri) {
+      self.posts.remove(&Cursor {
+        time,
+        uri: uri.to_owned(),
+      });
+    }
+  }
+
+  /// Keeps only the posts whose URI matches `f`.
+  fn retain(&mut self, f: impl Fn(&str) -> bool) {
+    self.posts.retain(|post| f(&post.uri));
+    self.times.retain(|uri, _| f(uri));
+  }
+}
+
+/// The URIs of the posts of a feed, from the most to the least recent.
+///
+/// Once `capacity` posts are stored, the oldest one is evicted for each new post.
+/// Cloning a store is cheap, and the clones share the same posts.
+#[derive(Debug, Clone)]
+pub struct FeedStore {
+  inner: Arc<RwLock<Inner>>,
+}
+
+impl Default for FeedStore {
+  fn default() -> Self {
+    Self::new(DEFAULT_CAPACITY)
+  }
+}
+
+impl FeedStore {
+  /// Creates an empty store, keeping up to `capacity` posts.
+  #[must_use]
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      inner: Arc::new(RwLock::new(Inner {
+        posts: BTreeSet::new(),
+        times: HashMap::new(),
+        capacity,
+      })),
+    }
+  }
+
+  fn read(&self) -> RwLockReadGuard<'_, Inner> {
+    self.inner.read().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  fn write(&self) -> RwLockWriteGuard<'_, Inner> {
+    self.inner.write().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// The number of posts in the store.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.read().posts.len()
+  }
+
+  /// Returns `true` if the store has no post.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.read().posts.is_empty()
+  }
+
+  /// Adds a post created at `time` (in microseconds), replacing it if it's already stored.
+  pub fn insert(&self, uri: String, time: i64) {
+    self.write().insert(uri, time);
+  }
+
+  /// Removes a post, if it's stored.
+  pub fn remove(&self, uri: &str) {
+    self.write().remove(uri);
+  }
+
+  /// Removes every post of the repository `did`.
+  pub fn remove_repo(&self, did: &Did) {
+    let prefix = format!("at://{}/", did.as_str());
+    self.write().retain(|uri| !uri.starts_with(&prefix));
+  }
+
+  /// Returns up to `limit` URIs of the posts older than the `cursor`, or the most recent ones
+  /// without it, along with the cursor of the next page if this one is full.
+  #[must_use]
+  pub fn page(&self, cursor: Option<&Cursor>, limit: usize) -> (Vec<String>, Option<Cursor>) {
+    let end = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+    let posts: Vec<Cursor> = self
+      .read()
+      .posts
+      .range((Bound::Unbounded, end))
+      .rev()
+      .take(limit)
+      .cloned()
+      .collect();
+    let next = posts.last().filter(|_| posts.len() == limit).cloned();
+    (posts.into_iter().map(|post| post.uri).collect(), next)
+  }
+}
@@ -0,0 +1,305 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use futures::stream;
+use serde_json::{json, Value};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+};
+
+use super::*;
+use crate::atrium_xrpc_wss_client::subscriptions::repositories::fixtures::{
+  account, commit, create, delete, did, post, until_error,
+};
+
+const PUBLISHER: &str = "did:plc:publisher";
+
+fn generator() -> FeedGenerator {
+  FeedGenerator::new(vec![
+    Feed::builder()
+      .rkey("rust")
+      .filter(|post: &Post<'_>| post.record.text.contains("#rustlang"))
+      .build(),
+    Feed::builder()
+      .rkey("french")
+      .filter(|post: &Post<'_>| {
+        post
+          .record
+          .langs
+          .iter()
+          .flatten()
+          .any(|lang| lang.as_ref() == "fr")
+      })
+      .store(FeedStore::new(2))
+      .build(),
+  ])
+}
+
+fn uris(feed: &Feed) -> Vec<String> {
+  feed.store().page(None, 100).0
+}
+
+#[test]
+fn parse_cursor() {
+  let cursor = Cursor {
+    time: 1_704_067_200_000_000,
+    uri: String::from("at://did:plc:abc/app.bsky.feed.post/1"),
+  };
+  assert_eq!(
+    cursor.to_string(),
+    "1704067200000000::at://did:plc:abc/app.bsky.feed.post/1"
+  );
+  assert_eq!(
+    cursor
+      .to_string()
+      .parse::<Cursor>()
+      .expect("failed to parse"),
+    cursor
+  );
+  assert!("at://did:plc:abc/app.bsky.feed.post/1"
+    .parse::<Cursor>()
+    .is_err());
+  assert!("now::at://did:plc:abc/app.bsky.feed.post/1"
+    .parse::<Cursor>()
+    .is_err());
+}
+
+#[test]
+fn paginate_store() {
+  let store = FeedStore::new(3);
+  store.insert(String::from("at://did:plc:abc/app.bsky.feed.post/1"), 1);
+  store.insert(String::from("at://did:plc:abc/app.bsky.feed.post/2"), 2);
+  // Posts of the same time are ordered by URI.
+  store.insert(String::from("at://did:plc:abc/app.bsky.feed.post/3"), 2);
+  store.insert(String::from("at://did:plc:abc/app.bsky.feed.post/4"), 4);
+  // The oldest post was evicted.
+  assert_eq!(store.len(), 3);
+
+  let (first, cursor) = store.page(None, 2);
+  assert_eq!(
+    first,
+    [
+      "at://did:plc:abc/app.bsky.feed.post/4",
+      "at://did:plc:abc/app.bsky.feed.post/3"
+    ]
+  );
+  let cursor = cursor.expect("missing cursor");
+  let (second, cursor) = store.page(Some(&cursor), 2);
+  assert_eq!(second, ["at://did:plc:abc/app.bsky.feed.post/2"]);
+  assert_eq!(cursor, None);
+
+  store.remove("at://did:plc:abc/app.bsky.feed.post/4");
+  store.remove_repo(&did("did:plc:abc"));
+  assert!(store.is_empty());
+}
+
+#[test]
+fn index_matching_posts() {
+  let generator = generator();
+  for payload in [
+    commit(
+      1,
+      "did:plc:abc",
+      "2024-01-01T00:00:01.000Z",
+      vec![
+        create("app.bsky.feed.post/1", post("Hello #rustlang", &["en"])),
+        create("app.bsky.feed.post/2", post("Bonjour #rustlang", &["fr"])),
+        create("app.bsky.feed.post/3", post("Hello", &["en"])),
+        // Other collections are ignored, even with a post record.
+        create("app.bsky.feed.repost/4", post("#rustlang", &["fr"])),
+      ],
+    ),
+    commit(
+      2,
+      "did:plc:xyz",
+      "2024-01-01T00:00:02.000Z",
+      vec![
+        create("app.bsky.feed.post/5", post("Salut", &["fr"])),
+        create("app.bsky.feed.post/6", post("Coucou", &["fr"])),
+      ],
+    ),
+  ] {
+    generator.index(&payload);
+  }
+
+  let rust = generator.feed("rust").expect("missing feed");
+  assert_eq!(
+    uris(rust),
+    [
+      "at://did:plc:abc/app.bsky.feed.post/2",
+      "at://did:plc:abc/app.bsky.feed.post/1"
+    ]
+  );
+  // Only the last two posts fit in the store.
+  let french = generator.feed("french").expect("missing feed");
+  assert_eq!(
+    uris(french),
+    [
+      "at://did:plc:xyz/app.bsky.feed.post/6",
+      "at://did:plc:xyz/app.bsky.feed.post/5"
+    ]
+  );
+}
+
+#[tokio::test]
+async fn remove_deleted_posts_and_accounts() {
+  let generator = generator();
+  let subscription = stream::iter([
+    Ok(commit(
+      1,
+      "did:plc:abc",
+      "2024-01-01T00:00:01.000Z",
+      vec![
+        create("app.bsky.feed.post/1", post("#rustlang", &[])),
+        create("app.bsky.feed.post/2", post("#rustlang", &[])),
+      ],
+    )),
+    Ok(commit(
+      2,
+      "did:plc:xyz",
+      "2024-01-01T00:00:02.000Z",
+      vec![create("app.bsky.feed.post/1", post("#rustlang", &[]))],
+    )),
+    Ok(commit(
+      3,
+      "did:plc:xyz",
+      "2024-01-01T00:00:03.000Z",
+      vec![delete("app.bsky.feed.post/1")],
+    )),
+    // Deactivated accounts keep their posts.
+    Ok(account(4, "did:plc:abc", "deactivated")),
+  ]);
+  generator
+    .consume::<Infallible>(subscription)
+    .await
+    .expect("failed to consume");
+  let rust = generator.feed("rust").expect("missing feed");
+  assert_eq!(rust.store().len(), 2);
+
+  let res = generator
+    .consume(until_error([account(5, "did:plc:abc", "deleted")]))
+    .await;
+  assert!(matches!(res, Err(SubscriptionError::Server { .. })));
+  assert!(rust.store().is_empty());
+}
+
+/// Sends a `GET` request to the server, returning the status and the JSON body of the response.
+async fn get(addr: SocketAddr, path: &str) -> (u16, Value) {
+  let mut stream = TcpStream::connect(addr).await.expect("failed to connect");
+  stream
+    .write_all(
+      format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").as_bytes(),
+    )
+    .await
+    .expect("failed to send request");
+  let mut response = String::new();
+  stream
+    .read_to_string(&mut response)
+    .await
+    .expect("failed to read response");
+  let (head, body) = response.split_once("\r\n\r\n").expect("malformed response");
+  let status = head
+    .split(' ')
+    .nth(1)
+    .and_then(|status| status.parse().ok())
+    .expect("malformed status");
+  (status, serde_json::from_str(body).expect("malformed body"))
+}
+
+async fn server(generator: FeedGenerator) -> FeedServer {
+  FeedServer::builder()
+    .addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+    .service_did(did("did:web:feeds.example.com"))
+    .publisher_did(did(PUBLISHER))
+    .generator(generator)
+    .bind()
+    .await
+    .expect("failed to bind server")
+}
+
+#[tokio::test]
+async fn describe_feed_generator() {
+  let server = server(generator()).await;
+  let (status, body) = get(
+    server.local_addr(),
+    "/xrpc/app.bsky.feed.describeFeedGenerator",
+  )
+  .await;
+  assert_eq!(status, 200);
+  assert_eq!(
+    body,
+    json!({
+      "did": "did:web:feeds.example.com",
+      "feeds": [
+        { "uri": format!("at://{PUBLISHER}/app.bsky.feed.generator/rust") },
+        { "uri": format!("at://{PUBLISHER}/app.bsky.feed.generator/french") },
+      ],
+    })
+  );
+}
+
+#[tokio::test]
+async fn get_feed_skeleton() {
+  let generator = generator();
+  generator.index(&commit(
+    1,
+    "did:plc:abc",
+    "2024-01-01T00:00:01.000Z",
+    vec![
+      create("app.bsky.feed.post/1", post("#rustlang", &[])),
+      create("app.bsky.feed.post/2", post("#rustlang", &[])),
+      create("app.bsky.feed.post/3", post("#rustlang", &[])),
+    ],
+  ));
+  let server = server(generator).await;
+  let feed = format!("at://{PUBLISHER}/app.bsky.feed.generator/rust");
+
+  let (status, body) = get(
+    server.local_addr(),
+    &format!("/xrpc/app.bsky.feed.getFeedSkeleton?feed={feed}&limit=2"),
+  )
+  .await;
+  assert_eq!(status, 200);
+  assert_eq!(
+    body["feed"],
+    json!([
+      { "post": "at://did:plc:abc/app.bsky.feed.post/3" },
+      { "post": "at://did:plc:abc/app.bsky.feed.post/2" },
+    ])
+  );
+  let cursor = body["cursor"].as_str().expect("missing cursor");
+
+  let (status, body) = get(
+    server.local_addr(),
+    &format!("/xrpc/app.bsky.feed.getFeedSkeleton?feed={feed}&limit=2&cursor={cursor}"),
+  )
+  .await;
+  assert_eq!(status, 200);
+  assert_eq!(
+    body,
+    json!({ "feed": [{ "post": "at://did:plc:abc/app.bsky.feed.post/1" }] })
+  );
+}
+
+#[tokio::test]
+async fn reject_invalid_requests() {
+  let server = server(generator()).await;
+  let feed = format!("at://{PUBLISHER}/app.bsky.feed.generator/rust");
+  for (query, error) in [
+    (
+      format!("feed=at://{PUBLISHER}/app.bsky.feed.generator/unknown"),
+      "UnknownFeed",
+    ),
+    (String::from("limit=10"), "InvalidRequest"),
+    (format!("feed={feed}&limit=101"), "InvalidRequest"),
+    (format!("feed={feed}&cursor=now"), "InvalidRequest"),
+  ] {
+    let (status, body) = get(
+      server.local_addr(),
+      &format!("/xrpc/app.bsky.feed.getFeedSkeleton?{query}"),
+    )
+    .await;
+    assert_eq!(status, 400, "{query}");
+    assert_eq!(body["error"], error, "{query}");
+  }
+}
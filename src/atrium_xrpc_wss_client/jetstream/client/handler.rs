@@ -0,0 +1,154 @@
+use atrium_api::types::{
+  string::{Datetime, Did},
+  CidLink,
+};
+use bytes::Bytes;
+use ipld_core::cid::Cid;
+
+use crate::{
+  atrium_xrpc_wss::subscriptions::{
+    repositories::{HandledData, ProcessedData},
+    ConnectionHandler, ProcessedPayload,
+  },
+  atrium_xrpc_wss_client::{
+    jetstream::{AccountEvent, CommitEvent, Event, EventKind, IdentityEvent},
+    subscriptions::repositories::{
+      filter::Filter,
+      firehose::Firehose,
+      type_defs::{Operation, ProcessedAccountData, ProcessedCommitData, ProcessedIdentityData},
+    },
+  },
+};
+
+/// An error while converting a Jetstream event.
+#[derive(Debug, thiserror::Error)]
+pub enum HandlingError {
+  #[error("Invalid event: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("Invalid CID: {0}")]
+  Cid(#[from] ipld_core::cid::Error),
+  #[error("Invalid time: {0}")]
+  Time(i64),
+}
+
+/// A [`ConnectionHandler`] that converts Jetstream events into the data processed by the [`Firehose`].
+///
+/// Each commit event carries a single operation, and since Jetstream doesn't send the commit CID,
+/// the blobs nor the commit time, they are left empty, using the time of the event instead.
+/// The `seq` of the payloads is the `time_us` of the events, which is the cursor of Jetstream.
+///
+/// Events rejected by its [`Filter`] are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct JetstreamHandler {
+  filter: Filter,
+}
+
+impl JetstreamHandler {
+  /// Creates a handler that only processes what the `filter` lets through.
+  #[must_use]
+  pub const fn new(filter: Filter) -> Self {
+    Self { filter }
+  }
+
+  fn process_commit(
+    &self,
+    repo: Did,
+    time_us: i64,
+    commit: CommitEvent,
+  ) -> Result<Option<ProcessedCommitData>, HandlingError> {
+    let CommitEvent {
+      rev,
+      operation,
+      collection,
+      rkey,
+      record,
+      cid,
+    } = commit;
+
+    let path = format!("{collection}/{rkey}");
+    let ops = if self.filter.matches_op(&operation, &path) {
+      vec![Operation {
+        action: operation,
+        path,
+        cid: cid
+          .map(|cid| Cid::try_from(cid.as_str()).map(CidLink))
+          .transpose()?,
+        record: record.map(serde_json::from_value).transpose()?,
+      }]
+    } else if self.filter.drop_empty_commits() {
+      return Ok(None);
+    } else {
+      Vec::new()
+    };
+
+    Ok(Some(ProcessedCommitData {
+      repo,
+      commit: None,
+      ops: Some(ops),
+      blobs: Vec::new(),
+      rev,
+      since: None,
+      time: datetime(time_us)?,
+    }))
+  }
+}
+
+impl ConnectionHandler for JetstreamHandler {
+  type HandledData = HandledData<Firehose>;
+  type HandlingError = HandlingError;
+
+  async fn handle_payload(
+    &self,
+    t: &str,
+    payload: Bytes,
+  ) -> Result<Option<ProcessedPayload<Self::HandledData>>, Self::HandlingError> {
+    if !matches!(t, "commit" | "identity" | "account") {
+      // Unknown kinds are ignored, like unknown frames of the firehose.
+      return Ok(None);
+    }
+
+    let Event { did, time_us, kind } = serde_json::from_slice(&payload)?;
+    if !self.filter.matches_repo(&did) {
+      return Ok(None);
+    }
+
+    let data = match kind {
+      EventKind::Commit { commit } => match self.process_commit(did, time_us, commit)? {
+        Some(commit) => ProcessedData::Commit(commit),
+        None => return Ok(None),
+      },
+      EventKind::Identity {
+        identity: IdentityEvent {
+          did, handle, time, ..
+        },
+      } => ProcessedData::Identity(ProcessedIdentityData { did, handle, time }),
+      EventKind::Account {
+        account:
+          AccountEvent {
+            active,
+            did,
+            time,
+            status,
+            ..
+          },
+      } => ProcessedData::Account(ProcessedAccountData {
+        did,
+        active,
+        status,
+        time,
+      }),
+    };
+
+    Ok(Some(ProcessedPayload {
+      seq: Some(time_us),
+      data,
+    }))
+  }
+}
+
+/// Converts a time in microseconds since the Unix epoch.
+fn datetime(time_us: i64) -> Result<Datetime, HandlingError> {
+  chrono::DateTime::from_timestamp_micros(time_us)
+    .map(|time| Datetime::new(time.fixed_offset()))
+    .ok_or(HandlingError::Time(time_us))
+}
@@ -0,0 +1,213 @@
+//! This file provides the [`Jetstream`] subscription, which consumes a Jetstream endpoint.
+//!
+//! Jetstream sends each event as a JSON text message, or as a zstd compressed binary message when
+//! requested with `compress=true`. The [`JetstreamHandler`] turns them into the same [`ProcessedData`]
+//! types produced by the [`Firehose`] handler, so consumers can switch between both sources:
+//! ```no_run
+//! # use firehose_client::atrium_xrpc_wss_client::jetstream::client::{Jetstream, JetstreamHandler};
+//! # async fn f() -> Result<(), Box<dyn std::error::Error>> {
+//! let (connection, _) =
+//!   tokio_tungstenite::connect_async("wss://jetstream2.us-east.bsky.network/subscribe").await?;
+//! let subscription = Jetstream::builder()
+//!   .connection(connection)
+//!   .handler(JetstreamHandler::default())
+//!   .build();
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`ProcessedData`]: crate::atrium_xrpc_wss::subscriptions::repositories::ProcessedData
+//! [`Firehose`]: crate::atrium_xrpc_wss_client::subscriptions::repositories::firehose::Firehose
+
+#[cfg(test)]
+mod tests;
+
+mod handler;
+pub use handler::{HandlingError, JetstreamHandler};
+
+use std::{error::Error as StdError, marker::PhantomData};
+
+use async_stream::stream;
+use bon::bon;
+use bytes::Bytes;
+use futures::{future, Stream, StreamExt};
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::atrium_xrpc_wss::subscriptions::{
+  ConnectionHandler, ProcessedPayload, Retryable, Subscription, SubscriptionError,
+};
+
+type WssResult = tungstenite::Result<Message>;
+
+/// A struct that represents the Jetstream subscription.
+pub struct Jetstream<ConnectionPayload> {
+  /// This is only here to constrain the `ConnectionPayload` used in [`Subscription`], or else we get a compile error.
+  _payload_kind: PhantomData<ConnectionPayload>,
+}
+
+/// An error while reading the messages of a Jetstream subscription.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error("Invalid JSON message: {0}")]
+  Json(#[from] serde_json::Error),
+  #[cfg(feature = "zstd")]
+  #[error("Decompression error: {0}")]
+  Decompression(#[source] std::io::Error),
+}
+
+impl Retryable for Error {
+  /// None of these are retryable, since the same message would be received again.
+  fn is_retryable(&self) -> bool {
+    false
+  }
+}
+
+/// The fields read from every message, to know its type before handling it.
+#[derive(Deserialize)]
+struct Header {
+  kind: String,
+}
+
+/// The transport-agnostic implementation of the subscription, over any stream of JSON messages.
+///
+/// The `t` given to the handler is the `kind` of the event, like `commit`.
+impl<E> Subscription<Result<Bytes, E>, Error> for Jetstream<Result<Bytes, E>>
+where
+  E: 'static + Send + Sync + StdError,
+{
+  fn handle_connection<H: ConnectionHandler + Sync>(
+    connection: impl Stream<Item = Result<Bytes, E>> + Unpin,
+    handler: H,
+  ) -> impl Stream<Item = Result<ProcessedPayload<H::HandledData>, SubscriptionError<Error>>> {
+    handle_messages(connection, handler, Ok)
+  }
+}
+
+/// Adapts the `tungstenite` connection into a stream of text messages,
+/// and delegates to the transport-agnostic implementation.
+impl Subscription<WssResult, Error> for Jetstream<WssResult> {
+  fn handle_connection<H: ConnectionHandler + Sync>(
+    connection: impl Stream<Item = WssResult> + Unpin,
+    handler: H,
+  ) -> impl Stream<Item = Result<ProcessedPayload<H::HandledData>, SubscriptionError<Error>>> {
+    Jetstream::handle_connection(text_messages(connection), handler)
+  }
+}
+
+/// Adapts the `tungstenite` connection into a stream of text messages, ignoring other message types.
+pub fn text_messages(
+  connection: impl Stream<Item = WssResult> + Unpin,
+) -> impl Stream<Item = tungstenite::Result<Bytes>> + Unpin {
+  connection.filter_map(|message| {
+    future::ready(match message {
+      Ok(Message::Text(text)) => Some(Ok(Bytes::from(text))),
+      Ok(_) => None, // Ignore other message types.
+      Err(e) => Some(Err(e)),
+    })
+  })
+}
+
+/// Defines the builder for any generic `Jetstream` struct that implements [`Subscription`].
+#[bon]
+impl<ConnectionPayload> Jetstream<ConnectionPayload>
+where
+  Self: Subscription<ConnectionPayload, Error>,
+{
+  #[builder]
+  pub fn new<H: ConnectionHandler + Sync>(
+    connection: impl Stream<Item = ConnectionPayload> + Unpin,
+    handler: H,
+  ) -> impl Stream<Item = Result<ProcessedPayload<H::HandledData>, SubscriptionError<Error>>> {
+    Self::handle_connection(connection, handler)
+  }
+}
+
+#[cfg(feature = "zstd")]
+#[bon]
+impl<E> Jetstream<Result<Bytes, E>>
+where
+  E: 'static + Send + Sync + StdError,
+{
+  /// Like [`Jetstream::builder`], starting with `Jetstream::compressed()`, but each message is
+  /// decompressed with the zstd `dictionary` used by Jetstream first.
+  ///
+  /// Compressed messages are sent as binary messages, which can be read from a `tungstenite`
+  /// connection with [`binary_frames`](crate::atrium_xrpc_wss_client::subscriptions::repositories::binary_frames).
+  #[builder(finish_fn = build)]
+  #[expect(
+    clippy::needless_pass_by_value,
+    reason = "Borrowing the dictionary would tie the returned stream to its lifetime."
+  )]
+  pub fn compressed<H: ConnectionHandler + Sync>(
+    connection: impl Stream<Item = Result<Bytes, E>> + Unpin,
+    #[builder(into)] dictionary: Vec<u8>,
+    handler: H,
+  ) -> impl Stream<Item = Result<ProcessedPayload<H::HandledData>, SubscriptionError<Error>>> {
+    let dictionary = zstd::dict::DecoderDictionary::copy(&dictionary);
+    handle_messages(connection, handler, move |data| {
+      decompress(&dictionary, &data)
+    })
+  }
+}
+
+/// Handles each message, after `decode` turns it into JSON.
+fn handle_messages<E, H>(
+  mut connection: impl Stream<Item = Result<Bytes, E>> + Unpin,
+  handler: H,
+  decode: impl Fn(Bytes) -> Result<Bytes, Error>,
+) -> impl Stream<Item = Result<ProcessedPayload<H::HandledData>, SubscriptionError<Error>>>
+where
+  E: 'static + Send + Sync + StdError,
+  H: ConnectionHandler + Sync,
+{
+  let stream = stream! {
+    loop {
+      let data = match connection.next().await {
+        None => break, // Server dropped connection
+        Some(Err(e)) => { // Transport error
+          yield Err(SubscriptionError::Transport(Box::new(e)));
+          break;
+        }
+        Some(Ok(data)) => data,
+      };
+
+      let (kind, json) = match decode(data).and_then(read_message) {
+        Ok(message) => message,
+        Err(e) => {
+          yield Err(SubscriptionError::Other(e));
+          break;
+        }
+      };
+
+      match handler.handle_payload(&kind, json).await {
+        Ok(Some(res)) => yield Ok(res), // Payload was successfully handled.
+        Ok(None) => {}, // Payload was ignored by Handler.
+        Err(e) => {
+          yield Err(SubscriptionError::Handler(Box::new(e)));
+          break;
+        },
+      }
+    }
+  };
+
+  Box::pin(stream)
+}
+
+/// Reads the `kind` of a JSON message.
+fn read_message(json: Bytes) -> Result<(String, Bytes), Error> {
+  let Header { kind } = serde_json::from_slice(&json)?;
+  Ok((kind, json))
+}
+
+/// Decompresses a message, which is a single zstd frame.
+#[cfg(feature = "zstd")]
+fn decompress(dictionary: &zstd::dict::DecoderDictionary<'_>, data: &[u8]) -> Result<Bytes, Error> {
+  use std::io::Read;
+
+  let mut json = Vec::new();
+  zstd::stream::read::Decoder::with_prepared_dictionary(data, dictionary)
+    .and_then(|mut decoder| decoder.read_to_end(&mut json))
+    .map_err(Error::Decompression)?;
+  Ok(Bytes::from(json))
+}
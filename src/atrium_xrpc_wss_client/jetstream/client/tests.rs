@@ -0,0 +1,238 @@
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use atrium_api::record::KnownRecord;
+use serde_json::json;
+
+use super::*;
+use crate::{
+  atrium_xrpc_wss::subscriptions::repositories::{HandledData, ProcessedData},
+  atrium_xrpc_wss_client::{
+    jetstream::server::JetstreamServer,
+    subscriptions::repositories::{filter::Filter, firehose::Firehose},
+  },
+};
+
+type Item = Result<ProcessedPayload<HandledData<Firehose>>, SubscriptionError<Error>>;
+
+fn messages() -> Vec<serde_json::Value> {
+  vec![
+    json!({
+      "did": "did:plc:abc",
+      "time_us": 1,
+      "kind": "commit",
+      "commit": {
+        "rev": "3m",
+        "operation": "create",
+        "collection": "app.bsky.feed.post",
+        "rkey": "3k",
+        "record": {
+          "$type": "app.bsky.feed.post",
+          "createdAt": "2024-01-01T00:00:00.000Z",
+          "text": "Hello",
+        },
+        "cid": "bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a",
+      },
+    }),
+    json!({
+      "did": "did:plc:abc",
+      "time_us": 2,
+      "kind": "commit",
+      "commit": {
+        "rev": "3n",
+        "operation": "delete",
+        "collection": "app.bsky.feed.like",
+        "rkey": "3l",
+      },
+    }),
+    // Unknown kinds are ignored.
+    json!({ "did": "did:plc:abc", "time_us": 3, "kind": "unknown" }),
+    json!({
+      "did": "did:plc:xyz",
+      "time_us": 4,
+      "kind": "identity",
+      "identity": {
+        "did": "did:plc:xyz",
+        "handle": "xyz.bsky.social",
+        "seq": 10,
+        "time": "2024-01-01T00:00:00.000Z",
+      },
+    }),
+    json!({
+      "did": "did:plc:xyz",
+      "time_us": 5,
+      "kind": "account",
+      "account": {
+        "active": false,
+        "did": "did:plc:xyz",
+        "seq": 11,
+        "time": "2024-01-01T00:00:00.000Z",
+        "status": "takendown",
+      },
+    }),
+  ]
+}
+
+fn connection(
+  messages: impl IntoIterator<Item = Vec<u8>>,
+) -> impl Stream<Item = Result<Bytes, Infallible>> + Unpin {
+  futures::stream::iter(messages.into_iter().map(|message| Ok(Bytes::from(message))))
+}
+
+fn json_connection() -> impl Stream<Item = Result<Bytes, Infallible>> + Unpin {
+  connection(
+    messages()
+      .into_iter()
+      .map(|message| serde_json::to_vec(&message).expect("failed to serialize")),
+  )
+}
+
+fn seqs(items: &[Item]) -> Vec<i64> {
+  items
+    .iter()
+    .map(|item| {
+      item
+        .as_ref()
+        .expect("failed to handle")
+        .seq
+        .expect("missing seq")
+    })
+    .collect()
+}
+
+#[tokio::test]
+async fn handle_json_messages() {
+  let items = Jetstream::builder()
+    .connection(json_connection())
+    .handler(JetstreamHandler::default())
+    .build()
+    .collect::<Vec<_>>()
+    .await;
+  assert_eq!(seqs(&items), [1, 2, 4, 5]);
+
+  let Some(Ok(ProcessedPayload {
+    data: ProcessedData::Commit(commit),
+    ..
+  })) = items.first()
+  else {
+    panic!("expected a commit");
+  };
+  assert_eq!(commit.repo.as_str(), "did:plc:abc");
+  assert!(commit.commit.is_none());
+  assert_eq!(commit.rev, "3m");
+  assert_eq!(commit.time.as_ref().timestamp_micros(), 1);
+  let ops = commit.ops.as_deref().expect("missing ops");
+  assert_eq!(ops.len(), 1);
+  assert_eq!(ops[0].action, "create");
+  assert_eq!(ops[0].path, "app.bsky.feed.post/3k");
+  assert!(ops[0].cid.is_some());
+  assert!(matches!(
+    &ops[0].record,
+    Some(KnownRecord::AppBskyFeedPost(post)) if post.text == "Hello"
+  ));
+
+  assert!(matches!(
+    &items[2],
+    Ok(ProcessedPayload {
+      data: ProcessedData::Identity(identity),
+      ..
+    }) if identity.handle.as_ref().map(AsRef::as_ref) == Some("xyz.bsky.social")
+  ));
+  assert!(matches!(
+    &items[3],
+    Ok(ProcessedPayload {
+      data: ProcessedData::Account(account),
+      ..
+    }) if !account.active && account.status.as_deref() == Some("takendown")
+  ));
+}
+
+#[tokio::test]
+async fn skip_filtered_events() {
+  let filter = Filter::builder()
+    .collections(vec![String::from("app.bsky.feed.post")])
+    .denied_dids(["did:plc:xyz".parse().expect("invalid did")].into())
+    .drop_empty_commits(true)
+    .build();
+  let items = Jetstream::builder()
+    .connection(json_connection())
+    .handler(JetstreamHandler::new(filter))
+    .build()
+    .collect::<Vec<_>>()
+    .await;
+  assert_eq!(seqs(&items), [1]);
+}
+
+#[tokio::test]
+async fn end_on_invalid_message() {
+  let items = Jetstream::builder()
+    .connection(connection([b"not json".to_vec(), b"{}".to_vec()]))
+    .handler(JetstreamHandler::default())
+    .build()
+    .collect::<Vec<_>>()
+    .await;
+  assert!(matches!(
+    &items[..],
+    [Err(SubscriptionError::Other(Error::Json(_)))]
+  ));
+}
+
+#[cfg(feature = "zstd")]
+#[tokio::test]
+async fn decompress_messages() {
+  let dictionary =
+    br#"{"did":"did:plc:abc","time_us":,"kind":"commit","commit":{"rev":""#.repeat(8);
+  let mut compressor =
+    zstd::bulk::Compressor::with_dictionary(3, &dictionary).expect("failed to load dictionary");
+  let compressed = messages()
+    .iter()
+    .map(|message| {
+      let json = serde_json::to_vec(message).expect("failed to serialize");
+      compressor.compress(&json).expect("failed to compress")
+    })
+    .chain([b"not zstd".to_vec()])
+    .collect::<Vec<_>>();
+
+  let items = Jetstream::compressed()
+    .connection(connection(compressed))
+    .dictionary(dictionary.as_slice())
+    .handler(JetstreamHandler::default())
+    .build()
+    .collect::<Vec<_>>()
+    .await;
+  assert_eq!(seqs(&items[..4]), [1, 2, 4, 5]);
+  assert!(matches!(
+    &items[4..],
+    [Err(SubscriptionError::Other(Error::Decompression(_)))]
+  ));
+}
+
+#[tokio::test]
+async fn consume_jetstream_server() {
+  let server = JetstreamServer::builder()
+    .addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+    .bind()
+    .await
+    .expect("failed to bind");
+  for message in messages() {
+    if message["kind"] != "unknown" {
+      let event = serde_json::from_value(message).expect("failed to deserialize");
+      server.publish(event).expect("failed to publish");
+    }
+  }
+
+  let url = format!("ws://{}/subscribe?cursor=0", server.local_addr());
+  let (connection, _) = tokio_tungstenite::connect_async(url)
+    .await
+    .expect("failed to connect");
+  let subscription = Jetstream::builder()
+    .connection(connection)
+    .handler(JetstreamHandler::default())
+    .build();
+  let items = tokio::time::timeout(
+    Duration::from_secs(5),
+    subscription.take(4).collect::<Vec<_>>(),
+  )
+  .await
+  .expect("timed out");
+  assert_eq!(seqs(&items), [1, 2, 4, 5]);
+}
@@ -0,0 +1,196 @@
+//! This file defines the events of the [Jetstream](https://github.com/bluesky-social/jetstream) JSON format,
+//! and the conversion from the payloads processed by the [`Firehose`] handler.
+//!
+//! Jetstream sends one event per operation of a commit, with the records converted from DAG-CBOR to JSON
+//! in the [`Dialect::Atproto`] dialect. The `time_us` of the events is given by a [`Clock`], when they
+//! are received, since the `time` of the payloads can repeat or go backwards.
+//!
+//! The [`client`] consumes a Jetstream endpoint, and the [`server`] re-broadcasts events to local clients.
+
+#[cfg(test)]
+mod tests;
+
+pub mod client;
+pub mod server;
+
+use atrium_api::types::string::{Datetime, Did, Handle};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+  atrium_xrpc_wss::subscriptions::{
+    repositories::{HandledData, ProcessedData},
+    ProcessedPayload,
+  },
+  atrium_xrpc_wss_client::{
+    json::{self, record_to_json, Dialect},
+    subscriptions::repositories::{
+      firehose::Firehose,
+      type_defs::{Operation, ProcessedAccountData, ProcessedCommitData, ProcessedIdentityData},
+    },
+  },
+};
+
+/// An error while converting a record to JSON.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error("Record conversion error: {0}")]
+  Record(#[from] json::Error),
+}
+
+/// A Jetstream event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+  pub did: Did,
+  /// The time the event was received, in microseconds since the Unix epoch, which is also the
+  /// cursor of Jetstream. It increases with every event, see [`Clock`].
+  pub time_us: i64,
+  #[serde(flatten)]
+  pub kind: EventKind,
+}
+
+/// The kind of a Jetstream [`Event`], along with its data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum EventKind {
+  Commit { commit: CommitEvent },
+  Identity { identity: IdentityEvent },
+  Account { account: AccountEvent },
+}
+
+/// A single operation of a commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitEvent {
+  pub rev: String,
+  /// `create`, `update` or `delete`.
+  pub operation: String,
+  pub collection: String,
+  pub rkey: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub record: Option<Value>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub cid: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityEvent {
+  pub did: Did,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub handle: Option<Handle>,
+  pub seq: i64,
+  pub time: Datetime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountEvent {
+  pub active: bool,
+  pub did: Did,
+  pub seq: i64,
+  pub time: Datetime,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub status: Option<String>,
+}
+
+/// The clock giving the `time_us` of the events, as they are received.
+///
+/// Its times are unique and never go backwards, so that they can be used as cursors: they are the
+/// current time, unless it's not after the previous one, in which case they follow it.
+#[derive(Debug, Default)]
+pub struct Clock {
+  last: i64,
+}
+
+impl Clock {
+  /// Returns the time of an event received now, in microseconds since the Unix epoch.
+  pub fn tick(&mut self) -> i64 {
+    self.last = Utc::now()
+      .timestamp_micros()
+      .max(self.last.saturating_add(1));
+    self.last
+  }
+}
+
+impl Event {
+  /// Converts a payload processed by the [`Firehose`] into Jetstream events.
+  ///
+  /// A commit becomes one event per operation, while the payloads that Jetstream doesn't send
+  /// (like `#info`) become none. The `time_us` of each event is a tick of the `clock`.
+  ///
+  /// # Errors
+  /// Returns an error if a record can't be converted to JSON.
+  pub fn from_payload(
+    payload: ProcessedPayload<HandledData<Firehose>>,
+    clock: &mut Clock,
+  ) -> Result<Vec<Self>, Error> {
+    let ProcessedPayload { seq, data } = payload;
+    let seq = seq.unwrap_or_default();
+    match data {
+      ProcessedData::Commit(commit) => from_commit(commit, clock),
+      ProcessedData::Identity(ProcessedIdentityData { did, handle, time }) => Ok(vec![Self {
+        did: did.clone(),
+        time_us: clock.tick(),
+        kind: EventKind::Identity {
+          identity: IdentityEvent {
+            did,
+            handle,
+            seq,
+            time,
+          },
+        },
+      }]),
+      ProcessedData::Account(ProcessedAccountData {
+        did,
+        active,
+        status,
+        time,
+      }) => Ok(vec![Self {
+        did: did.clone(),
+        time_us: clock.tick(),
+        kind: EventKind::Account {
+          account: AccountEvent {
+            active,
+            did,
+            seq,
+            time,
+            status,
+          },
+        },
+      }]),
+      _ => Ok(Vec::new()),
+    }
+  }
+}
+
+fn from_commit(commit: ProcessedCommitData, clock: &mut Clock) -> Result<Vec<Event>, Error> {
+  let ProcessedCommitData { repo, ops, rev, .. } = commit;
+  ops
+    .unwrap_or_default()
+    .into_iter()
+    .map(|op| {
+      let Operation {
+        action,
+        path,
+        cid,
+        record,
+      } = op;
+      let (collection, rkey) = path.split_once('/').unwrap_or((&path, ""));
+      Ok(Event {
+        did: repo.clone(),
+        time_us: clock.tick(),
+        kind: EventKind::Commit {
+          commit: CommitEvent {
+            rev: rev.clone(),
+            operation: action,
+            collection: collection.to_owned(),
+            rkey: rkey.to_owned(),
+            record: record
+              .map(|record| record_to_json(&record, Dialect::Atproto))
+              .transpose()?,
+            cid: cid.map(|cid| cid.0.to_string()),
+          },
+        },
+      })
+    })
+    .collect()
+}
@@ -0,0 +1,268 @@
+//! This file provides the [`JetstreamServer`], which re-broadcasts Jetstream [`Event`]s to local `WebSocket` clients.
+//!
+//! It's meant to share a single upstream subscription between many services: the events are published once,
+//! and every client receives the ones matching its query parameters, following the Jetstream `/subscribe` API:
+//! - `wantedCollections`: the collections of the commits to send, which can end with `.*` to match a prefix.
+//!   Identity and account events are always sent.
+//! - `wantedDids`: the repositories whose events are sent.
+//! - `cursor`: a time in microseconds, to replay the buffered events from. The events are published
+//!   with increasing times, given by a [`Clock`](super::Clock), so a cursor resumes right at the
+//!   event it names.
+//!
+//! Handshakes on other paths are rejected with a `404`, and malformed parameters with a `400`.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+  collections::{HashSet, VecDeque},
+  io,
+  net::SocketAddr,
+  sync::{Arc, Mutex, PoisonError},
+};
+
+use atrium_api::types::string::Did;
+use bon::bon;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::{
+  net::{TcpListener, TcpStream},
+  sync::broadcast::{self, error::RecvError},
+  task::JoinHandle,
+};
+use tokio_tungstenite::{
+  accept_hdr_async,
+  tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+  },
+};
+
+use super::{Event, EventKind};
+use crate::atrium_xrpc_wss_client::subscriptions::repositories::filter::Filter;
+
+/// The path of the subscription endpoint.
+const PATH: &str = "/subscribe";
+
+/// An event, along with its JSON serialization shared by all the clients.
+#[derive(Debug)]
+struct Entry {
+  event: Event,
+  json: String,
+}
+
+/// The events buffered for replay, and the channel to the connected clients.
+#[derive(Debug)]
+struct Shared {
+  buffer: VecDeque<Arc<Entry>>,
+  buffer_size: usize,
+  sender: broadcast::Sender<Arc<Entry>>,
+}
+
+impl Shared {
+  fn lock(shared: &Mutex<Self>) -> std::sync::MutexGuard<'_, Self> {
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+}
+
+/// The query parameters of a client.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Params {
+  #[serde(default)]
+  wanted_collections: Vec<String>,
+  #[serde(default)]
+  wanted_dids: HashSet<Did>,
+  cursor: Option<i64>,
+}
+
+impl Params {
+  fn filter(&self) -> Filter {
+    Filter::builder()
+      .collections(self.wanted_collections.clone())
+      .maybe_allowed_dids((!self.wanted_dids.is_empty()).then(|| self.wanted_dids.clone()))
+      .build()
+  }
+}
+
+/// Returns `true` if the `event` should be sent to a client with the `filter`.
+fn matches(filter: &Filter, event: &Event) -> bool {
+  filter.matches_repo(&event.did)
+    && match &event.kind {
+      EventKind::Commit { commit } => filter.matches_op(&commit.operation, &commit.collection),
+      EventKind::Identity { .. } | EventKind::Account { .. } => true,
+    }
+}
+
+/// A `WebSocket` server re-broadcasting the published [`Event`]s to its clients.
+///
+/// Clients that fall behind by more than the capacity of their queue are disconnected,
+/// and can reconnect with a `cursor` to replay the buffered events they missed.
+///
+/// The server stops when dropped.
+#[derive(Debug)]
+pub struct JetstreamServer {
+  local_addr: SocketAddr,
+  shared: Arc<Mutex<Shared>>,
+  task: JoinHandle<()>,
+}
+
+#[bon]
+impl JetstreamServer {
+  /// Binds the server to the `addr`, buffering the last `buffer_size` events for replay
+  /// (defaults to 100 000), with a queue of `client_capacity` events per client (defaults to 10 000).
+  ///
+  /// # Errors
+  /// Returns an error if the server could not be bound.
+  #[builder(finish_fn = bind)]
+  #[expect(
+    tail_expr_drop_order,
+    reason = "Dropping a pending accept before or after the handle of a client task changes nothing."
+  )]
+  pub async fn new(
+    addr: SocketAddr,
+    #[builder(default = 100_000)] buffer_size: usize,
+    #[builder(default = 10_000)] client_capacity: usize,
+  ) -> io::Result<Self> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let (sender, _) = broadcast::channel(client_capacity.max(1));
+    let shared = Arc::new(Mutex::new(Shared {
+      buffer: VecDeque::new(),
+      buffer_size,
+      sender,
+    }));
+
+    let task_shared = Arc::clone(&shared);
+    let task = tokio::spawn(async move {
+      while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve(stream, Arc::clone(&task_shared)));
+      }
+    });
+
+    Ok(Self {
+      local_addr,
+      shared,
+      task,
+    })
+  }
+}
+
+impl JetstreamServer {
+  /// The address the server is listening on.
+  #[must_use]
+  pub const fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+
+  /// Publishes an event to the connected clients, and buffers it for replay.
+  ///
+  /// The events must be published in the order of their `time_us`, as given by a
+  /// [`Clock`](super::Clock), since the replay starts from the first one at or after the cursor.
+  ///
+  /// # Errors
+  /// Returns an error if the event could not be serialized.
+  pub fn publish(&self, event: Event) -> Result<(), serde_json::Error> {
+    let json = serde_json::to_string(&event)?;
+    let entry = Arc::new(Entry { event, json });
+    let mut shared = Shared::lock(&self.shared);
+    if shared.buffer_size > 0 {
+      if shared.buffer.len() == shared.buffer_size {
+        shared.buffer.pop_front();
+      }
+      shared.buffer.push_back(Arc::clone(&entry));
+    }
+    // There may be no client connected, which is fine.
+    shared.sender.send(entry).ok();
+    drop(shared);
+    Ok(())
+  }
+
+  /// The number of clients currently connected.
+  #[must_use]
+  pub fn clients(&self) -> usize {
+    Shared::lock(&self.shared).sender.receiver_count()
+  }
+}
+
+impl Drop for JetstreamServer {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
+
+/// Builds the response rejecting a handshake.
+fn error_response(status: StatusCode, message: &str) -> ErrorResponse {
+  let mut response = ErrorResponse::new(Some(message.to_owned()));
+  *response.status_mut() = status;
+  response
+}
+
+/// Serves the events to a single client.
+async fn serve(stream: TcpStream, shared: Arc<Mutex<Shared>>) {
+  let mut params = Params::default();
+  #[expect(
+    clippy::result_large_err,
+    reason = "The signature is defined by `tungstenite`."
+  )]
+  let callback = |request: &Request, response: Response| {
+    let uri = request.uri();
+    if uri.path() != PATH {
+      return Err(error_response(StatusCode::NOT_FOUND, "Not found"));
+    }
+    params = serde_html_form::from_str(uri.query().unwrap_or_default())
+      .map_err(|e| error_response(StatusCode::BAD_REQUEST, &format!("Invalid query: {e}")))?;
+    Ok(response)
+  };
+  let Ok(mut ws) = accept_hdr_async(stream, callback).await else {
+    return;
+  };
+  let filter = params.filter();
+
+  // The replayed events are collected while holding the lock, so that none is missed or sent twice.
+  let (replay, mut receiver) = {
+    let shared = Shared::lock(&shared);
+    let replay = params.cursor.map_or_else(Vec::new, |cursor| {
+      let start = shared
+        .buffer
+        .partition_point(|entry| entry.event.time_us < cursor);
+      shared.buffer.range(start..).cloned().collect()
+    });
+    (replay, shared.sender.subscribe())
+  };
+
+  for entry in replay {
+    if matches(&filter, &entry.event) && ws.send(Message::text(entry.json.clone())).await.is_err() {
+      return;
+    }
+  }
+
+  loop {
+    tokio::select! {
+      entry = receiver.recv() => match entry {
+        Ok(entry) => {
+          if matches(&filter, &entry.event) && ws.send(Message::text(entry.json.clone())).await.is_err() {
+            return;
+          }
+        }
+        Err(RecvError::Lagged(_)) => {
+          let frame = CloseFrame {
+            code: CloseCode::Again,
+            reason: "Consumer too slow".into(),
+          };
+          drop(ws.close(Some(frame)).await);
+          return;
+        }
+        Err(RecvError::Closed) => break,
+      },
+      message = ws.next() => match message {
+        // Pings are answered while reading, and other messages are ignored.
+        Some(Ok(_)) => {}
+        Some(Err(_)) | None => return,
+      },
+    }
+  }
+  drop(ws.close(None).await);
+}
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use tokio_tungstenite::{connect_async, tungstenite, MaybeTlsStream, WebSocketStream};
+
+use super::*;
+use crate::atrium_xrpc_wss_client::{
+  jetstream::{Clock, CommitEvent, IdentityEvent},
+  subscriptions::repositories::fixtures::{self, create},
+};
+
+fn did(did: &str) -> Did {
+  did.parse().expect("invalid did")
+}
+
+fn commit(repo: &str, collection: &str, time_us: i64) -> Event {
+  Event {
+    did: did(repo),
+    time_us,
+    kind: EventKind::Commit {
+      commit: CommitEvent {
+        rev: time_us.to_string(),
+        operation: String::from("create"),
+        collection: collection.to_owned(),
+        rkey: time_us.to_string(),
+        record: None,
+        cid: None,
+      },
+    },
+  }
+}
+
+fn events() -> Vec<Event> {
+  vec![
+    commit("did:plc:abc", "app.bsky.feed.post", 1),
+    commit("did:plc:abc", "app.bsky.feed.like", 2),
+    commit("did:plc:xyz", "app.bsky.feed.post", 3),
+    Event {
+      did: did("did:plc:xyz"),
+      time_us: 4,
+      kind: EventKind::Identity {
+        identity: IdentityEvent {
+          did: did("did:plc:xyz"),
+          handle: None,
+          seq: 4,
+          time: "2024-01-01T00:00:00.000Z"
+            .parse()
+            .expect("invalid datetime"),
+        },
+      },
+    },
+  ]
+}
+
+async fn bind() -> JetstreamServer {
+  JetstreamServer::builder()
+    .addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+    .bind()
+    .await
+    .expect("failed to bind")
+}
+
+async fn connect(
+  server: &JetstreamServer,
+  query: &str,
+) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+  let url = format!("ws://{}/subscribe?{query}", server.local_addr());
+  connect_async(url).await.expect("failed to connect").0
+}
+
+/// Reads the `time_us` of the next `n` events.
+async fn read(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>, n: usize) -> Vec<i64> {
+  let mut times = Vec::new();
+  while times.len() < n {
+    let message = tokio::time::timeout(Duration::from_secs(5), ws.next())
+      .await
+      .expect("timed out")
+      .expect("connection closed")
+      .expect("failed to read");
+    if let Message::Text(text) = message {
+      let event = serde_json::from_str::<Event>(&text).expect("failed to deserialize");
+      times.push(event.time_us);
+    }
+  }
+  times
+}
+
+#[tokio::test]
+async fn replay_filtered_events() {
+  let server = bind().await;
+  for event in events() {
+    server.publish(event).expect("failed to publish");
+  }
+
+  let mut ws = connect(&server, "cursor=2").await;
+  assert_eq!(read(&mut ws, 3).await, [2, 3, 4]);
+
+  let mut ws = connect(
+    &server,
+    "cursor=0&wantedCollections=app.bsky.feed.post&wantedDids=did:plc:xyz",
+  )
+  .await;
+  assert_eq!(read(&mut ws, 2).await, [3, 4]);
+
+  let mut ws = connect(&server, "cursor=0&wantedCollections=app.bsky.feed.*").await;
+  assert_eq!(read(&mut ws, 4).await, [1, 2, 3, 4]);
+}
+
+#[tokio::test]
+async fn replay_events_received_out_of_order() {
+  let server = bind().await;
+  let mut clock = Clock::default();
+  // The relay's `time` of the payloads goes backwards and repeats.
+  let payloads = [
+    "2024-01-01T00:00:03.000Z",
+    "2024-01-01T00:00:01.000Z",
+    "2024-01-01T00:00:01.000Z",
+    "2024-01-01T00:00:02.000Z",
+  ]
+  .into_iter()
+  .zip(1..)
+  .map(|(time, seq)| {
+    let path = format!("app.bsky.feed.post/{seq}");
+    fixtures::commit(
+      seq,
+      "did:plc:abc",
+      time,
+      vec![create(&path, fixtures::post("Hello", &["en"]))],
+    )
+  });
+  let mut times = Vec::new();
+  for payload in payloads {
+    for event in Event::from_payload(payload, &mut clock).expect("failed to convert") {
+      times.push(event.time_us);
+      server.publish(event).expect("failed to publish");
+    }
+  }
+
+  // Resuming from an event replays exactly the ones received from it.
+  for (index, cursor) in times.iter().enumerate() {
+    let mut ws = connect(&server, &format!("cursor={cursor}")).await;
+    assert_eq!(read(&mut ws, times.len() - index).await, times[index..]);
+  }
+}
+
+#[tokio::test]
+async fn broadcast_live_events() {
+  let server = bind().await;
+  let mut first = connect(&server, "wantedCollections=app.bsky.feed.like").await;
+  let mut second = connect(&server, "").await;
+  while server.clients() < 2 {
+    tokio::time::sleep(Duration::from_millis(10)).await;
+  }
+
+  for event in events() {
+    server.publish(event).expect("failed to publish");
+  }
+  assert_eq!(read(&mut first, 2).await, [2, 4]);
+  assert_eq!(read(&mut second, 4).await, [1, 2, 3, 4]);
+}
+
+#[tokio::test]
+async fn reject_invalid_handshakes() {
+  let server = bind().await;
+  for (path, status) in [
+    ("subscribe?cursor=now", 400),
+    ("subscribe?wantedDids=alice", 400),
+    ("firehose", 404),
+  ] {
+    let url = format!("ws://{}/{path}", server.local_addr());
+    match connect_async(url).await {
+      Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), status, "{path}"),
+      res => panic!(
+        "unexpected handshake result for {path}: {:?}",
+        res.map(|_| ())
+      ),
+    }
+  }
+}
@@ -0,0 +1,143 @@
+use atrium_api::{app::bsky::feed::post, record::KnownRecord, types::CidLink};
+use serde_json::json;
+
+use super::*;
+use crate::atrium_xrpc_wss_client::subscriptions::repositories::car::tests as car;
+
+fn time() -> Datetime {
+  "2024-01-01T00:00:00.000Z"
+    .parse()
+    .expect("invalid datetime")
+}
+
+fn did() -> Did {
+  "did:plc:abc".parse().expect("invalid did")
+}
+
+/// The last tick of the [`clock`], far enough in the future that the next ones follow it.
+const LAST_TICK: i64 = 4_000_000_000_000_000;
+
+fn clock() -> Clock {
+  Clock { last: LAST_TICK }
+}
+
+#[test]
+fn tick_monotonic_clock() {
+  let mut clock = Clock::default();
+  let start = Utc::now().timestamp_micros();
+  let ticks = (0..1000).map(|_| clock.tick()).collect::<Vec<_>>();
+  assert!(ticks[0] >= start);
+  assert!(ticks.windows(2).all(|pair| pair[0] < pair[1]));
+
+  let mut clock = self::clock();
+  assert_eq!([clock.tick(), clock.tick()], [LAST_TICK + 1, LAST_TICK + 2]);
+}
+
+#[test]
+fn convert_commit_to_events() {
+  let payload = ProcessedPayload {
+    seq: Some(1),
+    data: ProcessedData::Commit(ProcessedCommitData {
+      repo: did(),
+      commit: Some(CidLink(car::cid(1))),
+      ops: Some(vec![
+        Operation {
+          action: String::from("create"),
+          path: String::from("app.bsky.feed.post/3k"),
+          cid: Some(CidLink(car::cid(2))),
+          record: Some(KnownRecord::from(post::RecordData {
+            created_at: time(),
+            embed: None,
+            entities: None,
+            facets: None,
+            labels: None,
+            langs: None,
+            reply: None,
+            tags: None,
+            text: String::from("Hello"),
+          })),
+        },
+        Operation {
+          action: String::from("delete"),
+          path: String::from("app.bsky.feed.like/3l"),
+          cid: None,
+          record: None,
+        },
+      ]),
+      blobs: vec![],
+      rev: String::from("3m"),
+      since: None,
+      time: time(),
+    }),
+  };
+
+  let events = Event::from_payload(payload, &mut clock()).expect("failed to convert");
+  assert_eq!(
+    serde_json::to_value(&events).expect("failed to serialize"),
+    json!([
+      {
+        "did": "did:plc:abc",
+        "time_us": LAST_TICK + 1,
+        "kind": "commit",
+        "commit": {
+          "rev": "3m",
+          "operation": "create",
+          "collection": "app.bsky.feed.post",
+          "rkey": "3k",
+          "record": {
+            "$type": "app.bsky.feed.post",
+            "createdAt": "2024-01-01T00:00:00.000Z",
+            "text": "Hello",
+          },
+          "cid": car::cid(2).to_string(),
+        },
+      },
+      {
+        "did": "did:plc:abc",
+        "time_us": LAST_TICK + 2,
+        "kind": "commit",
+        "commit": {
+          "rev": "3m",
+          "operation": "delete",
+          "collection": "app.bsky.feed.like",
+          "rkey": "3l",
+        },
+      },
+    ])
+  );
+}
+
+#[test]
+fn convert_account_to_event() {
+  let payload = ProcessedPayload {
+    seq: Some(2),
+    data: ProcessedData::Account(ProcessedAccountData {
+      did: did(),
+      active: false,
+      status: Some(String::from("takendown")),
+      time: time(),
+    }),
+  };
+
+  let events = Event::from_payload(payload, &mut clock()).expect("failed to convert");
+  let json = json!([{
+    "did": "did:plc:abc",
+    "time_us": LAST_TICK + 1,
+    "kind": "account",
+    "account": {
+      "active": false,
+      "did": "did:plc:abc",
+      "seq": 2,
+      "time": "2024-01-01T00:00:00.000Z",
+      "status": "takendown",
+    },
+  }]);
+  assert_eq!(
+    serde_json::to_value(&events).expect("failed to serialize"),
+    json
+  );
+  assert_eq!(
+    serde_json::from_value::<Vec<Event>>(json).expect("failed to deserialize"),
+    events
+  );
+}
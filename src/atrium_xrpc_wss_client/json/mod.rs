@@ -0,0 +1,123 @@
+//! This file provides the conversion of DAG-CBOR data, like records and the blocks of a commit, to JSON.
+//!
+//! Two [`Dialect`]s are supported, which only differ in how links and bytes are written:
+//! - [`Dialect::Atproto`], the JSON of the [`ATProto` data model](https://atproto.com/specs/data-model),
+//!   where links are written as `{"$link": cid}` and bytes as `{"$bytes": base64}`.
+//! - [`Dialect::DagJson`], the [DAG-JSON](https://ipld.io/specs/codecs/dag-json/spec/) codec,
+//!   where links are written as `{"/": cid}` and bytes as `{"/": {"bytes": base64}}`.
+//!
+//! In both, bytes are encoded in standard base64 without padding, and `$type` fields are kept as is.
+//! Since the keys of a [`Map`] are sorted, serializing the converted values with [`serde_json::to_string`]
+//! gives the canonical DAG-JSON encoding.
+
+#[cfg(test)]
+mod tests;
+
+use std::{collections::BTreeMap, convert::Infallible};
+
+use atrium_api::record::KnownRecord;
+use bytes::Bytes;
+use data_encoding::BASE64_NOPAD;
+use ipld_core::{ipld::Ipld, serde::SerdeError};
+use serde_json::{Map, Value};
+
+use crate::atrium_xrpc_wss_client::subscriptions::repositories::car;
+
+/// An error while converting DAG-CBOR data to JSON.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error("Record conversion error: {0}")]
+  Record(#[from] SerdeError),
+  #[error("Invalid DAG-CBOR block: {0}")]
+  Block(#[from] serde_ipld_dagcbor::DecodeError<Infallible>),
+  #[error("Invalid CAR file: {0}")]
+  Car(#[from] car::Error),
+}
+
+/// The JSON conventions used to write the links and bytes, which JSON has no type for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+  /// `{"$link": cid}` and `{"$bytes": base64}`, as used by the XRPC APIs and Jetstream.
+  #[default]
+  Atproto,
+  /// `{"/": cid}` and `{"/": {"bytes": base64}}`.
+  DagJson,
+}
+
+/// Converts an IPLD value to JSON.
+///
+/// Integers that don't fit in an `i64` nor a `u64`, and floats that aren't finite,
+/// can't be written in JSON and become `null`. Neither is allowed in DAG-CBOR anyway.
+#[must_use]
+pub fn ipld_to_json(ipld: Ipld, dialect: Dialect) -> Value {
+  match ipld {
+    Ipld::Null => Value::Null,
+    Ipld::Bool(b) => Value::Bool(b),
+    Ipld::Integer(i) => i64::try_from(i)
+      .map(Value::from)
+      .or_else(|_| u64::try_from(i).map(Value::from))
+      .unwrap_or(Value::Null),
+    Ipld::Float(f) => serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number),
+    Ipld::String(s) => Value::String(s),
+    Ipld::Bytes(bytes) => {
+      let base64 = Value::String(BASE64_NOPAD.encode(&bytes));
+      match dialect {
+        Dialect::Atproto => object("$bytes", base64),
+        Dialect::DagJson => object("/", object("bytes", base64)),
+      }
+    }
+    Ipld::List(list) => Value::Array(
+      list
+        .into_iter()
+        .map(|value| ipld_to_json(value, dialect))
+        .collect(),
+    ),
+    Ipld::Map(map) => Value::Object(
+      map
+        .into_iter()
+        .map(|(key, value)| (key, ipld_to_json(value, dialect)))
+        .collect(),
+    ),
+    Ipld::Link(cid) => {
+      let cid = Value::String(cid.to_string());
+      match dialect {
+        Dialect::Atproto => object("$link", cid),
+        Dialect::DagJson => object("/", cid),
+      }
+    }
+  }
+}
+
+/// Converts a record to JSON, through its IPLD representation.
+///
+/// # Errors
+/// Returns an error if the record can't be represented as IPLD.
+pub fn record_to_json(record: &KnownRecord, dialect: Dialect) -> Result<Value, Error> {
+  let ipld = ipld_core::serde::to_ipld(record)?;
+  Ok(ipld_to_json(ipld, dialect))
+}
+
+/// Decodes a DAG-CBOR block and converts it to JSON.
+///
+/// # Errors
+/// Returns an error if the block isn't valid DAG-CBOR.
+pub fn block_to_json(block: &[u8], dialect: Dialect) -> Result<Value, Error> {
+  let ipld = serde_ipld_dagcbor::from_slice(block)?;
+  Ok(ipld_to_json(ipld, dialect))
+}
+
+/// Converts all the blocks of a CAR file, like the `blocks` of a `#commit` payload, to JSON,
+/// indexed by their CID.
+///
+/// # Errors
+/// Returns an error if the CAR file or one of its blocks is invalid.
+pub fn blocks_to_json(car: &Bytes, dialect: Dialect) -> Result<BTreeMap<String, Value>, Error> {
+  car::read_blocks(car)?
+    .into_iter()
+    .map(|(cid, block)| Ok((cid.to_string(), block_to_json(&block, dialect)?)))
+    .collect()
+}
+
+fn object(key: &str, value: Value) -> Value {
+  Value::Object(Map::from_iter([(key.to_owned(), value)]))
+}
@@ -0,0 +1,95 @@
+use atrium_api::app::bsky::feed::post;
+use serde_json::json;
+
+use super::*;
+use crate::atrium_xrpc_wss_client::subscriptions::repositories::car::tests as car;
+
+fn ipld() -> Ipld {
+  Ipld::Map(
+    [
+      ("bytes".to_owned(), Ipld::Bytes(vec![1, 2, 3, 4])),
+      ("link".to_owned(), Ipld::Link(car::cid(1))),
+      (
+        "list".to_owned(),
+        Ipld::List(vec![Ipld::Integer(-1), Ipld::Bool(true), Ipld::Null]),
+      ),
+    ]
+    .into(),
+  )
+}
+
+#[test]
+fn convert_ipld_to_atproto_json() {
+  assert_eq!(
+    ipld_to_json(ipld(), Dialect::Atproto),
+    json!({
+      "bytes": { "$bytes": "AQIDBA" },
+      "link": { "$link": car::cid(1).to_string() },
+      "list": [-1, true, null],
+    })
+  );
+}
+
+#[test]
+fn convert_ipld_to_dag_json() {
+  let json = ipld_to_json(ipld(), Dialect::DagJson);
+  assert_eq!(
+    json,
+    json!({
+      "bytes": { "/": { "bytes": "AQIDBA" } },
+      "link": { "/": car::cid(1).to_string() },
+      "list": [-1, true, null],
+    })
+  );
+  assert_eq!(
+    serde_json::to_string(&json).expect("failed to serialize"),
+    format!(
+      r#"{{"bytes":{{"/":{{"bytes":"AQIDBA"}}}},"link":{{"/":"{}"}},"list":[-1,true,null]}}"#,
+      car::cid(1)
+    )
+  );
+}
+
+#[test]
+fn convert_record_to_json() {
+  let record = KnownRecord::from(post::RecordData {
+    created_at: "2024-01-01T00:00:00.000Z"
+      .parse()
+      .expect("invalid datetime"),
+    embed: None,
+    entities: None,
+    facets: None,
+    labels: None,
+    langs: Some(vec!["en".parse().expect("invalid language")]),
+    reply: None,
+    tags: None,
+    text: String::from("Hello"),
+  });
+  assert_eq!(
+    record_to_json(&record, Dialect::Atproto).expect("failed to convert"),
+    json!({
+      "$type": "app.bsky.feed.post",
+      "createdAt": "2024-01-01T00:00:00.000Z",
+      "langs": ["en"],
+      "text": "Hello",
+    })
+  );
+}
+
+#[test]
+fn convert_blocks_to_json() {
+  let block = car::block(serde_ipld_dagcbor::to_vec(&ipld()).expect("failed to serialize"));
+  let cid = block.0;
+  let blocks = car::car(&[block]);
+  let json = blocks_to_json(&blocks, Dialect::DagJson).expect("failed to convert");
+  assert_eq!(
+    json,
+    BTreeMap::from([(cid.to_string(), ipld_to_json(ipld(), Dialect::DagJson))])
+  );
+
+  let blocks = car::car(&[car::block(vec![0xff])]);
+  assert!(matches!(
+    blocks_to_json(&blocks, Dialect::DagJson),
+    Err(Error::Block(_))
+  ));
+}
@@ -0,0 +1,135 @@
+//! This file defines the layout of the JSON-lines exports, written by the [`JsonlSink`].
+//!
+//! An export is a directory of segment files, each holding one processed payload per line,
+//! serialized as JSON. A segment is named after the time its first line was written, so they sort
+//! chronologically, followed by a counter keeping the names unique. Once closed, it may be
+//! compressed with gzip (with the `gzip` feature) or zstd (with the `zstd` feature).
+//!
+//! Every closed segment is recorded as a [`SegmentInfo`] line of the [`MANIFEST`] file, with the
+//! range of seqs it holds, so that the segments needed to re-ingest a range can be found without
+//! reading them. The segment being written is only added to the manifest once closed.
+
+#[cfg(test)]
+mod tests;
+
+mod sink;
+pub use sink::{Error, JsonlSink};
+
+use std::{
+  fs::File,
+  io::{self, BufRead, BufReader},
+  path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the manifest file, in the directory of the segments.
+pub const MANIFEST: &str = "manifest.jsonl";
+
+/// The extension of uncompressed segment files.
+pub const EXTENSION: &str = "jsonl";
+/// The extension of gzip compressed segment files.
+pub const GZIP_EXTENSION: &str = "jsonl.gz";
+/// The extension of zstd compressed segment files.
+pub const ZSTD_EXTENSION: &str = "jsonl.zst";
+
+/// How the closed segments are compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+  #[default]
+  None,
+  Gzip,
+  Zstd,
+}
+
+impl Compression {
+  /// The extension of the segment files compressed this way.
+  #[must_use]
+  pub const fn extension(self) -> &'static str {
+    match self {
+      Self::None => EXTENSION,
+      Self::Gzip => GZIP_EXTENSION,
+      Self::Zstd => ZSTD_EXTENSION,
+    }
+  }
+
+  /// Returns an error if the feature needed for this compression is disabled.
+  fn check_supported(self) -> io::Result<()> {
+    let (supported, feature) = match self {
+      Self::None => (true, ""),
+      Self::Gzip => (cfg!(feature = "gzip"), "gzip"),
+      Self::Zstd => (cfg!(feature = "zstd"), "zstd"),
+    };
+    if supported {
+      Ok(())
+    } else {
+      Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Compression requires the `{feature}` feature"),
+      ))
+    }
+  }
+}
+
+/// A closed segment, as recorded in the [`MANIFEST`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+  /// The name of the segment file, relative to the directory of the manifest.
+  pub file: String,
+  /// The amount of lines in the segment.
+  pub count: u64,
+  /// The seq of the first line that has one, if any.
+  pub first_seq: Option<i64>,
+  /// The seq of the last line that has one, if any.
+  pub last_seq: Option<i64>,
+  /// When the first line was written, in microseconds since the Unix epoch.
+  pub started_at: i64,
+  /// When the segment was closed, in microseconds since the Unix epoch.
+  pub closed_at: i64,
+}
+
+/// Reads the manifest of the export in `directory`, in the order the segments were closed.
+///
+/// A missing manifest is read as empty, since it is only created when the first segment is closed.
+///
+/// # Errors
+/// Returns an error if the manifest could not be read, or if one of its lines is invalid.
+pub fn read_manifest(directory: &Path) -> io::Result<Vec<SegmentInfo>> {
+  let file = match File::open(directory.join(MANIFEST)) {
+    Ok(file) => file,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(e),
+  };
+  BufReader::new(file)
+    .lines()
+    .map(|line| Ok(serde_json::from_str(&line?)?))
+    .collect()
+}
+
+/// Opens a segment for reading its lines, decompressing it according to its extension.
+///
+/// # Errors
+/// Returns an error if the segment could not be opened, or if it is compressed
+/// without the feature needed to decompress it.
+pub fn open_segment(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  let compression = if name.ends_with(&format!(".{GZIP_EXTENSION}")) {
+    Compression::Gzip
+  } else if name.ends_with(&format!(".{ZSTD_EXTENSION}")) {
+    Compression::Zstd
+  } else {
+    Compression::None
+  };
+  compression.check_supported()?;
+
+  let file = BufReader::new(File::open(path)?);
+  Ok(match compression {
+    Compression::None => Box::new(file),
+    #[cfg(feature = "gzip")]
+    Compression::Gzip => Box::new(BufReader::new(flate2::bufread::GzDecoder::new(file))),
+    #[cfg(feature = "zstd")]
+    Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(file)?)),
+    #[cfg(not(all(feature = "gzip", feature = "zstd")))]
+    _ => unreachable!("Checked above."),
+  })
+}
@@ -0,0 +1,327 @@
+//! This file provides the [`JsonlSink`], which writes processed payloads to JSON-lines segments.
+
+use std::{
+  fs::{self, File, OpenOptions},
+  io::{self, BufWriter, Write},
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use bon::bon;
+use chrono::Utc;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use super::{Compression, SegmentInfo, EXTENSION, MANIFEST};
+use crate::atrium_xrpc_wss::subscriptions::{ProcessedPayload, SubscriptionError};
+
+/// How many lines [`JsonlSink::consume`] queues for the blocking thread writing them.
+const QUEUE_SIZE: usize = 1024;
+
+/// An error while consuming a subscription into a [`JsonlSink`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error<E> {
+  #[error("IO error: {0}")]
+  Io(#[from] io::Error),
+  #[error(transparent)]
+  Subscription(SubscriptionError<E>),
+}
+
+/// The segment currently being written.
+struct Segment {
+  path: PathBuf,
+  writer: BufWriter<File>,
+  size: u64,
+  count: u64,
+  first_seq: Option<i64>,
+  last_seq: Option<i64>,
+  started_at: i64,
+}
+
+/// Writes processed payloads as JSON lines, into segment files rotated by size or age.
+///
+/// A new segment is started when the current one reaches `max_segment_size` bytes (before
+/// compression), or when its first line is older than `max_segment_age`. Closed segments are
+/// compressed with `compression` and recorded in the manifest.
+///
+/// The current segment is closed when the sink is dropped.
+/// See the [`jsonl`](super) module for the layout of the exports.
+pub struct JsonlSink {
+  directory: PathBuf,
+  prefix: String,
+  max_segment_size: Option<u64>,
+  max_segment_age: Option<Duration>,
+  compression: Compression,
+  segment: Option<Segment>,
+  /// The number of segments opened so far.
+  opened: u64,
+}
+
+#[bon]
+impl JsonlSink {
+  /// Builds a new sink writing its segments and manifest to `directory`, creating it if needed.
+  ///
+  /// # Errors
+  /// Returns an error if the directory could not be created, or if the feature
+  /// needed for the `compression` is disabled.
+  #[builder]
+  pub fn new(
+    #[builder(into)] directory: PathBuf,
+    #[builder(into, default = String::from("firehose"))] prefix: String,
+    max_segment_size: Option<u64>,
+    max_segment_age: Option<Duration>,
+    #[builder(default)] compression: Compression,
+  ) -> io::Result<Self> {
+    compression.check_supported()?;
+    fs::create_dir_all(&directory)?;
+    Ok(Self {
+      directory,
+      prefix,
+      max_segment_size,
+      max_segment_age,
+      compression,
+      segment: None,
+      opened: 0,
+    })
+  }
+}
+
+impl JsonlSink {
+  /// Writes a payload as a single JSON line, rotating the segment if needed.
+  ///
+  /// # Errors
+  /// Returns an error if the payload could not be serialized or written.
+  pub fn write<D: Serialize>(&mut self, payload: &ProcessedPayload<D>) -> io::Result<()> {
+    self.write_line(&line(payload)?, payload.seq)
+  }
+
+  /// Writes the payloads of a subscription as lines, stopping when it ends or at its first error.
+  ///
+  /// The payloads are serialized as they are received, but written on a blocking thread, up to
+  /// 1024 lines ahead, along with the compression of the closed segments. The data is flushed
+  /// before returning, but the current segment is left open, so the sink can keep consuming a new
+  /// subscription.
+  ///
+  /// # Errors
+  /// Returns the error yielded by the subscription, or an IO error if a payload could not be written.
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The lines are handed to the writer, so only their leftovers are freed in a different order."
+  )]
+  pub async fn consume<D: Serialize, E>(
+    &mut self,
+    mut subscription: impl Stream<Item = Result<ProcessedPayload<D>, SubscriptionError<E>>> + Unpin,
+  ) -> Result<(), Error<E>> {
+    let (sender, mut receiver) = mpsc::channel::<(Vec<u8>, Option<i64>)>(QUEUE_SIZE);
+    let mut sink = self.detach();
+    let writer = tokio::task::spawn_blocking(move || {
+      let mut result = Ok(());
+      while let Some((line, seq)) = receiver.blocking_recv() {
+        result = sink.write_line(&line, seq);
+        if result.is_err() {
+          break;
+        }
+      }
+      let result = result.and_then(|()| sink.flush());
+      (sink, result)
+    });
+
+    let mut result = Ok(());
+    while let Some(payload) = subscription.next().await {
+      let line = payload
+        .map_err(Error::Subscription)
+        .and_then(|payload| Ok((line(&payload)?, payload.seq)));
+      match line {
+        // The writer only stops early after an error, which is returned below.
+        Ok(line) => {
+          if sender.send(line).await.is_err() {
+            break;
+          }
+        }
+        Err(e) => {
+          result = Err(e);
+          break;
+        }
+      }
+    }
+    drop(sender);
+    let (mut sink, written) = writer.await.map_err(io::Error::other)?;
+    self.segment = sink.segment.take();
+    self.opened = sink.opened;
+    written?;
+    result
+  }
+
+  /// Flushes the lines written so far to the current segment.
+  ///
+  /// # Errors
+  /// Returns an error if flushing fails.
+  pub fn flush(&mut self) -> io::Result<()> {
+    self
+      .segment
+      .as_mut()
+      .map_or(Ok(()), |segment| segment.writer.flush())
+  }
+
+  /// Closes the current segment, compressing it and recording it in the manifest.
+  /// The next payload will be written to a new one.
+  ///
+  /// # Errors
+  /// Returns an error if the segment could not be flushed, compressed or recorded.
+  pub fn finish(&mut self) -> io::Result<()> {
+    let Some(Segment {
+      path,
+      mut writer,
+      count,
+      first_seq,
+      last_seq,
+      started_at,
+      ..
+    }) = self.segment.take()
+    else {
+      return Ok(());
+    };
+    writer.flush()?;
+    drop(writer);
+
+    let path = compress(&path, self.compression)?;
+    let info = SegmentInfo {
+      file: path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned(),
+      count,
+      first_seq,
+      last_seq,
+      started_at,
+      closed_at: Utc::now().timestamp_micros(),
+    };
+    let mut line = serde_json::to_vec(&info)?;
+    line.push(b'\n');
+    OpenOptions::new()
+      .append(true)
+      .create(true)
+      .open(self.directory.join(MANIFEST))?
+      .write_all(&line)
+  }
+
+  fn write_line(&mut self, line: &[u8], seq: Option<i64>) -> io::Result<()> {
+    let now = Utc::now().timestamp_micros();
+    if self.should_rotate(now) {
+      self.finish()?;
+    }
+    if self.segment.is_none() {
+      self.segment = Some(self.open(now)?);
+    }
+    if let Some(segment) = &mut self.segment {
+      segment.writer.write_all(line)?;
+      segment.size += line.len() as u64;
+      segment.count += 1;
+      if let Some(seq) = seq {
+        segment.first_seq.get_or_insert(seq);
+        segment.last_seq = Some(seq);
+      }
+    }
+    Ok(())
+  }
+
+  /// Moves the current segment to a new sink with the same settings, to write it from another
+  /// thread.
+  fn detach(&mut self) -> Self {
+    Self {
+      directory: self.directory.clone(),
+      prefix: self.prefix.clone(),
+      max_segment_size: self.max_segment_size,
+      max_segment_age: self.max_segment_age,
+      compression: self.compression,
+      segment: self.segment.take(),
+      opened: self.opened,
+    }
+  }
+
+  fn should_rotate(&self, now: i64) -> bool {
+    let Some(segment) = &self.segment else {
+      return false;
+    };
+    let too_big = self.max_segment_size.is_some_and(|max| segment.size >= max);
+    let too_old = self.max_segment_age.is_some_and(|max| {
+      let age = now.saturating_sub(segment.started_at);
+      u128::try_from(age).is_ok_and(|age| age >= max.as_micros())
+    });
+    too_big || too_old
+  }
+
+  fn open(&mut self, started_at: i64) -> io::Result<Segment> {
+    let (file, path) = loop {
+      let path = self.directory.join(format!(
+        "{}-{started_at:020}-{:06}.{EXTENSION}",
+        self.prefix, self.opened
+      ));
+      self.opened += 1;
+      // The name may have been taken by another sink, or before a restart, and the segment
+      // compressed since.
+      if path.with_extension(self.compression.extension()).exists() {
+        continue;
+      }
+      let file = OpenOptions::new().append(true).create_new(true).open(&path);
+      if !file
+        .as_ref()
+        .is_err_and(|e| e.kind() == io::ErrorKind::AlreadyExists)
+      {
+        break (file?, path);
+      }
+    };
+    Ok(Segment {
+      path,
+      writer: BufWriter::new(file),
+      size: 0,
+      count: 0,
+      first_seq: None,
+      last_seq: None,
+      started_at,
+    })
+  }
+}
+
+impl Drop for JsonlSink {
+  fn drop(&mut self) {
+    // The segment may fail to close silently here, unless `finish` was called beforehand.
+    drop(self.finish());
+  }
+}
+
+/// Serializes a payload as a JSON line.
+fn line<D: Serialize>(payload: &ProcessedPayload<D>) -> io::Result<Vec<u8>> {
+  let mut line = serde_json::to_vec(payload)?;
+  line.push(b'\n');
+  Ok(line)
+}
+
+/// Compresses a closed segment, replacing it with the compressed file, whose path is returned.
+fn compress(path: &Path, compression: Compression) -> io::Result<PathBuf> {
+  let compressed = path.with_extension(compression.extension());
+  match compression {
+    Compression::None => return Ok(path.to_owned()),
+    #[cfg(feature = "gzip")]
+    Compression::Gzip => {
+      let writer = BufWriter::new(File::create_new(&compressed)?);
+      let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+      io::copy(&mut File::open(path)?, &mut encoder)?;
+      encoder.finish()?.flush()?;
+    }
+    #[cfg(feature = "zstd")]
+    Compression::Zstd => {
+      let writer = BufWriter::new(File::create_new(&compressed)?);
+      let mut encoder = zstd::Encoder::new(writer, 0)?;
+      io::copy(&mut File::open(path)?, &mut encoder)?;
+      encoder.finish()?.flush()?;
+    }
+    // Already checked when building the sink.
+    #[cfg(not(all(feature = "gzip", feature = "zstd")))]
+    _ => compression.check_supported()?,
+  }
+  fs::remove_file(path)?;
+  Ok(compressed)
+}
@@ -0,0 +1,206 @@
+use std::{convert::Infallible, path::PathBuf, time::Duration};
+
+use futures::stream;
+use serde_json::{json, Value};
+
+use super::*;
+use crate::{
+  atrium_xrpc_wss::subscriptions::{ProcessedPayload, SubscriptionError},
+  atrium_xrpc_wss_client::subscriptions::repositories::fixtures::until_error,
+};
+
+fn payload(seq: Option<i64>) -> ProcessedPayload<Value> {
+  ProcessedPayload {
+    seq,
+    data: json!({ "text": "Hello" }),
+  }
+}
+
+fn read_lines(path: &Path) -> Vec<Value> {
+  open_segment(path)
+    .expect("failed to open segment")
+    .lines()
+    .map(|line| serde_json::from_str(&line.expect("failed to read line")).expect("invalid line"))
+    .collect()
+}
+
+fn segments(directory: &Path) -> Vec<PathBuf> {
+  read_manifest(directory)
+    .expect("failed to read manifest")
+    .into_iter()
+    .map(|info| directory.join(info.file))
+    .collect()
+}
+
+#[test]
+fn write_lines_and_manifest() {
+  let dir = tempfile::tempdir().expect("failed to create dir");
+  assert!(read_manifest(dir.path())
+    .expect("failed to read manifest")
+    .is_empty());
+
+  let mut sink = JsonlSink::builder()
+    .directory(dir.path())
+    .build()
+    .expect("failed to build sink");
+  for seq in [Some(1), None, Some(2)] {
+    sink.write(&payload(seq)).expect("failed to write");
+  }
+  sink.finish().expect("failed to finish");
+
+  let manifest = read_manifest(dir.path()).expect("failed to read manifest");
+  assert_eq!(manifest.len(), 1);
+  assert!(manifest[0].file.ends_with(EXTENSION));
+  assert_eq!(manifest[0].count, 3);
+  assert_eq!(manifest[0].first_seq, Some(1));
+  assert_eq!(manifest[0].last_seq, Some(2));
+  assert!(manifest[0].started_at <= manifest[0].closed_at);
+
+  let lines = read_lines(&dir.path().join(&manifest[0].file));
+  assert_eq!(
+    lines,
+    [
+      json!({ "seq": 1, "data": { "text": "Hello" } }),
+      json!({ "seq": null, "data": { "text": "Hello" } }),
+      json!({ "seq": 2, "data": { "text": "Hello" } }),
+    ]
+  );
+}
+
+#[test]
+fn rotate_by_size() {
+  let dir = tempfile::tempdir().expect("failed to create dir");
+  let build = || {
+    JsonlSink::builder()
+      .directory(dir.path())
+      .max_segment_size(1)
+      .build()
+      .expect("failed to build sink")
+  };
+  let mut sink = build();
+  for seq in 1..=3 {
+    sink.write(&payload(Some(seq))).expect("failed to write");
+  }
+  drop(sink);
+  // A new sink doesn't overwrite the segments of the previous one.
+  build().write(&payload(Some(4))).expect("failed to write");
+
+  let manifest = read_manifest(dir.path()).expect("failed to read manifest");
+  let seqs = manifest
+    .iter()
+    .map(|info| (info.count, info.first_seq, info.last_seq))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    seqs,
+    [
+      (1, Some(1), Some(1)),
+      (1, Some(2), Some(2)),
+      (1, Some(3), Some(3)),
+      (1, Some(4), Some(4))
+    ]
+  );
+  let lines = segments(dir.path())
+    .iter()
+    .map(|segment| read_lines(segment))
+    .collect::<Vec<_>>();
+  assert!(lines.iter().all(|lines| lines.len() == 1));
+}
+
+#[test]
+fn rotate_by_age() {
+  let dir = tempfile::tempdir().expect("failed to create dir");
+  let mut sink = JsonlSink::builder()
+    .directory(dir.path())
+    .max_segment_age(Duration::from_millis(50))
+    .build()
+    .expect("failed to build sink");
+  sink.write(&payload(Some(1))).expect("failed to write");
+  sink.write(&payload(Some(2))).expect("failed to write");
+  std::thread::sleep(Duration::from_millis(60));
+  sink.write(&payload(Some(3))).expect("failed to write");
+  drop(sink);
+
+  let counts = read_manifest(dir.path())
+    .expect("failed to read manifest")
+    .iter()
+    .map(|info| info.count)
+    .collect::<Vec<_>>();
+  assert_eq!(counts, [2, 1]);
+}
+
+#[tokio::test]
+async fn consume_until_error() {
+  let dir = tempfile::tempdir().expect("failed to create dir");
+  let mut sink = JsonlSink::builder()
+    .directory(dir.path())
+    .build()
+    .expect("failed to build sink");
+  let res = sink
+    .consume(until_error([payload(Some(1)), payload(Some(2))]))
+    .await;
+  assert!(matches!(
+    res,
+    Err(Error::Subscription(SubscriptionError::Server { .. }))
+  ));
+  // The segment is left open, and the next subscription is written to it.
+  sink
+    .consume(stream::iter([Ok::<_, SubscriptionError<Infallible>>(
+      payload(Some(3)),
+    )]))
+    .await
+    .expect("failed to consume");
+  drop(sink);
+
+  let segments = segments(dir.path());
+  assert_eq!(segments.len(), 1);
+  assert_eq!(read_lines(&segments[0]).len(), 3);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn compress_closed_segments_with_gzip() {
+  compress_closed_segments(Compression::Gzip);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn compress_closed_segments_with_zstd() {
+  compress_closed_segments(Compression::Zstd);
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn compress_closed_segments(compression: Compression) {
+  let dir = tempfile::tempdir().expect("failed to create dir");
+  let mut sink = JsonlSink::builder()
+    .directory(dir.path())
+    .compression(compression)
+    .build()
+    .expect("failed to build sink");
+  sink.write(&payload(Some(1))).expect("failed to write");
+  sink.finish().expect("failed to finish");
+
+  let segments = segments(dir.path());
+  assert_eq!(segments.len(), 1);
+  assert!(segments[0]
+    .to_string_lossy()
+    .ends_with(compression.extension()));
+  assert_eq!(read_lines(&segments[0]).len(), 1);
+  // Only the compressed segment and the manifest are left.
+  assert_eq!(
+    std::fs::read_dir(dir.path())
+      .expect("failed to read dir")
+      .count(),
+    2
+  );
+}
+
+#[cfg(not(feature = "gzip"))]
+#[test]
+fn reject_compression_without_feature() {
+  let dir = tempfile::tempdir().expect("failed to create dir");
+  let res = JsonlSink::builder()
+    .directory(dir.path())
+    .compression(Compression::Gzip)
+    .build();
+  assert!(res.is_err_and(|e| e.kind() == io::ErrorKind::Unsupported));
+}
@@ -1,4 +1,20 @@
 mod client;
 pub use client::{Error, XrpcWssClient};
 
+pub mod archive;
+#[cfg(feature = "feed-generator")]
+pub mod feed_generator;
+#[cfg(feature = "jetstream")]
+pub mod jetstream;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "jsonl")]
+pub mod jsonl;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod relay;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod subscriptions;
+#[cfg(any(test, feature = "test-server"))]
+pub mod test_server;
@@ -0,0 +1,308 @@
+//! This file provides the [`ParquetExporter`], which writes the operations of commits to
+//! Parquet files partitioned by hour and collection.
+
+use std::{
+  collections::{btree_map::Entry, BTreeMap},
+  fs::{self, File, OpenOptions},
+  io,
+  path::{Path, PathBuf},
+};
+
+use arrow_schema::ArrowError;
+use atrium_api::types::string::Nsid;
+use bon::bon;
+use chrono::Utc;
+use futures::{Stream, StreamExt};
+use parquet::{
+  arrow::ArrowWriter,
+  basic::{Compression, ZstdLevel},
+  errors::ParquetError,
+  file::properties::WriterProperties,
+};
+use tokio::sync::mpsc;
+
+use super::{record_batch, schema, OperationRow};
+use crate::{
+  atrium_xrpc_wss::subscriptions::{
+    repositories::{HandledData, ProcessedData},
+    ProcessedPayload, SubscriptionError,
+  },
+  atrium_xrpc_wss_client::{json, subscriptions::repositories::firehose::Firehose},
+};
+
+type Payload = ProcessedPayload<HandledData<Firehose>>;
+
+/// The number of payloads received ahead of the thread writing them, while consuming a subscription.
+const QUEUE_SIZE: usize = 1024;
+
+/// An error while writing the exports.
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+  #[error("IO error: {0}")]
+  Io(#[from] io::Error),
+  #[error("Parquet error: {0}")]
+  Parquet(#[from] ParquetError),
+  #[error("Arrow error: {0}")]
+  Arrow(#[from] ArrowError),
+  #[error("Record conversion error: {0}")]
+  Record(#[from] json::Error),
+}
+
+/// An error while consuming a subscription into a [`ParquetExporter`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error<E> {
+  #[error(transparent)]
+  Export(#[from] ExportError),
+  #[error(transparent)]
+  Subscription(SubscriptionError<E>),
+}
+
+/// The key of a partition, which is the hour of the commits (formatted like `2024-01-01T00`)
+/// and the collection of the operations.
+type PartitionKey = (String, String);
+
+/// The file of a partition being written, and the rows not written to it yet.
+struct Partition {
+  writer: ArrowWriter<File>,
+  rows: Vec<OperationRow>,
+}
+
+/// Writes the operations of commits to zstd compressed Parquet files, with the
+/// [`schema`](super::schema) of the exports.
+///
+/// The files are partitioned by the hour of the commits (in UTC) and the collection of the
+/// operations, in the `collection={collection}/hour={YYYY-MM-DDTHH}` directories, which most query
+/// engines recognize as partition columns. A partition is closed once a commit of a later hour is
+/// written, so late commits start a new file in the partition of their hour.
+///
+/// Rows are written in batches of `batch_size`, and the open partitions are closed when the
+/// exporter is dropped. Operations on collections which aren't valid NSIDs are skipped.
+pub struct ParquetExporter {
+  directory: PathBuf,
+  batch_size: usize,
+  properties: WriterProperties,
+  partitions: BTreeMap<PartitionKey, Partition>,
+  /// The number of files opened so far.
+  opened: u64,
+}
+
+#[bon]
+impl ParquetExporter {
+  /// Builds a new exporter, whose partitions are written under `directory`. The directories
+  /// are created as needed.
+  ///
+  /// # Errors
+  /// Returns an error if the directory could not be created.
+  #[builder]
+  pub fn new(
+    #[builder(into)] directory: PathBuf,
+    #[builder(default = 8192)] batch_size: usize,
+  ) -> io::Result<Self> {
+    fs::create_dir_all(&directory)?;
+    let properties = WriterProperties::builder()
+      .set_compression(Compression::ZSTD(ZstdLevel::default()))
+      .build();
+    Ok(Self {
+      directory,
+      batch_size,
+      properties,
+      partitions: BTreeMap::new(),
+      opened: 0,
+    })
+  }
+}
+
+impl ParquetExporter {
+  /// Writes the operations of a commit, ignoring the other payloads.
+  ///
+  /// # Errors
+  /// Returns an error if a record can't be converted, or if the rows could not be written.
+  pub fn write(&mut self, payload: &Payload) -> Result<(), ExportError> {
+    let ProcessedData::Commit(commit) = &payload.data else {
+      return Ok(());
+    };
+    let hour = commit
+      .time
+      .as_ref()
+      .with_timezone(&Utc)
+      .format("%Y-%m-%dT%H")
+      .to_string();
+    self.close_before(&hour)?;
+
+    for row in OperationRow::from_commit(payload.seq, commit)? {
+      if Nsid::new(row.collection.clone()).is_err() {
+        tracing::warn!(
+          collection = row.collection,
+          "skipping an invalid collection"
+        );
+        continue;
+      }
+      let partition = match self
+        .partitions
+        .entry((hour.clone(), row.collection.clone()))
+      {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+          let partition = open(
+            &self.directory,
+            &self.properties,
+            entry.key(),
+            &mut self.opened,
+          )?;
+          entry.insert(partition)
+        }
+      };
+      partition.rows.push(row);
+      if partition.rows.len() >= self.batch_size {
+        write_rows(partition)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Exports the commits of a subscription, up to its end or its first error.
+  ///
+  /// The rows are converted and written on a blocking thread, which the subscription can get
+  /// 1024 payloads ahead of. The open partitions are left open, so the exporter can keep
+  /// consuming a new subscription.
+  ///
+  /// # Errors
+  /// Returns the error yielded by the subscription, or an [`ExportError`] if a payload could not be written.
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The payloads are moved to the writer, so only what's left of them is dropped in a different order."
+  )]
+  pub async fn consume<E>(
+    &mut self,
+    mut subscription: impl Stream<Item = Result<Payload, SubscriptionError<E>>> + Unpin,
+  ) -> Result<(), Error<E>> {
+    let (sender, mut receiver) = mpsc::channel::<Payload>(QUEUE_SIZE);
+    let mut exporter = self.detach();
+    let writer = tokio::task::spawn_blocking(move || {
+      let mut result = Ok(());
+      while let Some(payload) = receiver.blocking_recv() {
+        result = exporter.write(&payload);
+        if result.is_err() {
+          break;
+        }
+      }
+      (exporter, result)
+    });
+
+    let mut result = Ok(());
+    while let Some(payload) = subscription.next().await {
+      match payload {
+        // The writer only stops early after an error, which is returned below.
+        Ok(payload) => {
+          if sender.send(payload).await.is_err() {
+            break;
+          }
+        }
+        Err(e) => {
+          result = Err(Error::Subscription(e));
+          break;
+        }
+      }
+    }
+    drop(sender);
+    let (mut exporter, written) = writer
+      .await
+      .map_err(|e| ExportError::from(io::Error::other(e)))?;
+    self.partitions = std::mem::take(&mut exporter.partitions);
+    self.opened = exporter.opened;
+    written?;
+    result
+  }
+
+  /// Closes every open partition, writing their remaining rows.
+  /// The next commits will be written to new files.
+  ///
+  /// # Errors
+  /// Returns an error if a partition could not be written or closed.
+  pub fn finish(&mut self) -> Result<(), ExportError> {
+    for partition in std::mem::take(&mut self.partitions).into_values() {
+      close(partition)?;
+    }
+    Ok(())
+  }
+
+  /// Moves the open partitions to a new exporter with the same settings, to write them from
+  /// another thread.
+  fn detach(&mut self) -> Self {
+    Self {
+      directory: self.directory.clone(),
+      batch_size: self.batch_size,
+      properties: self.properties.clone(),
+      partitions: std::mem::take(&mut self.partitions),
+      opened: self.opened,
+    }
+  }
+
+  /// Closes the partitions of the hours before `hour`.
+  fn close_before(&mut self, hour: &str) -> Result<(), ExportError> {
+    while let Some(entry) = self.partitions.first_entry() {
+      if entry.key().0.as_str() >= hour {
+        break;
+      }
+      close(entry.remove())?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for ParquetExporter {
+  fn drop(&mut self) {
+    // Failing to write the last rows goes unnoticed here, `finish` must be called beforehand to
+    // catch it.
+    drop(self.finish());
+  }
+}
+
+/// Opens a new file in a partition, counting it in `opened`.
+fn open(
+  directory: &Path,
+  properties: &WriterProperties,
+  (hour, collection): &PartitionKey,
+  opened: &mut u64,
+) -> Result<Partition, ExportError> {
+  let directory = directory
+    .join(format!("collection={collection}"))
+    .join(format!("hour={hour}"));
+  fs::create_dir_all(&directory)?;
+  // Named after the time they are started at, so the files of a partition sort chronologically,
+  // and the number of files opened before, since a partition can be reopened in the same
+  // microsecond.
+  let started_at = Utc::now().timestamp_micros();
+  let file = loop {
+    let file = OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .open(directory.join(format!("part-{started_at:020}-{opened:06}.parquet")));
+    *opened += 1;
+    // The name may also have been taken by another exporter, or before a restart.
+    if !file
+      .as_ref()
+      .is_err_and(|e| e.kind() == io::ErrorKind::AlreadyExists)
+    {
+      break file?;
+    }
+  };
+  Ok(Partition {
+    writer: ArrowWriter::try_new(file, schema(), Some(properties.clone()))?,
+    rows: Vec::new(),
+  })
+}
+
+fn close(mut partition: Partition) -> Result<(), ExportError> {
+  write_rows(&mut partition)?;
+  partition.writer.close()?;
+  Ok(())
+}
+
+fn write_rows(partition: &mut Partition) -> Result<(), ExportError> {
+  if !partition.rows.is_empty() {
+    partition.writer.write(&record_batch(&partition.rows)?)?;
+    partition.rows.clear();
+  }
+  Ok(())
+}
@@ -0,0 +1,229 @@
+//! This file defines the columnar representation of the operations of a commit,
+//! and the [`ParquetExporter`] which writes them to Parquet files.
+//!
+//! Every [`Operation`] becomes an [`OperationRow`], with the fields of the commit it belongs to,
+//! the record converted to JSON in the [`Dialect::Atproto`] dialect, and typed columns for the
+//! fields of the most common `app.bsky` records, which are `null` for the other collections:
+//!
+//! | Column         | Type                 | Filled for                                   |
+//! |----------------|----------------------|----------------------------------------------|
+//! | `seq`          | `Int64`              | Every operation, if the payload had a seq.   |
+//! | `time`         | `Timestamp(us, UTC)` | Every operation, with the time of the commit.|
+//! | `did`          | `Utf8`               | Every operation.                             |
+//! | `collection`   | `Utf8`               | Every operation.                             |
+//! | `rkey`         | `Utf8`               | Every operation.                             |
+//! | `action`       | `Utf8`               | Every operation.                             |
+//! | `cid`          | `Utf8`               | Creations and updates.                       |
+//! | `record`       | `Utf8`               | Creations and updates of known records.      |
+//! | `created_at`   | `Timestamp(us, UTC)` | Posts, likes, reposts, follows and blocks.   |
+//! | `text`         | `Utf8`               | Posts.                                       |
+//! | `langs`        | `List<Utf8>`         | Posts.                                       |
+//! | `reply_root`   | `Utf8`               | Replies, with the AT-URI of the thread root. |
+//! | `reply_parent` | `Utf8`               | Replies, with the AT-URI of the parent post. |
+//! | `subject_uri`  | `Utf8`               | Likes and reposts.                           |
+//! | `subject_did`  | `Utf8`               | Follows and blocks.                          |
+
+#[cfg(test)]
+mod tests;
+
+mod exporter;
+pub use exporter::{Error, ExportError, ParquetExporter};
+
+use std::sync::{Arc, LazyLock};
+
+use arrow_array::{
+  builder::{ListBuilder, StringBuilder},
+  ArrayRef, Int64Array, RecordBatch, StringArray, TimestampMicrosecondArray,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use atrium_api::{
+  record::KnownRecord,
+  types::string::{Datetime, Did},
+};
+
+use crate::atrium_xrpc_wss_client::{
+  json::{self, record_to_json, Dialect},
+  subscriptions::repositories::type_defs::{Operation, ProcessedCommitData},
+};
+
+/// The timezone of the timestamp columns.
+const UTC: &str = "UTC";
+
+/// A single operation of a commit, as written in a row of the exports.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationRow {
+  pub seq: Option<i64>,
+  /// The time of the commit, in microseconds since the Unix epoch.
+  pub time: i64,
+  pub did: String,
+  pub collection: String,
+  pub rkey: String,
+  pub action: String,
+  pub cid: Option<String>,
+  /// The record, as JSON.
+  pub record: Option<String>,
+  /// The `createdAt` of the record, in microseconds since the Unix epoch.
+  pub created_at: Option<i64>,
+  pub text: Option<String>,
+  pub langs: Option<Vec<String>>,
+  pub reply_root: Option<String>,
+  pub reply_parent: Option<String>,
+  pub subject_uri: Option<String>,
+  pub subject_did: Option<String>,
+}
+
+impl OperationRow {
+  /// Converts every operation of a commit into a row.
+  ///
+  /// # Errors
+  /// Returns an error if a record can't be converted to JSON.
+  pub fn from_commit(
+    seq: Option<i64>,
+    commit: &ProcessedCommitData,
+  ) -> Result<Vec<Self>, json::Error> {
+    let time = commit.time.as_ref().timestamp_micros();
+    commit
+      .ops
+      .iter()
+      .flatten()
+      .map(|op| Self::from_operation(seq, time, &commit.repo, op))
+      .collect()
+  }
+
+  fn from_operation(
+    seq: Option<i64>,
+    time: i64,
+    did: &Did,
+    op: &Operation,
+  ) -> Result<Self, json::Error> {
+    let (collection, rkey) = op.path.split_once('/').unwrap_or((&op.path, ""));
+    let mut row = Self {
+      seq,
+      time,
+      did: did.as_str().to_owned(),
+      collection: collection.to_owned(),
+      rkey: rkey.to_owned(),
+      action: op.action.clone(),
+      cid: op.cid.as_ref().map(|cid| cid.0.to_string()),
+      record: op
+        .record
+        .as_ref()
+        .map(|record| record_to_json(record, Dialect::Atproto).map(|json| json.to_string()))
+        .transpose()?,
+      ..Self::default()
+    };
+    if let Some(record) = &op.record {
+      row.fill_typed_columns(record);
+    }
+    Ok(row)
+  }
+
+  fn fill_typed_columns(&mut self, record: &KnownRecord) {
+    match record {
+      KnownRecord::AppBskyFeedPost(post) => {
+        self.created_at = Some(micros(&post.created_at));
+        self.text = Some(post.text.clone());
+        self.langs = post
+          .langs
+          .as_ref()
+          .map(|langs| langs.iter().map(|lang| lang.as_ref().to_string()).collect());
+        if let Some(reply) = &post.reply {
+          self.reply_root = Some(reply.root.uri.clone());
+          self.reply_parent = Some(reply.parent.uri.clone());
+        }
+      }
+      KnownRecord::AppBskyFeedLike(like) => {
+        self.created_at = Some(micros(&like.created_at));
+        self.subject_uri = Some(like.subject.uri.clone());
+      }
+      KnownRecord::AppBskyFeedRepost(repost) => {
+        self.created_at = Some(micros(&repost.created_at));
+        self.subject_uri = Some(repost.subject.uri.clone());
+      }
+      KnownRecord::AppBskyGraphFollow(follow) => {
+        self.created_at = Some(micros(&follow.created_at));
+        self.subject_did = Some(follow.subject.as_str().to_owned());
+      }
+      KnownRecord::AppBskyGraphBlock(block) => {
+        self.created_at = Some(micros(&block.created_at));
+        self.subject_did = Some(block.subject.as_str().to_owned());
+      }
+      _ => {}
+    }
+  }
+}
+
+fn micros(datetime: &Datetime) -> i64 {
+  datetime.as_ref().timestamp_micros()
+}
+
+/// The schema of the [`OperationRow`]s, described in the [module](self) documentation.
+#[must_use]
+pub fn schema() -> SchemaRef {
+  static SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    let timestamp = DataType::Timestamp(TimeUnit::Microsecond, Some(UTC.into()));
+    let string = |name: &str, nullable: bool| Field::new(name, DataType::Utf8, nullable);
+    Arc::new(Schema::new(vec![
+      Field::new("seq", DataType::Int64, true),
+      Field::new("time", timestamp.clone(), false),
+      string("did", false),
+      string("collection", false),
+      string("rkey", false),
+      string("action", false),
+      string("cid", true),
+      string("record", true),
+      Field::new("created_at", timestamp, true),
+      string("text", true),
+      Field::new_list("langs", Field::new_list_field(DataType::Utf8, true), true),
+      string("reply_root", true),
+      string("reply_parent", true),
+      string("subject_uri", true),
+      string("subject_did", true),
+    ]))
+  });
+  Arc::clone(&SCHEMA)
+}
+
+/// Converts rows into a [`RecordBatch`] with the [`schema`] of the exports.
+///
+/// # Errors
+/// Returns an error if the batch doesn't match the schema, which shouldn't happen.
+pub fn record_batch(rows: &[OperationRow]) -> Result<RecordBatch, ArrowError> {
+  let strings = |f: fn(&OperationRow) -> Option<&str>| -> ArrayRef {
+    Arc::new(rows.iter().map(f).collect::<StringArray>())
+  };
+  let timestamps = |f: fn(&OperationRow) -> Option<i64>| -> ArrayRef {
+    Arc::new(
+      rows
+        .iter()
+        .map(f)
+        .collect::<TimestampMicrosecondArray>()
+        .with_timezone(UTC),
+    )
+  };
+  let mut langs = ListBuilder::new(StringBuilder::new());
+  for row in rows {
+    langs.append_option(row.langs.as_ref().map(|langs| langs.iter().map(Some)));
+  }
+
+  RecordBatch::try_new(
+    schema(),
+    vec![
+      Arc::new(rows.iter().map(|row| row.seq).collect::<Int64Array>()),
+      timestamps(|row| Some(row.time)),
+      strings(|row| Some(&row.did)),
+      strings(|row| Some(&row.collection)),
+      strings(|row| Some(&row.rkey)),
+      strings(|row| Some(&row.action)),
+      strings(|row| row.cid.as_deref()),
+      strings(|row| row.record.as_deref()),
+      timestamps(|row| row.created_at),
+      strings(|row| row.text.as_deref()),
+      Arc::new(langs.finish()),
+      strings(|row| row.reply_root.as_deref()),
+      strings(|row| row.reply_parent.as_deref()),
+      strings(|row| row.subject_uri.as_deref()),
+      strings(|row| row.subject_did.as_deref()),
+    ],
+  )
+}
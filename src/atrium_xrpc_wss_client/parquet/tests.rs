@@ -0,0 +1,216 @@
+use std::{convert::Infallible, fs::File, path::Path};
+
+use arrow_array::{cast::AsArray, types::Int64Type, Array};
+use atrium_api::app::bsky::feed::post;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+use super::*;
+use crate::{
+  atrium_xrpc_wss::subscriptions::SubscriptionError,
+  atrium_xrpc_wss_client::subscriptions::repositories::{
+    car::tests as car,
+    fixtures::{self, commit, commit_data, create, delete, follow, strong_ref},
+  },
+};
+
+fn post() -> KnownRecord {
+  KnownRecord::from(post::RecordData {
+    reply: Some(
+      post::ReplyRefData {
+        parent: strong_ref("at://did:plc:xyz/app.bsky.feed.post/2"),
+        root: strong_ref("at://did:plc:xyz/app.bsky.feed.post/1"),
+      }
+      .into(),
+    ),
+    ..fixtures::post_data("Hello", &["en"])
+  })
+}
+
+#[test]
+fn convert_commit_to_rows() {
+  let commit = commit_data(
+    "did:plc:abc",
+    "2024-01-01T00:00:01.000Z",
+    vec![
+      create("app.bsky.feed.post/1", post()),
+      create("app.bsky.graph.follow/2", follow("did:plc:xyz")),
+      delete("app.bsky.feed.like/3"),
+    ],
+  );
+  let rows = OperationRow::from_commit(Some(7), &commit).expect("failed to convert");
+  assert_eq!(rows.len(), 3);
+
+  let post = &rows[0];
+  assert_eq!(post.seq, Some(7));
+  assert_eq!(post.time, 1_704_067_201_000_000);
+  assert_eq!(post.did, "did:plc:abc");
+  assert_eq!(
+    (post.collection.as_str(), post.rkey.as_str()),
+    ("app.bsky.feed.post", "1")
+  );
+  assert_eq!(post.cid, Some(car::cid(2).to_string()));
+  assert!(post
+    .record
+    .as_deref()
+    .is_some_and(|record| record.contains(r#""$type":"app.bsky.feed.post""#)));
+  assert_eq!(post.created_at, Some(1_704_067_200_000_000));
+  assert_eq!(post.text.as_deref(), Some("Hello"));
+  assert_eq!(post.langs, Some(vec![String::from("en")]));
+  assert_eq!(
+    post.reply_root.as_deref(),
+    Some("at://did:plc:xyz/app.bsky.feed.post/1")
+  );
+  assert_eq!(
+    post.reply_parent.as_deref(),
+    Some("at://did:plc:xyz/app.bsky.feed.post/2")
+  );
+
+  assert_eq!(rows[1].subject_did.as_deref(), Some("did:plc:xyz"));
+  assert_eq!(rows[1].text, None);
+
+  assert_eq!(rows[2].action, "delete");
+  assert_eq!(
+    (&rows[2].cid, &rows[2].record, rows[2].created_at),
+    (&None, &None, None)
+  );
+}
+
+#[test]
+fn convert_rows_to_batch() {
+  let commit = commit_data(
+    "did:plc:abc",
+    "2024-01-01T00:00:01.000Z",
+    vec![
+      create("app.bsky.feed.post/1", post()),
+      delete("app.bsky.feed.like/3"),
+    ],
+  );
+  let rows = OperationRow::from_commit(None, &commit).expect("failed to convert");
+  let batch = record_batch(&rows).expect("failed to convert");
+  assert_eq!(batch.schema(), schema());
+  assert_eq!(batch.num_rows(), 2);
+  assert_eq!(
+    batch
+      .column_by_name("seq")
+      .expect("missing seq")
+      .null_count(),
+    2
+  );
+  let langs = batch
+    .column_by_name("langs")
+    .expect("missing langs")
+    .as_list::<i32>();
+  assert_eq!(langs.value(0).as_string::<i32>().value(0), "en");
+  assert!(langs.is_null(1));
+}
+
+fn read_seqs(path: &Path) -> Vec<i64> {
+  let file = File::open(path).expect("failed to open file");
+  ParquetRecordBatchReaderBuilder::try_new(file)
+    .and_then(ParquetRecordBatchReaderBuilder::build)
+    .expect("failed to read file")
+    .flat_map(|batch| {
+      let batch = batch.expect("failed to read batch");
+      let seqs = batch.column_by_name("seq").expect("missing seq");
+      seqs.as_primitive::<Int64Type>().values().to_vec()
+    })
+    .collect()
+}
+
+fn files(directory: &Path) -> Vec<std::path::PathBuf> {
+  let mut files = std::fs::read_dir(directory)
+    .map(|entries| {
+      entries
+        .map(|entry| entry.expect("failed to read entry").path())
+        .collect::<Vec<_>>()
+    })
+    .unwrap_or_default();
+  files.sort();
+  files
+}
+
+#[test]
+fn export_partitioned_by_hour_and_collection() {
+  let dir = tempfile::tempdir().expect("failed to create dir");
+  let mut exporter = ParquetExporter::builder()
+    .directory(dir.path())
+    .batch_size(1)
+    .build()
+    .expect("failed to build exporter");
+  let payloads = [
+    (1, "2024-01-01T00:00:01.000Z", "app.bsky.feed.post/1"),
+    (2, "2024-01-01T00:59:59.000Z", "app.bsky.graph.follow/2"),
+    (3, "2024-01-01T00:59:59.000Z", "app.bsky.feed.post/3"),
+    (4, "2024-01-01T01:00:00.000Z", "app.bsky.feed.post/4"),
+    (5, "2024-01-01T01:00:00.000Z", "not a collection/5"),
+  ];
+  for (seq, time, path) in payloads {
+    let record = if path.starts_with("app.bsky.graph.follow") {
+      follow("did:plc:xyz")
+    } else {
+      post()
+    };
+    let payload = commit(seq, "did:plc:abc", time, vec![create(path, record)]);
+    exporter.write(&payload).expect("failed to write");
+  }
+
+  // The partitions of the first hour were closed by the first commit of the second one.
+  let posts = dir.path().join("collection=app.bsky.feed.post");
+  let first_hour = files(&posts.join("hour=2024-01-01T00"));
+  assert_eq!(first_hour.len(), 1);
+  assert_eq!(read_seqs(&first_hour[0]), [1, 3]);
+  let follows = files(
+    &dir
+      .path()
+      .join("collection=app.bsky.graph.follow/hour=2024-01-01T00"),
+  );
+  assert_eq!(read_seqs(&follows[0]), [2]);
+
+  exporter.finish().expect("failed to finish");
+  let second_hour = files(&posts.join("hour=2024-01-01T01"));
+  assert_eq!(second_hour.len(), 1);
+  assert_eq!(read_seqs(&second_hour[0]), [4]);
+  // Only the valid collections have a partition.
+  assert_eq!(files(dir.path()).len(), 2);
+}
+
+#[tokio::test]
+async fn consume_late_commits() {
+  let dir = tempfile::tempdir().expect("failed to create dir");
+  let mut exporter = ParquetExporter::builder()
+    .directory(dir.path())
+    .build()
+    .expect("failed to build exporter");
+  let payloads = [
+    (1, "2024-01-01T00:00:00.000Z"),
+    (2, "2024-01-01T01:00:00.000Z"),
+    (3, "2024-01-01T00:30:00.000Z"),
+  ]
+  .map(|(seq, time)| {
+    let path = format!("app.bsky.feed.post/{seq}");
+    Ok::<_, SubscriptionError<Infallible>>(commit(
+      seq,
+      "did:plc:abc",
+      time,
+      vec![create(&path, post())],
+    ))
+  });
+  exporter
+    .consume(futures::stream::iter(payloads))
+    .await
+    .expect("failed to consume");
+  // The partitions are left open by `consume`.
+  exporter.finish().expect("failed to finish");
+
+  // The late commit started a new file in the partition of its hour.
+  let first_hour = files(
+    &dir
+      .path()
+      .join("collection=app.bsky.feed.post/hour=2024-01-01T00"),
+  );
+  let seqs = first_hour
+    .iter()
+    .map(|file| read_seqs(file))
+    .collect::<Vec<_>>();
+  assert_eq!(seqs, [[1], [3]]);
+}
@@ -0,0 +1,687 @@
+//! This file provides the [`RelayServer`], which re-emits raw `com.atproto.sync.subscribeRepos` frames
+//! to downstream clients, backfilling them from a persisted frame log.
+//!
+//! The frames are published once, typically as they are received from an upstream connection,
+//! and are broadcast right away, while a blocking thread appends them to an [`archive`](super::archive).
+//! Publishing waits when the log falls too far behind, so the frames kept in memory stay bounded.
+//! The log is split into segments, indexed by the first `seq` they hold, and the oldest segments can
+//! be deleted to bound its size. Clients connecting with a `cursor` receive every frame after it,
+//! following the [event stream specs](https://atproto.com/specs/event-stream):
+//! - A `cursor` greater than the last `seq` gets a `FutureCursor` error frame, and is disconnected.
+//! - A `cursor` older than the log gets an `OutdatedCursor` info frame, followed by the whole log.
+//! - A client falling behind by more than its queue gets a `ConsumerTooSlow` error frame, and is disconnected.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+  collections::VecDeque,
+  fs::{self, File, OpenOptions},
+  io::{self, BufReader, Seek},
+  net::SocketAddr,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex, PoisonError},
+};
+
+use atrium_api::com::atproto::sync::subscribe_repos::{self, InfoData};
+use bon::bon;
+use bytes::Bytes;
+use chrono::Utc;
+use futures::{stream, SinkExt, StreamExt};
+use tokio::{
+  net::{TcpListener, TcpStream},
+  sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+  },
+  task::JoinHandle,
+};
+use tokio_tungstenite::{
+  accept_hdr_async,
+  tungstenite::{
+    self,
+    handshake::server::{Request, Response},
+    Message,
+  },
+  WebSocketStream,
+};
+
+use super::archive::{read_magic, ArchiveRecord, Recorder, Replay, EXTENSION};
+use crate::atrium_xrpc_wss::{client::XrpcUri, subscriptions::frames::Frame};
+
+/// The prefix of the names of the log segments.
+const PREFIX: &str = "relay";
+
+/// How many published frames can wait to be written to the log.
+const QUEUE_SIZE: usize = 1024;
+
+/// A frame, along with its `seq` if it has one.
+#[derive(Debug, Clone)]
+struct Entry {
+  seq: Option<i64>,
+  received_at: i64,
+  frame: Bytes,
+}
+
+/// A segment of the frame log.
+#[derive(Debug)]
+struct LogSegment {
+  path: PathBuf,
+  /// The `seq` of its first frame having one.
+  first_seq: Option<i64>,
+}
+
+/// The index of the frame log, the recent frames kept in memory for backfilling, and the channel
+/// to the connected clients.
+struct Shared {
+  /// The segments of the log, from the oldest one.
+  segments: VecDeque<LogSegment>,
+  /// The last frames published, up to the `window`, and the ones not written to the log yet.
+  recent: VecDeque<Entry>,
+  window: usize,
+  /// The number of frames published so far.
+  published: u64,
+  /// The number of frames written to the log so far.
+  written: u64,
+  last_seq: Option<i64>,
+  /// The error which stopped the writing of the log, if any.
+  failed: Option<Arc<io::Error>>,
+  sender: broadcast::Sender<Entry>,
+}
+
+impl Shared {
+  fn lock(shared: &Mutex<Self>) -> std::sync::MutexGuard<'_, Self> {
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  fn push(&mut self, entry: Entry) {
+    self.last_seq = entry.seq.or(self.last_seq);
+    self.published += 1;
+    self.recent.push_back(entry);
+    self.trim();
+  }
+
+  /// Drops the recent frames beyond the `window`, once they are written to the log.
+  fn trim(&mut self) {
+    while self.recent.len() > self.window
+      && self.published - (self.recent.len() as u64) < self.written
+    {
+      self.recent.pop_front();
+    }
+  }
+
+  /// Marks `count` more frames as written, along with the segments they were `logged` in and
+  /// their `seq`s.
+  ///
+  /// Returns `true` if a segment was started.
+  fn write(&mut self, count: usize, logged: Vec<(PathBuf, Option<i64>)>) -> bool {
+    self.written += count as u64;
+    self.trim();
+    let mut started = false;
+    for (path, seq) in logged {
+      match self.segments.back_mut() {
+        Some(segment) if segment.path == path => segment.first_seq = segment.first_seq.or(seq),
+        _ => {
+          self.segments.push_back(LogSegment {
+            path,
+            first_seq: seq,
+          });
+          started = true;
+        }
+      }
+    }
+    started
+  }
+
+  /// The first `seq` which can be backfilled, from the log or from memory.
+  fn first_seq(&self) -> Option<i64> {
+    self
+      .segments
+      .iter()
+      .find_map(|segment| segment.first_seq)
+      .or_else(|| self.recent.iter().find_map(|entry| entry.seq))
+  }
+
+  /// The paths of the segments holding the frames from `from_seq`.
+  fn segments_from(&self, from_seq: Option<i64>) -> Vec<PathBuf> {
+    let start = from_seq
+      .and_then(|from| {
+        self
+          .segments
+          .iter()
+          .rposition(|segment| segment.first_seq.is_some_and(|first| first <= from))
+      })
+      .unwrap_or_default();
+    self
+      .segments
+      .range(start..)
+      .map(|segment| segment.path.clone())
+      .collect()
+  }
+}
+
+/// A `WebSocket` server re-emitting the published frames to its clients, with cursor backfill.
+///
+/// The server stops when dropped, and the frames still being written to the log are written in the
+/// background. Call [`close`](Self::close) to wait for them.
+pub struct RelayServer {
+  host: String,
+  shared: Arc<Mutex<Shared>>,
+  /// The queue of the frames to write to the log.
+  log: mpsc::Sender<Entry>,
+  writer: Option<JoinHandle<Result<(), Arc<io::Error>>>>,
+  task: JoinHandle<()>,
+}
+
+#[bon]
+impl RelayServer {
+  /// Binds the server to the `addr`, logging the frames to the `directory`.
+  ///
+  /// The log is split into segments of `max_segment_size` bytes (defaults to 64 MiB). When a
+  /// segment is started, the oldest ones are deleted until the log fits in `max_log_size` bytes,
+  /// if it's set. Only the last segment is read back, the others are indexed by their first `seq`
+  /// and read when backfilling clients. If the last segment ends with a partial frame, as left by a
+  /// crash, it's truncated to its last complete one. The last `window` frames are also kept in memory
+  /// (defaults to 10 000), and each client has a queue of `client_capacity` frames (defaults to
+  /// 10 000).
+  ///
+  /// # Errors
+  /// Returns an error if the log could not be read or created, or if the server could not be bound.
+  #[builder(finish_fn = bind)]
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The accept loop only drops join handles and pending accepts, in any order."
+  )]
+  pub async fn new(
+    addr: SocketAddr,
+    #[builder(into)] directory: PathBuf,
+    #[builder(default = 10_000)] window: usize,
+    #[builder(default = 10_000)] client_capacity: usize,
+    #[builder(default = 64 * 1024 * 1024)] max_segment_size: u64,
+    max_log_size: Option<u64>,
+  ) -> io::Result<Self> {
+    let recorder = Recorder::builder()
+      .directory(&directory)
+      .prefix(PREFIX)
+      .max_segment_size(max_segment_size)
+      .build()?;
+    let (segments, entries) = tokio::task::spawn_blocking(move || read_log(&directory))
+      .await
+      .map_err(io::Error::other)??;
+    let (sender, _) = broadcast::channel(client_capacity.max(1));
+    let mut shared = Shared {
+      last_seq: segments.iter().rev().find_map(|segment| segment.first_seq),
+      segments,
+      recent: VecDeque::new(),
+      window,
+      published: 0,
+      written: 0,
+      failed: None,
+      sender,
+    };
+    for entry in entries {
+      shared.push(entry);
+    }
+    shared.written = shared.published;
+    shared.trim();
+
+    let listener = TcpListener::bind(addr).await?;
+    let host = listener.local_addr()?.to_string();
+    let shared = Arc::new(Mutex::new(shared));
+    let task_shared = Arc::clone(&shared);
+    let task = tokio::spawn(async move {
+      while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve(stream, Arc::clone(&task_shared)));
+      }
+    });
+
+    let (log, mut receiver) = mpsc::channel(QUEUE_SIZE);
+    let writer_shared = Arc::clone(&shared);
+    let writer = tokio::task::spawn_blocking(move || {
+      write_log(recorder, &mut receiver, &writer_shared, max_log_size).map_err(|e| {
+        // Set before the queue is closed, so that publishing reports it.
+        let e = Arc::new(e);
+        Shared::lock(&writer_shared).failed = Some(Arc::clone(&e));
+        e
+      })
+    });
+
+    Ok(Self {
+      host,
+      shared,
+      log,
+      writer: Some(writer),
+      task,
+    })
+  }
+}
+
+impl RelayServer {
+  /// The `host:port` the server is listening on.
+  #[must_use]
+  pub fn host(&self) -> &str {
+    &self.host
+  }
+
+  /// The [`XrpcUri`] clients should connect to, without TLS.
+  #[must_use]
+  pub fn xrpc_uri(&self) -> XrpcUri<'_> {
+    XrpcUri::insecure(&self.host, subscribe_repos::NSID)
+  }
+
+  /// The number of clients currently connected.
+  #[must_use]
+  pub fn clients(&self) -> usize {
+    Shared::lock(&self.shared).sender.receiver_count()
+  }
+
+  /// Publishes a binary frame, sending it to the connected clients.
+  ///
+  /// The frame is appended to the log on a blocking thread, and kept in memory until it's written.
+  /// When 1024 frames are already waiting to be written, this waits for the log to catch up.
+  ///
+  /// # Errors
+  /// Returns an error if a previous frame could not be written to the log.
+  pub async fn publish(&self, frame: Bytes) -> io::Result<()> {
+    let entry = Entry {
+      seq: seq_of(&frame),
+      received_at: Utc::now().timestamp_micros(),
+      frame,
+    };
+    // Reserved before taking the lock, which can't be held while waiting.
+    let permit = self.log.reserve().await;
+    let mut shared = Shared::lock(&self.shared);
+    if let Some(e) = &shared.failed {
+      return Err(io::Error::new(e.kind(), Arc::clone(e)));
+    }
+    // Queued while holding the lock, so that the log is written in the order of the recent frames.
+    permit
+      .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?
+      .send(entry.clone());
+    shared.push(entry.clone());
+    // There may be no client connected, which is fine.
+    shared.sender.send(entry).ok();
+    drop(shared);
+    Ok(())
+  }
+
+  /// Stops the server, and waits for the published frames to be written to the log.
+  ///
+  /// # Errors
+  /// Returns an error if a frame could not be written to the log, or if the log could not be closed.
+  pub async fn close(mut self) -> io::Result<()> {
+    let writer = self.writer.take();
+    // Closes the queue of the writer.
+    drop(self);
+    match writer {
+      Some(writer) => writer
+        .await
+        .map_err(io::Error::other)?
+        .map_err(|e| io::Error::new(e.kind(), e)),
+      None => Ok(()),
+    }
+  }
+}
+
+impl Drop for RelayServer {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
+
+fn seq_of(frame: &Bytes) -> Option<i64> {
+  Frame::try_from(frame.clone()).ok().and_then(|f| f.seq())
+}
+
+fn is_segment(path: &Path) -> bool {
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  name.starts_with(&format!("{PREFIX}-")) && name.ends_with(&format!(".{EXTENSION}"))
+}
+
+/// Opens a segment of the log, checking its magic bytes.
+fn open_segment(path: &Path) -> io::Result<BufReader<File>> {
+  let mut reader = BufReader::new(File::open(path)?);
+  read_magic(&mut reader)?;
+  Ok(reader)
+}
+
+/// Indexes the segments of the log in the `directory`, reading back the frames of the last one.
+#[expect(
+  tail_expr_drop_order,
+  reason = "The readers of the segments are only closed, in any order."
+)]
+fn read_log(directory: &Path) -> io::Result<(VecDeque<LogSegment>, Vec<Entry>)> {
+  let mut paths = fs::read_dir(directory)?
+    .map(|entry| entry.map(|e| e.path()))
+    .filter(|path| path.as_ref().map_or(true, |p| is_segment(p)))
+    .collect::<io::Result<Vec<_>>>()?;
+  paths.sort();
+  // A segment left without any complete frame by a crash is removed, the previous one is the last.
+  let mut last = None;
+  while let Some(path) = paths.pop() {
+    if let Some(entries) = recover_segment(&path)? {
+      last = Some((path, entries));
+      break;
+    }
+    fs::remove_file(&path)?;
+  }
+  let Some((last, entries)) = last else {
+    return Ok((VecDeque::new(), Vec::new()));
+  };
+
+  let mut segments = VecDeque::with_capacity(paths.len() + 1);
+  for path in paths {
+    let mut reader = open_segment(&path)?;
+    let mut first_seq = None;
+    while let Some(record) = ArchiveRecord::read_from(&mut reader)? {
+      if record.seq.is_some() {
+        first_seq = record.seq;
+        break;
+      }
+    }
+    segments.push_back(LogSegment { path, first_seq });
+  }
+
+  segments.push_back(LogSegment {
+    path: last,
+    first_seq: entries.iter().find_map(|entry| entry.seq),
+  });
+  Ok((segments, entries))
+}
+
+/// Reads back the frames of the last segment of the log, which may have been torn by a crash.
+///
+/// A partial frame at its end is truncated, so that only complete frames are backfilled. Returns
+/// `None` if not even the magic bytes were written.
+#[expect(
+  tail_expr_drop_order,
+  reason = "The segment is truncated through its own handle, so the reader can be closed before or after the records."
+)]
+fn recover_segment(path: &Path) -> io::Result<Option<Vec<Entry>>> {
+  let mut reader = BufReader::new(File::open(path)?);
+  match read_magic(&mut reader) {
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+    res => res?,
+  }
+  let mut entries = Vec::new();
+  // The end of the last complete frame.
+  let mut end = reader.stream_position()?;
+  loop {
+    match ArchiveRecord::read_from(&mut reader) {
+      Ok(Some(record)) => {
+        entries.push(Entry {
+          seq: record.seq,
+          received_at: record.received_at,
+          frame: record.frame,
+        });
+        end = reader.stream_position()?;
+      }
+      Ok(None) => break,
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+        OpenOptions::new().write(true).open(path)?.set_len(end)?;
+        break;
+      }
+      Err(e) => return Err(e),
+    }
+  }
+  Ok(Some(entries))
+}
+
+/// Appends the published frames to the log, until the queue is closed.
+///
+/// The frames are written in batches, and each batch is flushed before its frames can be dropped
+/// from memory, so that clients can be backfilled from the log.
+#[expect(
+  tail_expr_drop_order,
+  reason = "The batches are flushed explicitly, and their buffers can be freed in any order."
+)]
+fn write_log(
+  mut recorder: Recorder,
+  receiver: &mut mpsc::Receiver<Entry>,
+  shared: &Mutex<Shared>,
+  max_log_size: Option<u64>,
+) -> io::Result<()> {
+  // The segment being written, and whether a frame with a `seq` was written to it.
+  let mut current: Option<(PathBuf, bool)> = None;
+  while let Some(entry) = receiver.blocking_recv() {
+    let mut batch = vec![entry];
+    while let Ok(entry) = receiver.try_recv() {
+      batch.push(entry);
+    }
+    // The segments started by the batch, and the first `seq`s written to them.
+    let mut logged = Vec::new();
+    for entry in &batch {
+      recorder.record(&ArchiveRecord {
+        received_at: entry.received_at,
+        seq: entry.seq,
+        frame: entry.frame.clone(),
+      })?;
+      let Some(path) = recorder.segment() else {
+        continue;
+      };
+      match &mut current {
+        Some((current, has_seq)) if current.as_path() == path => {
+          if !*has_seq && entry.seq.is_some() {
+            *has_seq = true;
+            logged.push((path.to_owned(), entry.seq));
+          }
+        }
+        _ => {
+          current = Some((path.to_owned(), entry.seq.is_some()));
+          logged.push((path.to_owned(), entry.seq));
+        }
+      }
+    }
+    recorder.flush()?;
+
+    let started = Shared::lock(shared).write(batch.len(), logged);
+    if let Some(max_size) = max_log_size.filter(|_| started) {
+      expire(shared, max_size)?;
+    }
+  }
+  recorder.finish()
+}
+
+/// Deletes the oldest segments of the log, but not the one being written, until it fits in
+/// `max_size` bytes.
+fn expire(shared: &Mutex<Shared>, max_size: u64) -> io::Result<()> {
+  let paths = Shared::lock(shared)
+    .segments
+    .iter()
+    .map(|segment| segment.path.clone())
+    .collect::<Vec<_>>();
+  let sizes = paths
+    .iter()
+    .map(|path| fs::metadata(path).map(|metadata| metadata.len()))
+    .collect::<io::Result<Vec<_>>>()?;
+  let mut size = sizes.iter().sum::<u64>();
+  let mut expired = 0;
+  while size > max_size && expired + 1 < sizes.len() {
+    size -= sizes[expired];
+    expired += 1;
+  }
+
+  // Removed from the index first, so that new clients don't try to read them.
+  Shared::lock(shared).segments.drain(..expired);
+  paths[..expired].iter().try_for_each(fs::remove_file)
+}
+
+type Connection = WebSocketStream<TcpStream>;
+
+async fn send(ws: &mut Connection, frame: &Frame) -> bool {
+  match frame.encode() {
+    Ok(data) => ws.send(Message::Binary(data.into())).await.is_ok(),
+    Err(_) => false,
+  }
+}
+
+/// Sends an error frame, and closes the connection.
+async fn send_error(ws: &mut Connection, error: &str) {
+  let frame = Frame::Error {
+    error: error.to_owned(),
+    message: None,
+  };
+  if send(ws, &frame).await {
+    drop(ws.close(None).await);
+  }
+}
+
+/// Serves the frames to a single client.
+async fn serve(stream: TcpStream, shared: Arc<Mutex<Shared>>) {
+  let mut cursor = None;
+  #[expect(
+    clippy::result_large_err,
+    reason = "The signature is defined by `tungstenite`."
+  )]
+  let callback = |request: &Request, response: Response| {
+    cursor = request
+      .uri()
+      .query()
+      .and_then(|q| serde_html_form::from_str::<subscribe_repos::ParametersData>(q).ok())
+      .and_then(|p| p.cursor);
+    Ok(response)
+  };
+  let Ok(mut ws) = accept_hdr_async(stream, callback).await else {
+    return;
+  };
+
+  // The recent frames are collected while holding the lock, so that none is missed.
+  let (recent, first_seq, last_seq, mut receiver) = {
+    let shared = Shared::lock(&shared);
+    (
+      shared.recent.clone(),
+      shared.first_seq(),
+      shared.last_seq,
+      shared.sender.subscribe(),
+    )
+  };
+
+  // The `seq` of the last frame sent, to skip the ones sent twice while switching from the backfill.
+  let mut last_sent = None;
+  if let Some(cursor) = cursor {
+    if cursor > last_seq.unwrap_or_default() {
+      send_error(&mut ws, "FutureCursor").await;
+      return;
+    }
+    if first_seq.is_some_and(|first| cursor < first - 1) {
+      let info = Frame::message(
+        "#info",
+        &InfoData {
+          message: Some(String::from("Cursor is older than the frame log")),
+          name: String::from("OutdatedCursor"),
+        },
+      );
+      if !matches!(info, Ok(info) if send(&mut ws, &info).await) {
+        return;
+      }
+    }
+    last_sent = Some(cursor);
+    if !backfill(&mut ws, &shared, &recent, &mut last_sent).await {
+      return;
+    }
+  }
+
+  loop {
+    tokio::select! {
+      entry = receiver.recv() => match entry {
+        Ok(Entry { seq, frame, .. }) => {
+          if seq.zip(last_sent).is_some_and(|(seq, last)| seq <= last) {
+            continue;
+          }
+          if ws.send(Message::Binary(frame.into())).await.is_err() {
+            return;
+          }
+          last_sent = seq.or(last_sent);
+        }
+        Err(RecvError::Lagged(_)) => {
+          send_error(&mut ws, "ConsumerTooSlow").await;
+          return;
+        }
+        Err(RecvError::Closed) => break,
+      },
+      message = ws.next() => match message {
+        // Pings are answered while reading, and other messages are ignored.
+        Some(Ok(_)) => {}
+        Some(Err(_)) | None => return,
+      },
+    }
+  }
+  drop(ws.close(None).await);
+}
+
+/// Sends the frames after `last_sent`, from the log and then from the `recent` frames.
+///
+/// Returns `false` if the connection was dropped.
+async fn backfill(
+  ws: &mut Connection,
+  shared: &Mutex<Shared>,
+  recent: &VecDeque<Entry>,
+  last_sent: &mut Option<i64>,
+) -> bool {
+  let recent_start = recent.iter().find_map(|entry| entry.seq);
+  let from_seq = last_sent.map(|seq| seq + 1);
+  if recent_start.is_none_or(|start| from_seq.is_some_and(|from| from < start)) {
+    let Ok(mut replays) = Shared::lock(shared)
+      .segments_from(from_seq)
+      .into_iter()
+      .map(|path| {
+        Replay::builder()
+          .path(path)
+          .maybe_from_seq(from_seq)
+          .build()
+      })
+      .collect::<io::Result<Vec<_>>>()
+    else {
+      return false;
+    };
+    // Only the segment being written may end with a partial frame, which is in the recent frames.
+    let current = replays.pop();
+    let mut log = stream::iter(replays)
+      .map(|replay| (replay, false))
+      .chain(stream::iter(current.map(|replay| (replay, true))))
+      .flat_map(|(replay, current)| replay.into_stream().map(move |message| (message, current)));
+    // The log is read until the recent frames.
+    loop {
+      let message = match log.next().await {
+        Some((Ok(message), _)) => message,
+        None => break,
+        Some((Err(tungstenite::Error::Io(e)), true))
+          if e.kind() == io::ErrorKind::UnexpectedEof =>
+        {
+          break
+        }
+        // A segment may have been deleted since, or be corrupted, which would leave a gap.
+        Some((Err(_), _)) => return false,
+      };
+      let Message::Binary(data) = message else {
+        continue;
+      };
+      let frame = Bytes::from(data);
+      let seq = seq_of(&frame);
+      if seq
+        .zip(recent_start)
+        .is_some_and(|(seq, start)| seq >= start)
+      {
+        break;
+      }
+      if ws.send(Message::Binary(frame.into())).await.is_err() {
+        return false;
+      }
+      *last_sent = seq.or(*last_sent);
+    }
+  }
+
+  for Entry { seq, frame, .. } in recent {
+    let Some(seq) = *seq else {
+      continue;
+    };
+    if last_sent.is_some_and(|last| seq <= last) {
+      continue;
+    }
+    if ws.send(Message::Binary(frame.to_vec())).await.is_err() {
+      return false;
+    }
+    *last_sent = Some(seq);
+  }
+  true
+}
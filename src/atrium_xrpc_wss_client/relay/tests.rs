@@ -0,0 +1,268 @@
+use std::{path::Path, time::Duration};
+
+use atrium_api::com::atproto::sync::subscribe_repos::{self, ParametersData};
+use futures::{
+  channel::mpsc::{self, UnboundedReceiver},
+  StreamExt,
+};
+
+use super::*;
+use crate::{
+  atrium_xrpc_wss::{
+    client::WssClient,
+    subscriptions::{
+      repositories::{self, HandledData, InfoName, ProcessedData, Repositories},
+      ProcessedPayload, SubscriptionError,
+    },
+  },
+  atrium_xrpc_wss_client::{
+    subscriptions::repositories::firehose::Firehose, test_server::tests::commit, XrpcWssClient,
+  },
+};
+
+type Item = Result<ProcessedPayload<HandledData<Firehose>>, SubscriptionError<repositories::Error>>;
+
+async fn bind(directory: &Path, window: usize, client_capacity: usize) -> RelayServer {
+  RelayServer::builder()
+    .addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+    .directory(directory)
+    .window(window)
+    .client_capacity(client_capacity)
+    // Every frame is written to its own segment.
+    .max_segment_size(1)
+    .bind()
+    .await
+    .expect("failed to bind")
+}
+
+async fn publish(server: &RelayServer, seqs: impl IntoIterator<Item = i64>) {
+  for seq in seqs {
+    let frame = commit(seq, false).encode().expect("failed to serialize");
+    server.publish(frame).await.expect("failed to publish");
+  }
+}
+
+/// Waits for the published frames to be written to the log.
+async fn wait_written(server: &RelayServer) {
+  let unwritten = || {
+    let shared = Shared::lock(&server.shared);
+    shared.published - shared.written
+  };
+  while unwritten() > 0 {
+    tokio::time::sleep(Duration::from_millis(10)).await;
+  }
+}
+
+/// Subscribes to the server, forwarding the items from a separate task, since the connection borrows the client.
+fn subscribe(server: &RelayServer, cursor: Option<i64>) -> UnboundedReceiver<Item> {
+  let host = server.host().to_owned();
+  let (sender, receiver) = mpsc::unbounded();
+  tokio::spawn(async move {
+    let client = XrpcWssClient::builder()
+      .xrpc_uri(XrpcUri::insecure(&host, subscribe_repos::NSID))
+      .params(ParametersData { cursor })
+      .build();
+    let connection = client.connect().await.expect("failed to connect");
+    let subscription = Repositories::builder()
+      .connection(connection)
+      .handler(Firehose::default())
+      .build();
+    subscription.map(Ok).forward(sender).await.ok();
+  });
+  receiver
+}
+
+/// Reads the next `n` items, with a timeout.
+async fn read(subscription: &mut UnboundedReceiver<Item>, n: usize) -> Vec<Item> {
+  tokio::time::timeout(
+    Duration::from_secs(5),
+    subscription.by_ref().take(n).collect(),
+  )
+  .await
+  .expect("timed out")
+}
+
+fn seqs(items: &[Item]) -> Vec<i64> {
+  items
+    .iter()
+    .filter_map(|item| match item {
+      Ok(ProcessedPayload {
+        seq: Some(seq),
+        data: ProcessedData::Commit(_),
+      }) => Some(*seq),
+      _ => None,
+    })
+    .collect()
+}
+
+#[tokio::test]
+async fn backfill_from_log_and_memory() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  // Only the last 2 frames are kept in memory, so the others are read from the log.
+  let server = bind(directory.path(), 2, 16).await;
+  publish(&server, 1..=5).await;
+  wait_written(&server).await;
+  assert_eq!(Shared::lock(&server.shared).recent.len(), 2);
+
+  let mut subscription = subscribe(&server, Some(1));
+  assert_eq!(seqs(&read(&mut subscription, 4).await), [2, 3, 4, 5]);
+
+  publish(&server, [6]).await;
+  assert_eq!(seqs(&read(&mut subscription, 1).await), [6]);
+}
+
+#[tokio::test]
+async fn reject_future_cursor() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let server = bind(directory.path(), 16, 16).await;
+  publish(&server, 1..=2).await;
+
+  let mut subscription = subscribe(&server, Some(3));
+  assert!(matches!(
+    &read(&mut subscription, 1).await[..],
+    [Err(SubscriptionError::Other(
+      repositories::Error::FutureCursor(None)
+    ))]
+  ));
+}
+
+#[tokio::test]
+async fn report_outdated_cursor_after_restart() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let server = bind(directory.path(), 16, 16).await;
+  publish(&server, 5..=6).await;
+  server.close().await.expect("failed to close");
+
+  // The log is read back, so the frames published before the restart are still available.
+  let server = bind(directory.path(), 16, 16).await;
+  publish(&server, [7]).await;
+  let mut subscription = subscribe(&server, Some(2));
+  let items = read(&mut subscription, 4).await;
+  assert!(matches!(
+    &items[0],
+    Ok(ProcessedPayload {
+      data: ProcessedData::Info(info),
+      ..
+    }) if info.name == InfoName::OutdatedCursor
+  ));
+  assert_eq!(seqs(&items[1..]), [5, 6, 7]);
+}
+
+#[tokio::test]
+async fn disconnect_slow_consumer() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let server = bind(directory.path(), 16, 1).await;
+  let mut subscription = subscribe(&server, None);
+  while server.clients() < 1 {
+    tokio::time::sleep(Duration::from_millis(10)).await;
+  }
+
+  // The frames are published without letting the client task run, so its queue overflows.
+  publish(&server, 1..=3).await;
+  let items = read(&mut subscription, 2).await;
+  assert!(matches!(
+    items.last(),
+    Some(Err(SubscriptionError::Other(
+      repositories::Error::ConsumerTooSlow(None)
+    )))
+  ));
+}
+
+#[tokio::test]
+async fn expire_old_segments() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let server = RelayServer::builder()
+    .addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+    .directory(directory.path())
+    .max_segment_size(1)
+    .max_log_size(1)
+    .bind()
+    .await
+    .expect("failed to bind");
+  publish(&server, 1..=5).await;
+  server.close().await.expect("failed to close");
+  // Only the segment being written is kept.
+  let segments = fs::read_dir(directory.path())
+    .expect("failed to read directory")
+    .count();
+  assert_eq!(segments, 1);
+
+  let server = bind(directory.path(), 16, 16).await;
+  let mut subscription = subscribe(&server, Some(1));
+  let items = read(&mut subscription, 2).await;
+  assert!(matches!(
+    &items[0],
+    Ok(ProcessedPayload {
+      data: ProcessedData::Info(info),
+      ..
+    }) if info.name == InfoName::OutdatedCursor
+  ));
+  assert_eq!(seqs(&items[1..]), [5]);
+}
+
+fn segment_paths(directory: &Path) -> Vec<PathBuf> {
+  let mut paths = fs::read_dir(directory)
+    .expect("failed to read directory")
+    .map(|entry| entry.expect("failed to read entry").path())
+    .collect::<Vec<_>>();
+  paths.sort();
+  paths
+}
+
+#[tokio::test]
+async fn recover_torn_log() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  let server = bind(directory.path(), 16, 16).await;
+  publish(&server, 1..=3).await;
+  server.close().await.expect("failed to close");
+
+  // A crash left a partial frame at the end of the last segment, and a later empty one.
+  let last = segment_paths(directory.path())
+    .pop()
+    .expect("missing segment");
+  let len = fs::metadata(&last).expect("failed to read metadata").len();
+  let mut file = OpenOptions::new()
+    .append(true)
+    .open(&last)
+    .expect("failed to open segment");
+  io::Write::write_all(&mut file, &[1, 2, 3]).expect("failed to write");
+  let empty = directory
+    .path()
+    .join(format!("{PREFIX}-{:020}-000000.{EXTENSION}", i64::MAX));
+  File::create(&empty).expect("failed to create segment");
+
+  let server = bind(directory.path(), 16, 16).await;
+  assert_eq!(
+    fs::metadata(&last).expect("failed to read metadata").len(),
+    len
+  );
+  assert!(!empty.exists());
+  publish(&server, [4]).await;
+  let mut subscription = subscribe(&server, Some(0));
+  assert_eq!(seqs(&read(&mut subscription, 4).await), [1, 2, 3, 4]);
+}
+
+#[tokio::test]
+async fn disconnect_on_corrupt_segment() {
+  let directory = tempfile::tempdir().expect("failed to create directory");
+  // Only the last frame is kept in memory, so the others are read from the log.
+  let server = bind(directory.path(), 1, 16).await;
+  publish(&server, 1..=3).await;
+  wait_written(&server).await;
+
+  // The first segment is missing the end of its frame, which must not be skipped.
+  let first = segment_paths(directory.path())
+    .into_iter()
+    .next()
+    .expect("missing segment");
+  let len = fs::metadata(&first).expect("failed to read metadata").len();
+  OpenOptions::new()
+    .write(true)
+    .open(&first)
+    .and_then(|file| file.set_len(len - 1))
+    .expect("failed to truncate segment");
+
+  let mut subscription = subscribe(&server, Some(0));
+  let items = read(&mut subscription, 3).await;
+  assert_eq!(seqs(&items), [] as [i64; 0]);
+}
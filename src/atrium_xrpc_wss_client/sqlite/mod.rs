@@ -0,0 +1,479 @@
+//! This file provides the [`Indexer`], which materializes the most common `app.bsky` records
+//! of the firehose into `SQLite` tables.
+//!
+//! Each collection has its own table, with a row per record keyed by its AT-URI, along with the
+//! DID of the repository, the record key, the CID and the time of the commit as `indexed_at`:
+//!
+//! | Collection              | Table      | Columns                                                      |
+//! |-------------------------|------------|--------------------------------------------------------------|
+//! | `app.bsky.feed.post`    | `posts`    | `text`, `langs`, `reply_root`, `reply_parent`, `created_at`  |
+//! | `app.bsky.feed.like`    | `likes`    | `subject_uri`, `subject_cid`, `created_at`                   |
+//! | `app.bsky.feed.repost`  | `reposts`  | `subject_uri`, `subject_cid`, `created_at`                   |
+//! | `app.bsky.graph.follow` | `follows`  | `subject`, `created_at`                                      |
+//! | `app.bsky.graph.block`  | `blocks`   | `subject`, `created_at`                                      |
+//! | `app.bsky.actor.profile`| `profiles` | `display_name`, `description`, `avatar`, `banner`, `created_at` |
+//!
+//! Times are stored as RFC 3339 strings in UTC, `langs` as a comma separated list, and `avatar` and
+//! `banner` as the CIDs of their blobs. The `cursor` table holds the seq of the last indexed
+//! payload, which is updated in the same transaction as the records, so resuming from it never
+//! skips nor repeats a commit.
+
+#[cfg(test)]
+mod tests;
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use atrium_api::{
+  app::bsky::{actor::profile, feed::post},
+  com::atproto::repo::strong_ref,
+  record::KnownRecord,
+  types::{
+    string::{Datetime, Did},
+    BlobRef, TypedBlobRef,
+  },
+};
+use bon::bon;
+use chrono::{SecondsFormat, Utc};
+use futures::{Stream, StreamExt};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::{
+  atrium_xrpc_wss::subscriptions::{
+    repositories::{HandledData, ProcessedData},
+    ProcessedPayload, SubscriptionError,
+  },
+  atrium_xrpc_wss_client::subscriptions::repositories::{
+    firehose::Firehose,
+    type_defs::{Operation, ProcessedAccountData, ProcessedCommitData},
+  },
+};
+
+type Payload = ProcessedPayload<HandledData<Firehose>>;
+
+/// The tables of the indexed collections.
+const TABLES: [(&str, &str); 6] = [
+  ("app.bsky.feed.post", "posts"),
+  ("app.bsky.feed.like", "likes"),
+  ("app.bsky.feed.repost", "reposts"),
+  ("app.bsky.graph.follow", "follows"),
+  ("app.bsky.graph.block", "blocks"),
+  ("app.bsky.actor.profile", "profiles"),
+];
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS cursor (
+  id INTEGER PRIMARY KEY CHECK (id = 0),
+  seq INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS posts (
+  uri TEXT PRIMARY KEY,
+  did TEXT NOT NULL,
+  rkey TEXT NOT NULL,
+  cid TEXT NOT NULL,
+  text TEXT NOT NULL,
+  langs TEXT,
+  reply_root TEXT,
+  reply_parent TEXT,
+  created_at TEXT NOT NULL,
+  indexed_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS posts_did ON posts (did);
+CREATE INDEX IF NOT EXISTS posts_reply_root ON posts (reply_root);
+
+CREATE TABLE IF NOT EXISTS likes (
+  uri TEXT PRIMARY KEY,
+  did TEXT NOT NULL,
+  rkey TEXT NOT NULL,
+  cid TEXT NOT NULL,
+  subject_uri TEXT NOT NULL,
+  subject_cid TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  indexed_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS likes_did ON likes (did);
+CREATE INDEX IF NOT EXISTS likes_subject_uri ON likes (subject_uri);
+
+CREATE TABLE IF NOT EXISTS reposts (
+  uri TEXT PRIMARY KEY,
+  did TEXT NOT NULL,
+  rkey TEXT NOT NULL,
+  cid TEXT NOT NULL,
+  subject_uri TEXT NOT NULL,
+  subject_cid TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  indexed_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS reposts_did ON reposts (did);
+CREATE INDEX IF NOT EXISTS reposts_subject_uri ON reposts (subject_uri);
+
+CREATE TABLE IF NOT EXISTS follows (
+  uri TEXT PRIMARY KEY,
+  did TEXT NOT NULL,
+  rkey TEXT NOT NULL,
+  cid TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  indexed_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS follows_did ON follows (did);
+CREATE INDEX IF NOT EXISTS follows_subject ON follows (subject);
+
+CREATE TABLE IF NOT EXISTS blocks (
+  uri TEXT PRIMARY KEY,
+  did TEXT NOT NULL,
+  rkey TEXT NOT NULL,
+  cid TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  indexed_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS blocks_did ON blocks (did);
+CREATE INDEX IF NOT EXISTS blocks_subject ON blocks (subject);
+
+CREATE TABLE IF NOT EXISTS profiles (
+  uri TEXT PRIMARY KEY,
+  did TEXT NOT NULL,
+  rkey TEXT NOT NULL,
+  cid TEXT NOT NULL,
+  display_name TEXT,
+  description TEXT,
+  avatar TEXT,
+  banner TEXT,
+  created_at TEXT,
+  indexed_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS profiles_did ON profiles (did);
+";
+
+/// An error while consuming a subscription into an [`Indexer`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error<E> {
+  #[error("SQLite error: {0}")]
+  Sqlite(#[from] rusqlite::Error),
+  #[error(transparent)]
+  Subscription(SubscriptionError<E>),
+}
+
+/// Maintains the tables of the indexed collections from the payloads processed by the [`Firehose`].
+///
+/// Creations and updates replace the row of the record, and deletions remove it. When an account
+/// is deleted, the rows of all its records are removed too, but not when it is only deactivated,
+/// suspended or taken down, since those can be reverted without the records being sent again.
+///
+/// Records of other collections, and records whose type doesn't match their collection, are skipped.
+/// See the [`sqlite`](self) module for the layout of the tables.
+pub struct Indexer {
+  /// Shared with the blocking threads indexing the payloads of the subscriptions.
+  connection: Arc<Mutex<Connection>>,
+  batch_size: usize,
+}
+
+#[bon]
+impl Indexer {
+  /// Builds a new indexer on the `connection`, creating the tables if they don't exist.
+  ///
+  /// When consuming a subscription, up to `batch_size` payloads that are already received
+  /// are indexed in the same transaction.
+  ///
+  /// # Errors
+  /// Returns an error if the tables could not be created.
+  #[builder]
+  pub fn new(
+    connection: Connection,
+    #[builder(default = 1000)] batch_size: usize,
+  ) -> rusqlite::Result<Self> {
+    connection.execute_batch(SCHEMA)?;
+    Ok(Self {
+      connection: Arc::new(Mutex::new(connection)),
+      batch_size,
+    })
+  }
+}
+
+impl Indexer {
+  /// The connection to the database, to query the tables.
+  ///
+  /// It's locked while a batch of payloads is being indexed.
+  pub fn connection(&self) -> MutexGuard<'_, Connection> {
+    lock(&self.connection)
+  }
+
+  /// The seq of the last indexed payload, to resume the subscription from.
+  ///
+  /// # Errors
+  /// Returns an error if the cursor could not be read.
+  pub fn cursor(&self) -> rusqlite::Result<Option<i64>> {
+    self
+      .connection()
+      .query_row("SELECT seq FROM cursor WHERE id = 0", [], |row| row.get(0))
+      .optional()
+  }
+
+  /// Indexes the payloads in a single transaction, along with the seq of the last one that has one.
+  ///
+  /// # Errors
+  /// Returns an error if the payloads could not be indexed, in which case none of them are.
+  pub fn index(&mut self, payloads: &[Payload]) -> rusqlite::Result<()> {
+    index_payloads(&mut self.connection(), payloads)
+  }
+
+  /// Indexes a subscription in batches, until its end or the first error it yields.
+  ///
+  /// The transactions run on a blocking thread, so they don't hold up the executor. The payloads
+  /// received before the error are indexed before returning it.
+  ///
+  /// # Errors
+  /// Returns the error yielded by the subscription, or an `SQLite` error if the payloads could not be indexed.
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The chunks are moved to the blocking thread before the next one is read, so nothing depends on their drop order."
+  )]
+  pub async fn consume<E>(
+    &mut self,
+    subscription: impl Stream<Item = Result<Payload, SubscriptionError<E>>> + Unpin,
+  ) -> Result<(), Error<E>> {
+    let mut chunks = subscription.ready_chunks(self.batch_size.max(1));
+    while let Some(chunk) = chunks.next().await {
+      let mut payloads = Vec::with_capacity(chunk.len());
+      let mut error = None;
+      for item in chunk {
+        match item {
+          Ok(payload) => payloads.push(payload),
+          Err(e) => {
+            error = Some(e);
+            break;
+          }
+        }
+      }
+      let connection = Arc::clone(&self.connection);
+      tokio::task::spawn_blocking(move || index_payloads(&mut lock(&connection), &payloads))
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))?;
+      if let Some(e) = error {
+        return Err(Error::Subscription(e));
+      }
+    }
+    Ok(())
+  }
+}
+
+fn lock(connection: &Mutex<Connection>) -> MutexGuard<'_, Connection> {
+  connection.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn index_payloads(connection: &mut Connection, payloads: &[Payload]) -> rusqlite::Result<()> {
+  let tx = connection.transaction()?;
+  for payload in payloads {
+    match &payload.data {
+      ProcessedData::Commit(commit) => index_commit(&tx, commit)?,
+      ProcessedData::Account(account) => index_account(&tx, account)?,
+      _ => {}
+    }
+  }
+  if let Some(seq) = payloads.iter().rev().find_map(|payload| payload.seq) {
+    tx.prepare_cached(
+      "INSERT INTO cursor (id, seq) VALUES (0, ?1)
+       ON CONFLICT (id) DO UPDATE SET seq = excluded.seq",
+    )?
+    .execute([seq])?;
+  }
+  tx.commit()
+}
+
+fn index_commit(tx: &Transaction<'_>, commit: &ProcessedCommitData) -> rusqlite::Result<()> {
+  for op in commit.ops.iter().flatten() {
+    index_operation(tx, &commit.repo, &commit.time, op)?;
+  }
+  Ok(())
+}
+
+fn index_operation(
+  tx: &Transaction<'_>,
+  did: &Did,
+  time: &Datetime,
+  op: &Operation,
+) -> rusqlite::Result<()> {
+  let Some((collection, rkey)) = op.path.split_once('/') else {
+    return Ok(());
+  };
+  let Some((_, table)) = TABLES.iter().find(|(c, _)| *c == collection) else {
+    return Ok(());
+  };
+  let uri = format!("at://{}/{}", did.as_str(), op.path);
+  if op.action == "delete" {
+    tx.prepare_cached(&format!("DELETE FROM {table} WHERE uri = ?1"))?
+      .execute([&uri])?;
+    return Ok(());
+  }
+  let (Some(record), Some(cid)) = (&op.record, &op.cid) else {
+    return Ok(());
+  };
+
+  let key = Key {
+    uri,
+    did: did.as_str(),
+    rkey,
+    cid: cid.0.to_string(),
+    indexed_at: timestamp(time),
+  };
+  match (collection, record) {
+    ("app.bsky.feed.post", KnownRecord::AppBskyFeedPost(post)) => insert_post(tx, &key, post),
+    ("app.bsky.feed.like", KnownRecord::AppBskyFeedLike(like)) => {
+      insert_subject_uri(tx, table, &key, &like.subject, &like.created_at)
+    }
+    ("app.bsky.feed.repost", KnownRecord::AppBskyFeedRepost(repost)) => {
+      insert_subject_uri(tx, table, &key, &repost.subject, &repost.created_at)
+    }
+    ("app.bsky.graph.follow", KnownRecord::AppBskyGraphFollow(follow)) => {
+      insert_subject_did(tx, table, &key, &follow.subject, &follow.created_at)
+    }
+    ("app.bsky.graph.block", KnownRecord::AppBskyGraphBlock(block)) => {
+      insert_subject_did(tx, table, &key, &block.subject, &block.created_at)
+    }
+    ("app.bsky.actor.profile", KnownRecord::AppBskyActorProfile(profile)) => {
+      insert_profile(tx, &key, profile)
+    }
+    _ => Ok(()), // The type of the record doesn't match its collection.
+  }
+}
+
+/// The columns shared by the tables of every collection.
+struct Key<'a> {
+  uri: String,
+  did: &'a str,
+  rkey: &'a str,
+  cid: String,
+  indexed_at: String,
+}
+
+fn insert_post(tx: &Transaction<'_>, key: &Key<'_>, post: &post::Record) -> rusqlite::Result<()> {
+  let langs = post.langs.as_ref().map(|langs| {
+    langs
+      .iter()
+      .map(|lang| lang.as_ref().to_string())
+      .collect::<Vec<_>>()
+      .join(",")
+  });
+  let reply = post.reply.as_ref();
+  tx.prepare_cached(
+    "INSERT OR REPLACE INTO posts
+     (uri, did, rkey, cid, text, langs, reply_root, reply_parent, created_at, indexed_at)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+  )?
+  .execute(params![
+    key.uri,
+    key.did,
+    key.rkey,
+    key.cid,
+    post.text,
+    langs,
+    reply.map(|reply| &reply.root.uri),
+    reply.map(|reply| &reply.parent.uri),
+    timestamp(&post.created_at),
+    key.indexed_at,
+  ])?;
+  Ok(())
+}
+
+/// Inserts a record whose subject is another record, like a like or a repost.
+fn insert_subject_uri(
+  tx: &Transaction<'_>,
+  table: &str,
+  key: &Key<'_>,
+  subject: &strong_ref::Main,
+  created_at: &Datetime,
+) -> rusqlite::Result<()> {
+  tx.prepare_cached(&format!(
+    "INSERT OR REPLACE INTO {table}
+     (uri, did, rkey, cid, subject_uri, subject_cid, created_at, indexed_at)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+  ))?
+  .execute(params![
+    key.uri,
+    key.did,
+    key.rkey,
+    key.cid,
+    subject.uri,
+    subject.cid.as_ref().to_string(),
+    timestamp(created_at),
+    key.indexed_at,
+  ])?;
+  Ok(())
+}
+
+/// Inserts a record whose subject is an account, like a follow or a block.
+fn insert_subject_did(
+  tx: &Transaction<'_>,
+  table: &str,
+  key: &Key<'_>,
+  subject: &Did,
+  created_at: &Datetime,
+) -> rusqlite::Result<()> {
+  tx.prepare_cached(&format!(
+    "INSERT OR REPLACE INTO {table} (uri, did, rkey, cid, subject, created_at, indexed_at)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+  ))?
+  .execute(params![
+    key.uri,
+    key.did,
+    key.rkey,
+    key.cid,
+    subject.as_str(),
+    timestamp(created_at),
+    key.indexed_at,
+  ])?;
+  Ok(())
+}
+
+fn insert_profile(
+  tx: &Transaction<'_>,
+  key: &Key<'_>,
+  profile: &profile::Record,
+) -> rusqlite::Result<()> {
+  tx.prepare_cached(
+    "INSERT OR REPLACE INTO profiles
+     (uri, did, rkey, cid, display_name, description, avatar, banner, created_at, indexed_at)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+  )?
+  .execute(params![
+    key.uri,
+    key.did,
+    key.rkey,
+    key.cid,
+    profile.display_name,
+    profile.description,
+    profile.avatar.as_ref().map(blob_cid),
+    profile.banner.as_ref().map(blob_cid),
+    profile.created_at.as_ref().map(timestamp),
+    key.indexed_at,
+  ])?;
+  Ok(())
+}
+
+/// Removes the records of deleted accounts.
+fn index_account(tx: &Transaction<'_>, account: &ProcessedAccountData) -> rusqlite::Result<()> {
+  if account.active || account.status.as_deref() != Some("deleted") {
+    return Ok(());
+  }
+  for (_, table) in TABLES {
+    tx.prepare_cached(&format!("DELETE FROM {table} WHERE did = ?1"))?
+      .execute([account.did.as_str()])?;
+  }
+  Ok(())
+}
+
+/// Formats a time in UTC, with milliseconds, so the times sort like their strings.
+fn timestamp(datetime: &Datetime) -> String {
+  datetime
+    .as_ref()
+    .with_timezone(&Utc)
+    .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn blob_cid(blob: &BlobRef) -> String {
+  match blob {
+    BlobRef::Typed(TypedBlobRef::Blob(blob)) => blob.r#ref.0.to_string(),
+    BlobRef::Untyped(blob) => blob.cid.clone(),
+  }
+}
@@ -0,0 +1,221 @@
+use atrium_api::app::bsky::feed::like;
+use futures::{stream, StreamExt};
+
+use super::*;
+use crate::atrium_xrpc_wss_client::subscriptions::repositories::{
+  fixtures::{
+    account, commit, create, datetime, delete, did, follow, post, strong_ref, until_error, TIME,
+  },
+  type_defs::ProcessedIdentityData,
+};
+
+fn like() -> KnownRecord {
+  KnownRecord::from(like::RecordData {
+    created_at: datetime(TIME),
+    subject: strong_ref("at://did:plc:xyz/app.bsky.feed.post/1"),
+  })
+}
+
+fn profile() -> KnownRecord {
+  KnownRecord::from(profile::RecordData {
+    avatar: None,
+    banner: None,
+    created_at: None,
+    description: None,
+    display_name: Some(String::from("Alice")),
+    joined_via_starter_pack: None,
+    labels: None,
+  })
+}
+
+fn indexer() -> Indexer {
+  Indexer::builder()
+    .connection(Connection::open_in_memory().expect("failed to open database"))
+    .build()
+    .expect("failed to build indexer")
+}
+
+fn count(indexer: &Indexer, table: &str) -> i64 {
+  indexer
+    .connection()
+    .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+      row.get(0)
+    })
+    .expect("failed to count")
+}
+
+fn texts(indexer: &Indexer) -> Vec<(String, String, Option<String>)> {
+  indexer
+    .connection()
+    .prepare("SELECT uri, text, langs FROM posts ORDER BY uri")
+    .and_then(|mut statement| {
+      let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+      rows.collect()
+    })
+    .expect("failed to query")
+}
+
+#[test]
+fn index_records() {
+  let mut indexer = indexer();
+  assert_eq!(indexer.cursor().expect("failed to read cursor"), None);
+
+  indexer
+    .index(&[
+      commit(
+        1,
+        "did:plc:abc",
+        TIME,
+        vec![
+          create("app.bsky.feed.post/1", post("Hello", &["en", "fr"])),
+          create("app.bsky.feed.like/2", like()),
+          create("app.bsky.graph.follow/3", follow("did:plc:xyz")),
+          create("app.bsky.actor.profile/self", profile()),
+          // Unknown collections and mismatched records are skipped.
+          create("app.bsky.feed.threadgate/4", post("Hidden", &["en", "fr"])),
+          create("app.bsky.graph.block/5", post("Hidden", &["en", "fr"])),
+        ],
+      ),
+      // Payloads without a seq don't move the cursor back.
+      ProcessedPayload {
+        seq: None,
+        data: ProcessedData::Identity(ProcessedIdentityData {
+          did: did("did:plc:abc"),
+          handle: None,
+          time: datetime(TIME),
+        }),
+      },
+    ])
+    .expect("failed to index");
+
+  assert_eq!(indexer.cursor().expect("failed to read cursor"), Some(1));
+  assert_eq!(
+    texts(&indexer),
+    [(
+      String::from("at://did:plc:abc/app.bsky.feed.post/1"),
+      String::from("Hello"),
+      Some(String::from("en,fr"))
+    )]
+  );
+  let (subject_uri, created_at): (String, String) = indexer
+    .connection()
+    .query_row("SELECT subject_uri, created_at FROM likes", [], |row| {
+      Ok((row.get(0)?, row.get(1)?))
+    })
+    .expect("failed to query");
+  assert_eq!(subject_uri, "at://did:plc:xyz/app.bsky.feed.post/1");
+  assert_eq!(created_at, "2024-01-01T00:00:00.000Z");
+  let subject: String = indexer
+    .connection()
+    .query_row("SELECT subject FROM follows", [], |row| row.get(0))
+    .expect("failed to query");
+  assert_eq!(subject, "did:plc:xyz");
+  let display_name: String = indexer
+    .connection()
+    .query_row("SELECT display_name FROM profiles", [], |row| row.get(0))
+    .expect("failed to query");
+  assert_eq!(display_name, "Alice");
+  assert_eq!(count(&indexer, "blocks"), 0);
+}
+
+#[test]
+fn update_and_delete_records() {
+  let mut indexer = indexer();
+  indexer
+    .index(&[commit(
+      1,
+      "did:plc:abc",
+      TIME,
+      vec![
+        create("app.bsky.feed.post/1", post("Hello", &["en", "fr"])),
+        create("app.bsky.feed.post/2", post("World", &["en", "fr"])),
+      ],
+    )])
+    .expect("failed to index");
+
+  let mut update = create("app.bsky.feed.post/1", post("Edited", &["en", "fr"]));
+  update.action = String::from("update");
+  indexer
+    .index(&[commit(
+      2,
+      "did:plc:abc",
+      TIME,
+      vec![update, delete("app.bsky.feed.post/2")],
+    )])
+    .expect("failed to index");
+
+  assert_eq!(indexer.cursor().expect("failed to read cursor"), Some(2));
+  let texts = texts(&indexer);
+  assert_eq!(texts.len(), 1);
+  assert_eq!(texts[0].1, "Edited");
+}
+
+#[test]
+fn remove_deleted_accounts() {
+  let mut indexer = indexer();
+  indexer
+    .index(&[
+      commit(
+        1,
+        "did:plc:abc",
+        TIME,
+        vec![create("app.bsky.feed.post/1", post("Hello", &["en", "fr"]))],
+      ),
+      commit(
+        2,
+        "did:plc:abc",
+        TIME,
+        vec![create("app.bsky.feed.like/2", like())],
+      ),
+      commit(
+        3,
+        "did:plc:xyz",
+        TIME,
+        vec![create("app.bsky.feed.post/1", post("Hello", &["en", "fr"]))],
+      ),
+      // Deactivated accounts keep their records.
+      account(4, "did:plc:xyz", "deactivated"),
+    ])
+    .expect("failed to index");
+  assert_eq!(count(&indexer, "posts"), 2);
+
+  indexer
+    .index(&[account(5, "did:plc:abc", "deleted")])
+    .expect("failed to index");
+  assert_eq!(indexer.cursor().expect("failed to read cursor"), Some(5));
+  assert_eq!(count(&indexer, "posts"), 1);
+  assert_eq!(count(&indexer, "likes"), 0);
+}
+
+#[tokio::test]
+async fn consume_until_error() {
+  let mut indexer = indexer();
+  let subscription = until_error([
+    commit(
+      1,
+      "did:plc:abc",
+      TIME,
+      vec![create("app.bsky.feed.post/1", post("Hello", &["en", "fr"]))],
+    ),
+    commit(
+      2,
+      "did:plc:abc",
+      TIME,
+      vec![create("app.bsky.feed.post/2", post("World", &["en", "fr"]))],
+    ),
+  ])
+  .chain(stream::iter([Ok(commit(
+    3,
+    "did:plc:abc",
+    TIME,
+    vec![create("app.bsky.feed.post/3", post("Lost", &["en", "fr"]))],
+  ))]));
+  let res = indexer.consume(subscription).await;
+
+  assert!(matches!(
+    res,
+    Err(Error::Subscription(SubscriptionError::Server { .. }))
+  ));
+  assert_eq!(indexer.cursor().expect("failed to read cursor"), Some(2));
+  assert_eq!(count(&indexer, "posts"), 2);
+}
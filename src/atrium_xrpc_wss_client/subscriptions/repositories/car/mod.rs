@@ -0,0 +1,114 @@
+//! This file provides a minimal reader for the CAR (v1) files sent in `#commit` payloads.
+//! You can read more about the format in the [`IPLD documentation`](https://ipld.io/specs/transport/car/carv1/)
+//!
+//! Unlike a general purpose CAR reader, the blocks are sliced out of the CAR file,
+//! so they share its allocation instead of being copied. They are still checked against the
+//! SHA-256 digest of their CID, as the relay is not trusted to send matching blocks.
+
+#[cfg(test)]
+pub(crate) mod tests;
+
+use std::{collections::BTreeMap, convert::Infallible};
+
+use bytes::Bytes;
+use ipld_core::cid::Cid;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// The multihash code of SHA-256, the only hash function used by the repositories.
+const SHA2_256: u64 = 0x12;
+
+/// An error type for this module.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error("Unexpected end of the CAR file")]
+  UnexpectedEof,
+  #[error("Invalid varint in the CAR file")]
+  InvalidVarint,
+  #[error("Invalid CID in the CAR file: {0}")]
+  InvalidCid(#[from] ipld_core::cid::Error),
+  #[error("Invalid header in the CAR file: {0}")]
+  InvalidHeader(#[from] serde_ipld_dagcbor::DecodeError<Infallible>),
+  #[error("Unsupported CAR version: {0}")]
+  UnsupportedVersion(u64),
+  #[error("Unsupported multihash code {code:#x} for block {cid}")]
+  UnsupportedHash { cid: Cid, code: u64 },
+  #[error("The data of block {0} doesn't match its CID")]
+  HashMismatch(Cid),
+}
+
+/// The header of a CAR file, whose roots are not needed to look up the operations' records.
+#[derive(Deserialize)]
+struct Header {
+  version: u64,
+}
+
+/// Reads all the blocks from a CAR file, indexed by their CID.
+///
+/// Only the version of the header is checked, as the roots are not needed to look up the
+/// operations' records.
+///
+/// # Errors
+/// Returns an error if the CAR file is truncated, has an invalid varint, header or CID,
+/// is not a version 1 CAR file, or if a block doesn't match its CID.
+pub fn read_blocks(car: &Bytes) -> Result<BTreeMap<Cid, Bytes>, Error> {
+  let mut blocks = BTreeMap::new();
+  let mut rest = &car[..];
+
+  let header_len = read_varint(&mut rest)?;
+  let (header, tail) = rest
+    .split_at_checked(header_len)
+    .ok_or(Error::UnexpectedEof)?;
+  rest = tail;
+  let Header { version } = serde_ipld_dagcbor::from_slice(header)?;
+  if version != 1 {
+    return Err(Error::UnsupportedVersion(version));
+  }
+
+  while !rest.is_empty() {
+    let section_len = read_varint(&mut rest)?;
+    let (mut section, tail) = rest
+      .split_at_checked(section_len)
+      .ok_or(Error::UnexpectedEof)?;
+    rest = tail;
+
+    // `Cid::read_bytes` advances the slice past the CID, leaving only the block data.
+    let cid = Cid::read_bytes(&mut section)?;
+    verify_block(&cid, section)?;
+    blocks.insert(cid, car.slice_ref(section));
+  }
+
+  Ok(blocks)
+}
+
+/// Checks that the digest of the CID's multihash is the one of the block's `data`.
+fn verify_block(cid: &Cid, data: &[u8]) -> Result<(), Error> {
+  let hash = cid.hash();
+  if hash.code() != SHA2_256 {
+    return Err(Error::UnsupportedHash {
+      cid: *cid,
+      code: hash.code(),
+    });
+  }
+  if hash.digest() != Sha256::digest(data).as_slice() {
+    return Err(Error::HashMismatch(*cid));
+  }
+  Ok(())
+}
+
+/// Reads an unsigned LEB128 varint, advancing the slice past it.
+fn read_varint(bytes: &mut &[u8]) -> Result<usize, Error> {
+  let mut value = 0usize;
+  for (i, byte) in bytes.iter().enumerate() {
+    let shift = i * 7;
+    if shift >= usize::BITS as usize {
+      return Err(Error::InvalidVarint);
+    }
+    value |= usize::from(byte & 0x7f) << shift;
+    if byte & 0x80 == 0 {
+      *bytes = &bytes[i + 1..];
+      return Ok(value);
+    }
+  }
+  Err(Error::UnexpectedEof)
+}
@@ -0,0 +1,106 @@
+use ipld_core::{cid::multihash::Multihash, ipld::Ipld};
+
+use super::*;
+
+pub fn cid(digest: u8) -> Cid {
+  Cid::new_v1(
+    0x71,
+    Multihash::wrap(0x12, &[digest; 32]).expect("invalid multihash"),
+  )
+}
+
+/// A block of `data`, with the CID of its actual digest.
+pub fn block(data: Vec<u8>) -> (Cid, Vec<u8>) {
+  let digest = Sha256::digest(&data);
+  let hash = Multihash::wrap(SHA2_256, &digest).expect("invalid multihash");
+  (Cid::new_v1(0x71, hash), data)
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut value: usize) {
+  while value >= 0x80 {
+    #[expect(clippy::cast_possible_truncation, reason = "Masked to 7 bits.")]
+    buf.push((value as u8 & 0x7f) | 0x80);
+    value >>= 7;
+  }
+  #[expect(clippy::cast_possible_truncation, reason = "Less than 0x80.")]
+  buf.push(value as u8);
+}
+
+/// Writes a CAR file with the given blocks, using the first one as the root.
+pub fn car(blocks: &[(Cid, Vec<u8>)]) -> Bytes {
+  car_with_version(blocks, 1)
+}
+
+fn car_with_version(blocks: &[(Cid, Vec<u8>)], version: i128) -> Bytes {
+  let header = Ipld::Map(
+    [
+      (
+        "roots".to_owned(),
+        Ipld::List(vec![Ipld::Link(blocks[0].0)]),
+      ),
+      ("version".to_owned(), Ipld::Integer(version)),
+    ]
+    .into(),
+  );
+  let header = serde_ipld_dagcbor::to_vec(&header).expect("failed to serialize");
+  let mut buf = Vec::new();
+  push_varint(&mut buf, header.len());
+  buf.extend(header);
+  for (cid, data) in blocks {
+    let cid = cid.to_bytes();
+    push_varint(&mut buf, cid.len() + data.len());
+    buf.extend(cid);
+    buf.extend(data);
+  }
+  buf.into()
+}
+
+#[test]
+fn read_blocks_from_car() {
+  // A block larger than 127 bytes, to use a multi-byte varint.
+  let blocks = vec![block(vec![1; 200]), block(vec![2, 3])];
+  let car = car(&blocks);
+  let result = read_blocks(&car).expect("failed to read blocks");
+  assert_eq!(result.len(), 2);
+  for (cid, data) in blocks {
+    let block = &result[&cid];
+    assert_eq!(block, &data);
+    // The block must point into the CAR file instead of being a copy.
+    assert!(car.as_ptr_range().contains(&block.as_ptr()));
+  }
+}
+
+#[test]
+fn read_truncated_car() {
+  let car = car(&[block(vec![1, 2, 3])]);
+  let result = read_blocks(&car.slice(..car.len() - 1));
+  assert!(matches!(result, Err(Error::UnexpectedEof)));
+}
+
+#[test]
+fn read_tampered_car() {
+  let (cid, mut data) = block(vec![1, 2, 3]);
+  data[0] = 0;
+  let result = read_blocks(&car(&[block(vec![4]), (cid, data)]));
+  assert!(matches!(result, Err(Error::HashMismatch(tampered)) if tampered == cid));
+
+  let (cid, data) = block(vec![1, 2, 3]);
+  let sha3 = Cid::new_v1(
+    0x71,
+    Multihash::wrap(0x16, cid.hash().digest()).expect("invalid multihash"),
+  );
+  let result = read_blocks(&car(&[(sha3, data)]));
+  assert!(matches!(
+    result,
+    Err(Error::UnsupportedHash { code: 0x16, .. })
+  ));
+}
+
+#[test]
+fn read_car_with_other_version() {
+  let car = car_with_version(&[block(vec![1, 2, 3])], 2);
+  assert!(matches!(
+    read_blocks(&car),
+    Err(Error::UnsupportedVersion(2))
+  ));
+}
@@ -0,0 +1,132 @@
+//! This file defines the [`Filter`] used by the [`Firehose`](super::firehose::Firehose) handler
+//! to skip commits and operations before their records are decoded.
+
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashSet;
+
+use atrium_api::types::string::Did;
+use bon::bon;
+
+/// The action of a repository operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+  Create,
+  Update,
+  Delete,
+}
+
+impl Action {
+  /// Returns the name of the action, as sent in the operations of a commit.
+  #[must_use]
+  pub const fn as_str(self) -> &'static str {
+    match self {
+      Self::Create => "create",
+      Self::Update => "update",
+      Self::Delete => "delete",
+    }
+  }
+}
+
+/// A filter for the commits and operations processed by the [`Firehose`](super::firehose::Firehose).
+///
+/// The default filter lets everything through.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+  collections: Vec<String>,
+  allowed_dids: Option<HashSet<Did>>,
+  denied_dids: HashSet<Did>,
+  actions: Option<HashSet<Action>>,
+  drop_empty_commits: bool,
+}
+
+#[bon]
+impl Filter {
+  /// Builds a new filter.
+  ///
+  /// - `collections` are NSID globs, where `*` matches any sequence of characters
+  ///   (like `app.bsky.feed.*`). If empty, operations on any collection are kept.
+  /// - `allowed_dids`, if set, are the only repositories whose events are kept.
+  /// - `denied_dids` are repositories whose events are always skipped.
+  /// - `actions`, if set, are the only actions whose operations are kept.
+  /// - `drop_empty_commits` skips the commits left without operations after filtering.
+  ///   Commits marked as `tooBig` don't carry operations, so they are never dropped.
+  #[builder]
+  pub const fn new(
+    #[builder(default)] collections: Vec<String>,
+    allowed_dids: Option<HashSet<Did>>,
+    #[builder(default)] denied_dids: HashSet<Did>,
+    actions: Option<HashSet<Action>>,
+    #[builder(default)] drop_empty_commits: bool,
+  ) -> Self {
+    Self {
+      collections,
+      allowed_dids,
+      denied_dids,
+      actions,
+      drop_empty_commits,
+    }
+  }
+
+  /// Returns `true` if the events of the `repo` should be processed.
+  #[must_use]
+  pub fn matches_repo(&self, repo: &Did) -> bool {
+    !self.denied_dids.contains(repo)
+      && self
+        .allowed_dids
+        .as_ref()
+        .is_none_or(|allowed| allowed.contains(repo))
+  }
+
+  /// Returns `true` if an operation with the given `action` on the given `path` should be processed.
+  ///
+  /// The `path` is the `{collection}/{rkey}` of the record.
+  #[must_use]
+  pub fn matches_op(&self, action: &str, path: &str) -> bool {
+    let actions_match = self
+      .actions
+      .as_ref()
+      .is_none_or(|actions| actions.iter().any(|a| a.as_str() == action));
+    let collection = path
+      .split_once('/')
+      .map_or(path, |(collection, _)| collection);
+    actions_match
+      && (self.collections.is_empty()
+        || self
+          .collections
+          .iter()
+          .any(|glob| glob_matches(glob, collection)))
+  }
+
+  /// Returns `true` if the commits left without operations after filtering should be skipped.
+  #[must_use]
+  pub const fn drop_empty_commits(&self) -> bool {
+    self.drop_empty_commits
+  }
+}
+
+/// Matches the `text` against the `glob`, where `*` matches any sequence of characters.
+fn glob_matches(glob: &str, text: &str) -> bool {
+  let (glob, text) = (glob.as_bytes(), text.as_bytes());
+  let (mut g, mut t) = (0, 0);
+  // The position of the last `*` in the glob, and of the text when it was reached.
+  let mut backtrack = None;
+  while t < text.len() {
+    if g < glob.len() && glob[g] == b'*' {
+      backtrack = Some((g, t));
+      g += 1;
+    } else if g < glob.len() && glob[g] == text[t] {
+      g += 1;
+      t += 1;
+    } else if let Some((star, matched)) = backtrack {
+      // Lets the last `*` match one more character.
+      g = star + 1;
+      t = matched + 1;
+      backtrack = Some((star, t));
+    } else {
+      return false;
+    }
+  }
+  glob[g..].iter().all(|&c| c == b'*')
+}
@@ -0,0 +1,151 @@
+use atrium_api::{
+  app::bsky::feed::post,
+  com::atproto::sync::subscribe_repos::RepoOpData,
+  record::KnownRecord,
+  types::{string::Datetime, CidLink},
+};
+
+use super::*;
+use crate::{
+  atrium_xrpc_wss::subscriptions::{
+    repositories::{Commit, Handler},
+    ProcessedPayload,
+  },
+  atrium_xrpc_wss_client::subscriptions::repositories::{car::tests as car, firehose::Firehose},
+};
+
+fn did(did: &str) -> Did {
+  did.parse().expect("invalid did")
+}
+
+/// A commit from `did:plc:abc` creating a post, and deleting a like which has no block.
+fn commit() -> Commit {
+  let record = KnownRecord::from(post::RecordData {
+    created_at: Datetime::now(),
+    embed: None,
+    entities: None,
+    facets: None,
+    labels: None,
+    langs: None,
+    reply: None,
+    tags: None,
+    text: String::from("Hello"),
+  });
+  let block = car::block(serde_ipld_dagcbor::to_vec(&record).expect("failed to serialize"));
+  let cid = block.0;
+  Commit {
+    blobs: vec![],
+    blocks: car::car(&[block]),
+    commit: CidLink(cid),
+    ops: vec![
+      RepoOpData {
+        action: String::from("create"),
+        cid: Some(CidLink(cid)),
+        path: String::from("app.bsky.feed.post/1"),
+      }
+      .into(),
+      RepoOpData {
+        action: String::from("delete"),
+        cid: None,
+        path: String::from("app.bsky.feed.like/2"),
+      }
+      .into(),
+    ],
+    prev: None,
+    rebase: false,
+    repo: did("did:plc:abc"),
+    rev: String::from("1"),
+    seq: 1,
+    since: None,
+    time: Datetime::now(),
+    too_big: false,
+  }
+}
+
+async fn paths(filter: Filter) -> Option<Vec<String>> {
+  let processed = Firehose::new(filter)
+    .process_commit(commit())
+    .await
+    .expect("failed to process");
+  processed.map(|ProcessedPayload { data, .. }| {
+    data
+      .ops
+      .expect("missing ops")
+      .into_iter()
+      .map(|op| op.path)
+      .collect()
+  })
+}
+
+#[test]
+fn match_globs() {
+  assert!(glob_matches("app.bsky.feed.post", "app.bsky.feed.post"));
+  assert!(glob_matches("app.bsky.feed.*", "app.bsky.feed.post"));
+  assert!(glob_matches("*.post", "app.bsky.feed.post"));
+  assert!(glob_matches("app.*.post", "app.bsky.feed.post"));
+  assert!(glob_matches("*", ""));
+  assert!(!glob_matches("app.bsky.feed.*", "app.bsky.graph.follow"));
+  assert!(!glob_matches("app.bsky.feed", "app.bsky.feed.post"));
+  assert!(!glob_matches("*.like", "app.bsky.feed.post"));
+}
+
+#[tokio::test]
+async fn filter_commits() {
+  assert_eq!(
+    paths(Filter::default()).await,
+    Some(vec![
+      String::from("app.bsky.feed.post/1"),
+      String::from("app.bsky.feed.like/2")
+    ])
+  );
+  assert_eq!(
+    paths(
+      Filter::builder()
+        .collections(vec![String::from("app.bsky.feed.p*")])
+        .build()
+    )
+    .await,
+    Some(vec![String::from("app.bsky.feed.post/1")])
+  );
+  assert_eq!(
+    paths(Filter::builder().actions([Action::Delete].into()).build()).await,
+    Some(vec![String::from("app.bsky.feed.like/2")])
+  );
+  assert_eq!(
+    paths(
+      Filter::builder()
+        .collections(vec![String::from("app.bsky.graph.*")])
+        .build()
+    )
+    .await,
+    Some(vec![])
+  );
+  assert_eq!(
+    paths(
+      Filter::builder()
+        .collections(vec![String::from("app.bsky.graph.*")])
+        .drop_empty_commits(true)
+        .build()
+    )
+    .await,
+    None
+  );
+  assert_eq!(
+    paths(
+      Filter::builder()
+        .allowed_dids([did("did:plc:xyz")].into())
+        .build()
+    )
+    .await,
+    None
+  );
+  assert_eq!(
+    paths(
+      Filter::builder()
+        .denied_dids([did("did:plc:abc")].into())
+        .build()
+    )
+    .await,
+    None
+  );
+}
@@ -0,0 +1,228 @@
+#[cfg(test)]
+mod tests;
+
+use std::{collections::BTreeMap, convert::Infallible};
+
+use atrium_api::{
+  com::atproto::sync::subscribe_repos::{self, AccountData, IdentityData, InfoData, RepoOpData},
+  record::KnownRecord,
+  types::Object,
+};
+use bytes::Bytes;
+use ipld_core::cid::Cid;
+
+use super::{
+  car,
+  filter::Filter,
+  type_defs::{self, Operation},
+};
+use crate::atrium_xrpc_wss::subscriptions::{
+  repositories::{Commit, Handler},
+  ProcessedPayload,
+};
+
+/// Errors for this crate
+#[derive(Debug, thiserror::Error)]
+pub enum HandlingError {
+  #[error("CAR Decoding error: {0}")]
+  CarDecoding(#[from] car::Error),
+  #[error("IPLD Decoding error: {0}")]
+  IpldDecoding(#[from] serde_ipld_dagcbor::DecodeError<Infallible>),
+}
+
+/// The default [`Handler`], which decodes the records of each commit.
+///
+/// Commits and operations rejected by its [`Filter`] are skipped before their records are decoded.
+#[derive(Debug, Clone, Default)]
+pub struct Firehose {
+  filter: Filter,
+}
+
+impl Firehose {
+  /// Creates a handler that only processes what the `filter` lets through.
+  #[must_use]
+  pub const fn new(filter: Filter) -> Self {
+    Self { filter }
+  }
+}
+
+impl Handler for Firehose {
+  type HandlingError = self::HandlingError;
+
+  type ProcessedCommitData = type_defs::ProcessedCommitData;
+  async fn process_commit(
+    &self,
+    payload: Commit,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedCommitData>>, Self::HandlingError> {
+    let Commit {
+      blobs,
+      blocks,
+      commit,
+      ops,
+      repo,
+      rev,
+      seq,
+      since,
+      time,
+      too_big,
+      ..
+    } = payload;
+
+    if !self.filter.matches_repo(&repo) {
+      return Ok(None);
+    }
+
+    // If it is too big, the blocks and ops are not sent, so we skip the processing.
+    let ops_opt = if too_big {
+      None
+    } else {
+      let ops = ops
+        .into_iter()
+        .filter(|op| self.filter.matches_op(&op.action, &op.path))
+        .collect::<Vec<_>>();
+      if ops.is_empty() && self.filter.drop_empty_commits() {
+        return Ok(None);
+      }
+
+      // We read all the blocks from the CAR file and store them in a map
+      // so that we can look up the data for each operation by its CID.
+      let map = car::read_blocks(&blocks)?;
+
+      // "Invalid framing or invalid DAG-CBOR encoding are hard errors,
+      //  and the client should drop the entire connection instead of skipping the frame."
+      // https://atproto.com/specs/event-stream
+      Some(process_ops(ops, &map)?)
+    };
+
+    Ok(Some(ProcessedPayload {
+      seq: Some(seq),
+      data: Self::ProcessedCommitData {
+        ops: ops_opt,
+        blobs,
+        commit: Some(commit),
+        repo,
+        rev,
+        since,
+        time,
+      },
+    }))
+  }
+
+  type ProcessedIdentityData = type_defs::ProcessedIdentityData;
+  async fn process_identity(
+    &self,
+    payload: subscribe_repos::Identity,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedIdentityData>>, Self::HandlingError> {
+    let IdentityData {
+      did,
+      handle,
+      seq,
+      time,
+    } = payload.data;
+    if !self.filter.matches_repo(&did) {
+      return Ok(None);
+    }
+    Ok(Some(ProcessedPayload {
+      seq: Some(seq),
+      data: Self::ProcessedIdentityData { did, handle, time },
+    }))
+  }
+
+  type ProcessedAccountData = type_defs::ProcessedAccountData;
+  async fn process_account(
+    &self,
+    payload: subscribe_repos::Account,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedAccountData>>, Self::HandlingError> {
+    let AccountData {
+      active,
+      did,
+      seq,
+      status,
+      time,
+    } = payload.data;
+    if !self.filter.matches_repo(&did) {
+      return Ok(None);
+    }
+    Ok(Some(ProcessedPayload {
+      seq: Some(seq),
+      data: Self::ProcessedAccountData {
+        did,
+        active,
+        status,
+        time,
+      },
+    }))
+  }
+
+  type ProcessedHandleData = type_defs::ProcessedHandleData;
+  async fn process_handle(
+    &self,
+    _payload: subscribe_repos::Handle,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedHandleData>>, Self::HandlingError> {
+    Ok(None) // TODO: Implement
+  }
+
+  type ProcessedMigrateData = type_defs::ProcessedMigrateData;
+  async fn process_migrate(
+    &self,
+    _payload: subscribe_repos::Migrate,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedMigrateData>>, Self::HandlingError> {
+    Ok(None) // TODO: Implement
+  }
+
+  type ProcessedTombstoneData = type_defs::ProcessedTombstoneData;
+  async fn process_tombstone(
+    &self,
+    _payload: subscribe_repos::Tombstone,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedTombstoneData>>, Self::HandlingError> {
+    Ok(None) // TODO: Implement
+  }
+
+  type ProcessedInfoData = type_defs::ProcessedInfoData;
+  async fn process_info(
+    &self,
+    payload: subscribe_repos::Info,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedInfoData>>, Self::HandlingError> {
+    let InfoData { message, name } = payload.data;
+    Ok(Some(ProcessedPayload {
+      seq: None,
+      data: Self::ProcessedInfoData {
+        name: name.into(),
+        message,
+      },
+    }))
+  }
+}
+
+fn process_ops(
+  ops: Vec<Object<RepoOpData>>,
+  map: &BTreeMap<Cid, Bytes>,
+) -> Result<Vec<Operation>, serde_ipld_dagcbor::DecodeError<Infallible>> {
+  let mut processed_ops = Vec::with_capacity(ops.len());
+  for op in ops {
+    processed_ops.push(process_op(map, op)?);
+  }
+  Ok(processed_ops)
+}
+
+/// Processes a single operation.
+fn process_op(
+  map: &BTreeMap<Cid, Bytes>,
+  op: Object<RepoOpData>,
+) -> Result<Operation, serde_ipld_dagcbor::DecodeError<Infallible>> {
+  let RepoOpData { action, path, cid } = op.data;
+
+  // Finds in the map the `Record` with the operation's CID and deserializes it.
+  // If the item is not found, returns `None`.
+  let record = match cid.as_ref().and_then(|c| map.get(&c.0)) {
+    Some(item) => Some(serde_ipld_dagcbor::from_slice::<KnownRecord>(item)?),
+    None => None,
+  };
+
+  Ok(Operation {
+    action,
+    path,
+    cid,
+    record,
+  })
+}
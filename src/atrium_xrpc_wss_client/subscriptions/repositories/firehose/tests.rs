@@ -0,0 +1,31 @@
+use atrium_api::com::atproto::sync::subscribe_repos::{Info, InfoData};
+
+use super::*;
+use crate::atrium_xrpc_wss::subscriptions::repositories::InfoName;
+
+async fn info(name: &str) -> type_defs::ProcessedInfoData {
+  let payload = Info::from(InfoData {
+    message: Some(String::from("message")),
+    name: String::from(name),
+  });
+  Firehose::default()
+    .process_info(payload)
+    .await
+    .expect("failed to process info")
+    .expect("info was skipped")
+    .data
+}
+
+#[tokio::test]
+async fn process_known_info() {
+  let data = info("OutdatedCursor").await;
+  assert_eq!(data.name, InfoName::OutdatedCursor);
+  assert_eq!(data.message.as_deref(), Some("message"));
+}
+
+#[tokio::test]
+async fn process_unknown_info() {
+  let data = info("Unknown").await;
+  assert_eq!(data.name, InfoName::Other(String::from("Unknown")));
+  assert_eq!(data.message.as_deref(), Some("message"));
+}
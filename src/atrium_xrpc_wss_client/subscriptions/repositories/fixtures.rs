@@ -0,0 +1,148 @@
+//! This file provides fixtures of the payloads processed by the [`Firehose`], shared by the tests
+//! of the modules consuming them.
+//!
+//! It's public, unlike the other test helpers, since each feature only uses some of the fixtures.
+#![expect(
+  clippy::missing_panics_doc,
+  clippy::must_use_candidate,
+  reason = "The fixtures are only built for the tests."
+)]
+
+use std::convert::Infallible;
+
+use atrium_api::{
+  app::bsky::{feed::post, graph::follow},
+  com::atproto::repo::strong_ref,
+  record::KnownRecord,
+  types::{
+    string::{Datetime, Did},
+    CidLink,
+  },
+};
+use futures::{stream, Stream};
+
+use super::{
+  car::tests as car,
+  firehose::Firehose,
+  type_defs::{Operation, ProcessedAccountData, ProcessedCommitData},
+};
+use crate::atrium_xrpc_wss::subscriptions::{
+  repositories::{HandledData, ProcessedData},
+  ProcessedPayload, SubscriptionError,
+};
+
+pub type Payload = ProcessedPayload<HandledData<Firehose>>;
+
+/// The time of the records and accounts, and of the commits of most tests.
+pub const TIME: &str = "2024-01-01T00:00:00.000Z";
+
+pub fn datetime(s: &str) -> Datetime {
+  s.parse().expect("invalid datetime")
+}
+
+pub fn did(did: &str) -> Did {
+  did.parse().expect("invalid did")
+}
+
+/// A reference to the record at `uri`, with the CID of block 3.
+pub fn strong_ref(uri: &str) -> strong_ref::Main {
+  strong_ref::MainData {
+    cid: car::cid(3).to_string().parse().expect("invalid cid"),
+    uri: uri.to_owned(),
+  }
+  .into()
+}
+
+/// The creation of a `record` at `path`, with the CID of block 2.
+pub fn create(path: &str, record: KnownRecord) -> Operation {
+  Operation {
+    action: String::from("create"),
+    path: path.to_owned(),
+    cid: Some(CidLink(car::cid(2))),
+    record: Some(record),
+  }
+}
+
+pub fn delete(path: &str) -> Operation {
+  Operation {
+    action: String::from("delete"),
+    path: path.to_owned(),
+    cid: None,
+    record: None,
+  }
+}
+
+/// A commit of the `ops` to the `repo` at `time`, with the CID of block 1.
+pub fn commit_data(repo: &str, time: &str, ops: Vec<Operation>) -> ProcessedCommitData {
+  ProcessedCommitData {
+    repo: did(repo),
+    commit: Some(CidLink(car::cid(1))),
+    ops: Some(ops),
+    blobs: Vec::new(),
+    rev: String::from("3m"),
+    since: None,
+    time: datetime(time),
+  }
+}
+
+pub fn commit(seq: i64, repo: &str, time: &str, ops: Vec<Operation>) -> Payload {
+  ProcessedPayload {
+    seq: Some(seq),
+    data: ProcessedData::Commit(commit_data(repo, time, ops)),
+  }
+}
+
+/// An inactive account, with its `status`.
+pub fn account(seq: i64, repo: &str, status: &str) -> Payload {
+  ProcessedPayload {
+    seq: Some(seq),
+    data: ProcessedData::Account(ProcessedAccountData {
+      did: did(repo),
+      active: false,
+      status: Some(status.to_owned()),
+      time: datetime(TIME),
+    }),
+  }
+}
+
+/// A post in the `langs`, to be completed with the other fields of a test.
+pub fn post_data(text: &str, langs: &[&str]) -> post::RecordData {
+  post::RecordData {
+    created_at: datetime(TIME),
+    embed: None,
+    entities: None,
+    facets: None,
+    labels: None,
+    langs: Some(
+      langs
+        .iter()
+        .map(|lang| lang.parse().expect("invalid language"))
+        .collect(),
+    ),
+    reply: None,
+    tags: None,
+    text: text.to_owned(),
+  }
+}
+
+pub fn post(text: &str, langs: &[&str]) -> KnownRecord {
+  KnownRecord::from(post_data(text, langs))
+}
+
+pub fn follow(subject: &str) -> KnownRecord {
+  KnownRecord::from(follow::RecordData {
+    created_at: datetime(TIME),
+    subject: did(subject),
+  })
+}
+
+/// A subscription yielding the `payloads`, and then a `FutureCursor` error.
+pub fn until_error<T>(
+  payloads: impl IntoIterator<Item = T>,
+) -> impl Stream<Item = Result<T, SubscriptionError<Infallible>>> + Unpin {
+  let error = SubscriptionError::Server {
+    error: String::from("FutureCursor"),
+    message: None,
+  };
+  stream::iter(payloads.into_iter().map(Ok).chain([Err(error)]))
+}
@@ -0,0 +1,379 @@
+//! This file provides the [`FnHandler`], a [`Handler`] built from closures.
+//!
+//! It's meant for small tools and tests that only care about a few payload types:
+//! ```no_run
+//! # use firehose_client::atrium_xrpc_wss_client::subscriptions::repositories::fn_handler::FnHandler;
+//! let handler = FnHandler::builder()
+//!   .on_commit(|commit| async move { commit.repo })
+//!   .build();
+//! ```
+
+use std::future::Future;
+
+use atrium_api::com::atproto::sync::subscribe_repos::{
+  self, AccountData, HandleData, IdentityData, MigrateData, TombstoneData,
+};
+
+use super::{
+  firehose::{Firehose, HandlingError},
+  type_defs::{ProcessedCommitData, ProcessedInfoData},
+};
+use crate::atrium_xrpc_wss::subscriptions::{
+  repositories::{Commit, Handler},
+  ProcessedPayload,
+};
+
+/// A callback for payloads of type `P`.
+///
+/// It's implemented for any `Fn(P) -> impl Future` closure, whose output becomes the processed data,
+/// and for [`Ignore`], which ignores the payloads.
+pub trait Callback<P>: Send + Sync {
+  /// The processed data returned by the callback.
+  type Output;
+  /// Whether the payloads are ignored, in which case they don't even need to be processed.
+  const IGNORED: bool = false;
+
+  /// Calls the callback, returning `None` if the payload was ignored.
+  fn call(&self, payload: P) -> impl Future<Output = Option<Self::Output>> + Send;
+}
+
+/// A [`Callback`] that ignores every payload.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ignore;
+
+impl<P: Send> Callback<P> for Ignore {
+  type Output = ();
+  const IGNORED: bool = true;
+
+  async fn call(&self, _payload: P) -> Option<Self::Output> {
+    None
+  }
+}
+
+impl<P, F, Fut> Callback<P> for F
+where
+  F: Fn(P) -> Fut + Send + Sync,
+  Fut: Future + Send,
+{
+  type Output = Fut::Output;
+
+  fn call(&self, payload: P) -> impl Future<Output = Option<Self::Output>> + Send {
+    let future = self(payload);
+    async move { Some(future.await) }
+  }
+}
+
+/// A [`Handler`] that calls a closure for each payload type, ignoring the payloads without one.
+///
+/// Commits are processed like the [`Firehose`] does, so the commit callback receives the decoded records.
+/// Build it with [`FnHandler::builder`].
+#[derive(Debug, Clone)]
+pub struct FnHandler<C, I0, A, H, M, T, I1> {
+  commit: C,
+  identity: I0,
+  account: A,
+  handle: H,
+  migrate: M,
+  tombstone: T,
+  info: I1,
+}
+
+/// A builder for the [`FnHandler`], where every callback ignores its payloads until it's set.
+#[derive(Debug, Clone)]
+pub struct FnHandlerBuilder<C, I0, A, H, M, T, I1>(FnHandler<C, I0, A, H, M, T, I1>);
+
+impl FnHandler<Ignore, Ignore, Ignore, Ignore, Ignore, Ignore, Ignore> {
+  /// Starts building a handler that ignores every payload.
+  #[must_use]
+  pub const fn builder() -> FnHandlerBuilder<Ignore, Ignore, Ignore, Ignore, Ignore, Ignore, Ignore>
+  {
+    FnHandlerBuilder(Self {
+      commit: Ignore,
+      identity: Ignore,
+      account: Ignore,
+      handle: Ignore,
+      migrate: Ignore,
+      tombstone: Ignore,
+      info: Ignore,
+    })
+  }
+}
+
+impl<C, I0, A, H, M, T, I1> FnHandlerBuilder<C, I0, A, H, M, T, I1> {
+  /// Sets the callback for `#commit` payloads.
+  pub fn on_commit<F, Fut>(self, callback: F) -> FnHandlerBuilder<F, I0, A, H, M, T, I1>
+  where
+    F: Fn(ProcessedCommitData) -> Fut + Send + Sync,
+    Fut: Future + Send,
+  {
+    let FnHandler {
+      identity,
+      account,
+      handle,
+      migrate,
+      tombstone,
+      info,
+      ..
+    } = self.0;
+    FnHandlerBuilder(FnHandler {
+      commit: callback,
+      identity,
+      account,
+      handle,
+      migrate,
+      tombstone,
+      info,
+    })
+  }
+
+  /// Sets the callback for `#identity` payloads.
+  pub fn on_identity<F, Fut>(self, callback: F) -> FnHandlerBuilder<C, F, A, H, M, T, I1>
+  where
+    F: Fn(IdentityData) -> Fut + Send + Sync,
+    Fut: Future + Send,
+  {
+    let FnHandler {
+      commit,
+      account,
+      handle,
+      migrate,
+      tombstone,
+      info,
+      ..
+    } = self.0;
+    FnHandlerBuilder(FnHandler {
+      commit,
+      identity: callback,
+      account,
+      handle,
+      migrate,
+      tombstone,
+      info,
+    })
+  }
+
+  /// Sets the callback for `#account` payloads.
+  pub fn on_account<F, Fut>(self, callback: F) -> FnHandlerBuilder<C, I0, F, H, M, T, I1>
+  where
+    F: Fn(AccountData) -> Fut + Send + Sync,
+    Fut: Future + Send,
+  {
+    let FnHandler {
+      commit,
+      identity,
+      handle,
+      migrate,
+      tombstone,
+      info,
+      ..
+    } = self.0;
+    FnHandlerBuilder(FnHandler {
+      commit,
+      identity,
+      account: callback,
+      handle,
+      migrate,
+      tombstone,
+      info,
+    })
+  }
+
+  /// Sets the callback for `#handle` payloads.
+  pub fn on_handle<F, Fut>(self, callback: F) -> FnHandlerBuilder<C, I0, A, F, M, T, I1>
+  where
+    F: Fn(HandleData) -> Fut + Send + Sync,
+    Fut: Future + Send,
+  {
+    let FnHandler {
+      commit,
+      identity,
+      account,
+      migrate,
+      tombstone,
+      info,
+      ..
+    } = self.0;
+    FnHandlerBuilder(FnHandler {
+      commit,
+      identity,
+      account,
+      handle: callback,
+      migrate,
+      tombstone,
+      info,
+    })
+  }
+
+  /// Sets the callback for `#migrate` payloads.
+  pub fn on_migrate<F, Fut>(self, callback: F) -> FnHandlerBuilder<C, I0, A, H, F, T, I1>
+  where
+    F: Fn(MigrateData) -> Fut + Send + Sync,
+    Fut: Future + Send,
+  {
+    let FnHandler {
+      commit,
+      identity,
+      account,
+      handle,
+      tombstone,
+      info,
+      ..
+    } = self.0;
+    FnHandlerBuilder(FnHandler {
+      commit,
+      identity,
+      account,
+      handle,
+      migrate: callback,
+      tombstone,
+      info,
+    })
+  }
+
+  /// Sets the callback for `#tombstone` payloads.
+  pub fn on_tombstone<F, Fut>(self, callback: F) -> FnHandlerBuilder<C, I0, A, H, M, F, I1>
+  where
+    F: Fn(TombstoneData) -> Fut + Send + Sync,
+    Fut: Future + Send,
+  {
+    let FnHandler {
+      commit,
+      identity,
+      account,
+      handle,
+      migrate,
+      info,
+      ..
+    } = self.0;
+    FnHandlerBuilder(FnHandler {
+      commit,
+      identity,
+      account,
+      handle,
+      migrate,
+      tombstone: callback,
+      info,
+    })
+  }
+
+  /// Sets the callback for `#info` payloads.
+  pub fn on_info<F, Fut>(self, callback: F) -> FnHandlerBuilder<C, I0, A, H, M, T, F>
+  where
+    F: Fn(ProcessedInfoData) -> Fut + Send + Sync,
+    Fut: Future + Send,
+  {
+    let FnHandler {
+      commit,
+      identity,
+      account,
+      handle,
+      migrate,
+      tombstone,
+      ..
+    } = self.0;
+    FnHandlerBuilder(FnHandler {
+      commit,
+      identity,
+      account,
+      handle,
+      migrate,
+      tombstone,
+      info: callback,
+    })
+  }
+
+  /// Builds the handler.
+  pub fn build(self) -> FnHandler<C, I0, A, H, M, T, I1> {
+    self.0
+  }
+}
+
+impl<C, I0, A, H, M, T, I1> Handler for FnHandler<C, I0, A, H, M, T, I1>
+where
+  C: Callback<ProcessedCommitData>,
+  I0: Callback<IdentityData>,
+  A: Callback<AccountData>,
+  H: Callback<HandleData>,
+  M: Callback<MigrateData>,
+  T: Callback<TombstoneData>,
+  I1: Callback<ProcessedInfoData>,
+{
+  type HandlingError = HandlingError;
+
+  type ProcessedCommitData = C::Output;
+  async fn process_commit(
+    &self,
+    payload: Commit,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedCommitData>>, Self::HandlingError> {
+    if C::IGNORED {
+      return Ok(None);
+    }
+    let Some(ProcessedPayload { seq, data }) = Firehose::default().process_commit(payload).await?
+    else {
+      return Ok(None);
+    };
+    Ok(call(&self.commit, seq, data).await)
+  }
+
+  type ProcessedIdentityData = I0::Output;
+  async fn process_identity(
+    &self,
+    payload: subscribe_repos::Identity,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedIdentityData>>, Self::HandlingError> {
+    Ok(call(&self.identity, Some(payload.seq), payload.data).await)
+  }
+
+  type ProcessedAccountData = A::Output;
+  async fn process_account(
+    &self,
+    payload: subscribe_repos::Account,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedAccountData>>, Self::HandlingError> {
+    Ok(call(&self.account, Some(payload.seq), payload.data).await)
+  }
+
+  type ProcessedHandleData = H::Output;
+  async fn process_handle(
+    &self,
+    payload: subscribe_repos::Handle,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedHandleData>>, Self::HandlingError> {
+    Ok(call(&self.handle, Some(payload.seq), payload.data).await)
+  }
+
+  type ProcessedMigrateData = M::Output;
+  async fn process_migrate(
+    &self,
+    payload: subscribe_repos::Migrate,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedMigrateData>>, Self::HandlingError> {
+    Ok(call(&self.migrate, Some(payload.seq), payload.data).await)
+  }
+
+  type ProcessedTombstoneData = T::Output;
+  async fn process_tombstone(
+    &self,
+    payload: subscribe_repos::Tombstone,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedTombstoneData>>, Self::HandlingError> {
+    Ok(call(&self.tombstone, Some(payload.seq), payload.data).await)
+  }
+
+  type ProcessedInfoData = I1::Output;
+  async fn process_info(
+    &self,
+    payload: subscribe_repos::Info,
+  ) -> Result<Option<ProcessedPayload<Self::ProcessedInfoData>>, Self::HandlingError> {
+    let Some(ProcessedPayload { seq, data }) = Firehose::default().process_info(payload).await?
+    else {
+      return Ok(None);
+    };
+    Ok(call(&self.info, seq, data).await)
+  }
+}
+
+/// Calls the callback, wrapping its output with the `seq`.
+async fn call<P, F: Callback<P>>(
+  callback: &F,
+  seq: Option<i64>,
+  payload: P,
+) -> Option<ProcessedPayload<F::Output>> {
+  let data = callback.call(payload).await?;
+  Some(ProcessedPayload { seq, data })
+}
@@ -0,0 +1,190 @@
+//! This file defines the types used in the Firehose handler.
+//!
+//! With the `serde` feature, they can be serialized, with CIDs as strings.
+
+#[cfg(all(test, feature = "serde"))]
+mod tests;
+
+use atrium_api::{
+  record::KnownRecord,
+  types::{
+    string::{Datetime, Did, Handle},
+    CidLink,
+  },
+};
+
+use crate::atrium_xrpc_wss::subscriptions::{repositories::InfoName, shards::Partition};
+
+// region: Commit
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct ProcessedCommitData {
+  pub repo: Did,
+  /// The CID of the commit, which is `None` for commits received from Jetstream, since it doesn't send it.
+  #[cfg_attr(feature = "serde", serde(with = "cid_string::option"))]
+  pub commit: Option<CidLink>,
+  // `ops` can be `None` if the commit is marked as `too_big`.
+  pub ops: Option<Vec<Operation>>,
+  #[cfg_attr(feature = "serde", serde(with = "cid_string::vec"))]
+  pub blobs: Vec<CidLink>,
+  pub rev: String,
+  pub since: Option<String>,
+  pub time: Datetime,
+}
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Operation {
+  pub action: String,
+  pub path: String,
+  /// The CID of the record, which is `None` for deletions.
+  #[cfg_attr(feature = "serde", serde(with = "cid_string::option"))]
+  pub cid: Option<CidLink>,
+  pub record: Option<KnownRecord>,
+}
+// endregion: Commit
+
+// region: Identity
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessedIdentityData {
+  pub did: Did,
+  pub handle: Option<Handle>,
+  pub time: Datetime,
+}
+// endregion: Identity
+
+// region: Account
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessedAccountData {
+  pub did: Did,
+  pub active: bool,
+  // `status` is the reason why the account is not active, if any.
+  pub status: Option<String>,
+  pub time: Datetime,
+}
+// endregion: Account
+
+// region: Handle
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessedHandleData {}
+// endregion: Handle
+
+// region: Migrate
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessedMigrateData {}
+// endregion: Migrate
+
+// region: Tombstone
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessedTombstoneData {}
+// endregion: Tombstone
+
+// region: Info
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessedInfoData {
+  pub name: InfoName,
+  pub message: Option<String>,
+}
+// endregion: Info
+
+// region: Partition
+impl Partition for ProcessedCommitData {
+  fn repo(&self) -> Option<&str> {
+    Some(self.repo.as_str())
+  }
+}
+
+impl Partition for ProcessedIdentityData {
+  fn repo(&self) -> Option<&str> {
+    Some(self.did.as_str())
+  }
+}
+
+impl Partition for ProcessedAccountData {
+  fn repo(&self) -> Option<&str> {
+    Some(self.did.as_str())
+  }
+}
+
+/// The other processed types don't carry their DID yet, so they all go to the first shard.
+macro_rules! impl_partition_without_repo {
+  ($($ty:ty),*) => {
+    $(
+      impl Partition for $ty {
+        fn repo(&self) -> Option<&str> {
+          None
+        }
+      }
+    )*
+  };
+}
+impl_partition_without_repo!(
+  ProcessedHandleData,
+  ProcessedMigrateData,
+  ProcessedTombstoneData,
+  ProcessedInfoData
+);
+// endregion: Partition
+
+// region: Serde
+/// Serializes [`CidLink`]s as their string representations.
+#[cfg(feature = "serde")]
+mod cid_string {
+  use atrium_api::types::CidLink;
+  use ipld_core::cid::Cid;
+  use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+  /// Serializes a list of [`CidLink`]s as their string representations.
+  pub mod vec {
+    use super::{Cid, CidLink, Deserialize, Deserializer, Error, Serializer};
+
+    pub fn serialize<S: Serializer>(cids: &[CidLink], serializer: S) -> Result<S::Ok, S::Error> {
+      serializer.collect_seq(cids.iter().map(|cid| cid.0.to_string()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+      deserializer: D,
+    ) -> Result<Vec<CidLink>, D::Error> {
+      Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|cid| Cid::try_from(cid.as_str()).map(CidLink))
+        .collect::<Result<_, _>>()
+        .map_err(D::Error::custom)
+    }
+  }
+
+  /// Serializes an optional [`CidLink`] as its string representation.
+  pub mod option {
+    use super::{Cid, CidLink, Deserialize, Deserializer, Error, Serializer};
+
+    #[expect(
+      clippy::ref_option,
+      reason = "The signature is required by `serde(with)`."
+    )]
+    pub fn serialize<S: Serializer>(
+      cid: &Option<CidLink>,
+      serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+      match cid {
+        Some(cid) => serializer.collect_str(&cid.0),
+        None => serializer.serialize_none(),
+      }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+      deserializer: D,
+    ) -> Result<Option<CidLink>, D::Error> {
+      Option::<String>::deserialize(deserializer)?
+        .map(|cid| Cid::try_from(cid.as_str()).map(CidLink))
+        .transpose()
+        .map_err(D::Error::custom)
+    }
+  }
+}
+// endregion: Serde
@@ -0,0 +1,109 @@
+use atrium_api::{app::bsky::feed::post, types::string::Datetime};
+use serde_json::json;
+
+use super::*;
+use crate::{
+  atrium_xrpc_wss::subscriptions::{
+    repositories::{HandledData, ProcessedData},
+    ProcessedPayload,
+  },
+  atrium_xrpc_wss_client::subscriptions::repositories::{car::tests as car, firehose::Firehose},
+};
+
+type Payload = ProcessedPayload<HandledData<Firehose>>;
+
+/// Serializes the payload to JSON, checking that it deserializes back to the same JSON.
+fn round_trip(payload: &Payload) -> serde_json::Value {
+  let json = serde_json::to_value(payload).expect("failed to serialize");
+  let deserialized =
+    serde_json::from_value::<Payload>(json.clone()).expect("failed to deserialize");
+  assert_eq!(
+    serde_json::to_value(deserialized).expect("failed to serialize"),
+    json
+  );
+  json
+}
+
+#[test]
+fn serialize_commit() {
+  let time = "2024-01-01T00:00:00.000Z"
+    .parse::<Datetime>()
+    .expect("invalid datetime");
+  let payload = Payload {
+    seq: Some(1),
+    data: ProcessedData::Commit(ProcessedCommitData {
+      repo: "did:plc:abc".parse().expect("invalid did"),
+      commit: Some(CidLink(car::cid(1))),
+      ops: Some(vec![Operation {
+        action: String::from("create"),
+        path: String::from("app.bsky.feed.post/1"),
+        cid: Some(CidLink(car::cid(3))),
+        record: Some(KnownRecord::from(post::RecordData {
+          created_at: time.clone(),
+          embed: None,
+          entities: None,
+          facets: None,
+          labels: None,
+          langs: None,
+          reply: None,
+          tags: None,
+          text: String::from("Hello"),
+        })),
+      }]),
+      blobs: vec![CidLink(car::cid(2))],
+      rev: String::from("3k"),
+      since: None,
+      time,
+    }),
+  };
+
+  assert_eq!(
+    round_trip(&payload),
+    json!({
+      "seq": 1,
+      "data": {
+        "kind": "commit",
+        "data": {
+          "repo": "did:plc:abc",
+          "commit": car::cid(1).to_string(),
+          "ops": [{
+            "action": "create",
+            "path": "app.bsky.feed.post/1",
+            "cid": car::cid(3).to_string(),
+            "record": {
+              "$type": "app.bsky.feed.post",
+              "createdAt": "2024-01-01T00:00:00.000Z",
+              "text": "Hello",
+            },
+          }],
+          "blobs": [car::cid(2).to_string()],
+          "rev": "3k",
+          "since": null,
+          "time": "2024-01-01T00:00:00.000Z",
+        },
+      },
+    })
+  );
+}
+
+#[test]
+fn serialize_info() {
+  let payload = Payload {
+    seq: None,
+    data: ProcessedData::Info(ProcessedInfoData {
+      name: InfoName::OutdatedCursor,
+      message: None,
+    }),
+  };
+
+  assert_eq!(
+    round_trip(&payload),
+    json!({
+      "seq": null,
+      "data": {
+        "kind": "info",
+        "data": { "name": "OutdatedCursor", "message": null },
+      },
+    })
+  );
+}
@@ -0,0 +1,115 @@
+//! This file provides a local `com.atproto.sync.subscribeRepos` server, meant for tests.
+//! Outside of the tests of this crate, it's only built with the `test-server` feature.
+//!
+//! It serves a scripted sequence of [`Frame`]s to every client that connects, honoring the `cursor`
+//! query parameter, so subscriptions can be tested end to end without network access.
+
+#[cfg(test)]
+pub(crate) mod tests;
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use atrium_api::com::atproto::sync::subscribe_repos;
+use futures::SinkExt;
+use tokio::{
+  net::{TcpListener, TcpStream},
+  task::JoinHandle,
+};
+use tokio_tungstenite::{
+  accept_hdr_async,
+  tungstenite::{
+    handshake::server::{Request, Response},
+    Message,
+  },
+};
+
+use crate::atrium_xrpc_wss::{client::XrpcUri, subscriptions::frames::Frame};
+
+/// A `WebSocket` server bound to a random local port, serving a scripted sequence of frames.
+///
+/// Message frames are only sent if their `seq` is greater than the client's `cursor`.
+/// Frames without a `seq` (like `#info` and error frames) are always sent.
+/// After the last frame, the server closes the connection.
+///
+/// The server stops when dropped.
+pub struct TestServer {
+  host: String,
+  task: JoinHandle<()>,
+}
+
+impl TestServer {
+  /// Binds the server to a random local port and starts serving the `frames`.
+  ///
+  /// # Errors
+  /// Returns an error if the server could not be bound.
+  #[expect(
+    tail_expr_drop_order,
+    reason = "The clients are served by their own tasks, whose handles can be dropped in any order."
+  )]
+  pub async fn bind(frames: Vec<Frame>) -> io::Result<Self> {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+    let host = listener.local_addr()?.to_string();
+    let frames = Arc::new(frames);
+
+    let task = tokio::spawn(async move {
+      while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve(stream, Arc::clone(&frames)));
+      }
+    });
+
+    Ok(Self { host, task })
+  }
+
+  /// The `host:port` the server is listening on.
+  #[must_use]
+  pub fn host(&self) -> &str {
+    &self.host
+  }
+
+  /// The [`XrpcUri`] clients should connect to.
+  #[must_use]
+  pub fn xrpc_uri(&self) -> XrpcUri<'_> {
+    XrpcUri::insecure(&self.host, subscribe_repos::NSID)
+  }
+}
+
+impl Drop for TestServer {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
+
+/// Serves the frames to a single client.
+async fn serve(stream: TcpStream, frames: Arc<Vec<Frame>>) {
+  let mut cursor = None;
+  #[expect(
+    clippy::result_large_err,
+    reason = "The signature is defined by `tungstenite`."
+  )]
+  let callback = |request: &Request, response: Response| {
+    cursor = request
+      .uri()
+      .query()
+      .and_then(|q| serde_html_form::from_str::<subscribe_repos::ParametersData>(q).ok())
+      .and_then(|p| p.cursor);
+    Ok(response)
+  };
+  let Ok(mut ws) = accept_hdr_async(stream, callback).await else {
+    return;
+  };
+
+  for frame in frames.iter() {
+    if let (Some(cursor), Some(seq)) = (cursor, frame.seq()) {
+      if seq <= cursor {
+        continue;
+      }
+    }
+    let Ok(data) = frame.encode() else {
+      continue;
+    };
+    if ws.send(Message::Binary(data.into())).await.is_err() {
+      return;
+    }
+  }
+  drop(ws.close(None).await);
+}
@@ -0,0 +1,197 @@
+use atrium_api::{
+  app::bsky::feed::post,
+  com::atproto::sync::subscribe_repos::{CommitData, InfoData, RepoOpData},
+  record::KnownRecord,
+  types::{string::Datetime, CidLink},
+};
+use futures::StreamExt;
+
+use super::*;
+use crate::{
+  atrium_xrpc_wss::{
+    client::WssClient,
+    subscriptions::{
+      repositories::{self, HandledData, InfoName, ProcessedData, Repositories},
+      SubscriptionError,
+    },
+  },
+  atrium_xrpc_wss_client::{
+    subscriptions::repositories::{car::tests as car, firehose::Firehose, fn_handler::FnHandler},
+    XrpcWssClient,
+  },
+};
+
+type ProcessedPayload =
+  crate::atrium_xrpc_wss::subscriptions::ProcessedPayload<HandledData<Firehose>>;
+
+pub fn commit(seq: i64, too_big: bool) -> Frame {
+  let record = KnownRecord::from(post::RecordData {
+    created_at: Datetime::now(),
+    embed: None,
+    entities: None,
+    facets: None,
+    labels: None,
+    langs: None,
+    reply: None,
+    tags: None,
+    text: format!("Post #{seq}"),
+  });
+  let block = car::block(serde_ipld_dagcbor::to_vec(&record).expect("failed to serialize"));
+  let cid = block.0;
+  let blocks = if too_big {
+    Vec::new()
+  } else {
+    car::car(&[block]).into()
+  };
+  let data = CommitData {
+    blobs: vec![],
+    blocks,
+    commit: CidLink(cid),
+    ops: vec![RepoOpData {
+      action: String::from("create"),
+      cid: Some(CidLink(cid)),
+      path: format!("app.bsky.feed.post/{seq}"),
+    }
+    .into()],
+    prev: None,
+    rebase: false,
+    repo: "did:plc:abc".parse().expect("invalid did"),
+    rev: seq.to_string(),
+    seq,
+    since: None,
+    time: Datetime::now(),
+    too_big,
+  };
+  Frame::message("#commit", &data).expect("failed to serialize")
+}
+
+fn frames() -> Vec<Frame> {
+  vec![
+    commit(1, false),
+    commit(2, true),
+    Frame::message(
+      "#info",
+      &InfoData {
+        message: None,
+        name: String::from("OutdatedCursor"),
+      },
+    )
+    .expect("failed to serialize"),
+    Frame::Error {
+      error: String::from("ConsumerTooSlow"),
+      message: None,
+    },
+  ]
+}
+
+async fn subscribe(
+  server: &TestServer,
+  cursor: Option<i64>,
+) -> Vec<Result<ProcessedPayload, SubscriptionError<repositories::Error>>> {
+  let client = XrpcWssClient::builder()
+    .xrpc_uri(server.xrpc_uri())
+    .params(subscribe_repos::ParametersData { cursor })
+    .build();
+  let connection = client.connect().await.expect("failed to connect");
+  Repositories::builder()
+    .connection(connection)
+    .handler(Firehose::default())
+    .build()
+    .collect()
+    .await
+}
+
+#[tokio::test]
+async fn subscribe_from_start() {
+  let server = TestServer::bind(frames()).await.expect("failed to bind");
+  let mut results = subscribe(&server, None).await.into_iter();
+
+  let Some(Ok(ProcessedPayload {
+    seq: Some(1),
+    data: ProcessedData::Commit(commit),
+  })) = results.next()
+  else {
+    panic!("expected the first commit");
+  };
+  let ops = commit.ops.expect("expected ops");
+  assert!(matches!(
+    &ops[0].record,
+    Some(KnownRecord::AppBskyFeedPost(post)) if post.text == "Post #1"
+  ));
+
+  let Some(Ok(ProcessedPayload {
+    seq: Some(2),
+    data: ProcessedData::Commit(commit),
+  })) = results.next()
+  else {
+    panic!("expected the second commit");
+  };
+  assert!(commit.ops.is_none());
+
+  assert!(matches!(
+    results.next(),
+    Some(Ok(ProcessedPayload {
+      seq: None,
+      data: ProcessedData::Info(info),
+    })) if info.name == InfoName::OutdatedCursor
+  ));
+  assert!(matches!(
+    results.next(),
+    Some(Err(SubscriptionError::Other(
+      repositories::Error::ConsumerTooSlow(None)
+    )))
+  ));
+  assert!(results.next().is_none());
+}
+
+#[tokio::test]
+async fn subscribe_from_cursor() {
+  let server = TestServer::bind(frames()).await.expect("failed to bind");
+  let results = subscribe(&server, Some(1)).await;
+
+  assert_eq!(results.len(), 3);
+  assert!(matches!(
+    results[0],
+    Ok(ProcessedPayload {
+      seq: Some(2),
+      data: ProcessedData::Commit(_),
+    })
+  ));
+}
+
+#[tokio::test]
+async fn subscribe_with_fn_handler() {
+  let server = TestServer::bind(frames()).await.expect("failed to bind");
+  let client = XrpcWssClient::builder()
+    .xrpc_uri(server.xrpc_uri())
+    .params(subscribe_repos::ParametersData { cursor: None })
+    .build();
+  let connection = client.connect().await.expect("failed to connect");
+  let handler = FnHandler::builder()
+    .on_commit(|commit| async move { commit.ops.map_or(0, |ops| ops.len()) })
+    .build();
+  let results = Repositories::builder()
+    .connection(connection)
+    .handler(handler)
+    .build()
+    .collect::<Vec<_>>()
+    .await;
+
+  // The `#info` is ignored, since there's no callback for it.
+  assert_eq!(results.len(), 3);
+  assert!(matches!(
+    results[0],
+    Ok(crate::atrium_xrpc_wss::subscriptions::ProcessedPayload {
+      seq: Some(1),
+      data: ProcessedData::Commit(1),
+    })
+  ));
+  assert!(matches!(
+    results[1],
+    Ok(crate::atrium_xrpc_wss::subscriptions::ProcessedPayload {
+      seq: Some(2),
+      data: ProcessedData::Commit(0),
+    })
+  ));
+  assert!(results[2].is_err());
+}
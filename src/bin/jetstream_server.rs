@@ -0,0 +1,93 @@
+//! Subscribes to the `ATProto` Firehose once, and re-broadcasts it to local clients as Jetstream events.
+//!
+//! Usage: `jetstream-server [listen address] [relay host]`, which default to `127.0.0.1:6008` and `bsky.network`.
+//! Clients connect to `ws://{listen address}/subscribe`, with the Jetstream query parameters.
+
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::Context;
+use atrium_api::com::atproto::sync::subscribe_repos;
+use firehose_client::{
+  atrium_xrpc_wss::{
+    client::{WssClient, XrpcUri},
+    subscriptions::{repositories::Repositories, ProcessedPayload, Retryable},
+  },
+  atrium_xrpc_wss_client::{
+    jetstream::{server::JetstreamServer, Clock, Event},
+    subscriptions::repositories::firehose::Firehose,
+    XrpcWssClient,
+  },
+};
+use futures::StreamExt;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+  let mut args = std::env::args().skip(1);
+  let addr = args
+    .next()
+    .as_deref()
+    .unwrap_or("127.0.0.1:6008")
+    .parse::<SocketAddr>()
+    .context("invalid listen address")?;
+  let relay = args.next().unwrap_or_else(|| String::from("bsky.network"));
+
+  let server = JetstreamServer::builder().addr(addr).bind().await?;
+  eprintln!("Listening on ws://{}/subscribe", server.local_addr());
+
+  let xrpc_uri = XrpcUri::new(&relay, subscribe_repos::NSID);
+  let mut last_cursor = None;
+  // Kept across reconnections, so that the cursors of the clients keep increasing.
+  let mut clock = Clock::default();
+  loop {
+    match relay_events(&server, &xrpc_uri, &mut last_cursor, &mut clock).await {
+      Ok(()) => eprintln!("Disconnected, reconnecting"),
+      Err(e) => eprintln!("Disconnected, reconnecting: {e}"),
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+  }
+}
+
+/// Publishes the events of the relay until the connection is dropped.
+///
+/// Returns the error that ended the connection, if any.
+#[expect(
+  tail_expr_drop_order,
+  reason = "Every event is published before the next payload is read, so it doesn't matter when the payload is freed."
+)]
+async fn relay_events(
+  server: &JetstreamServer,
+  xrpc_uri: &XrpcUri<'_>,
+  last_cursor: &mut Option<i64>,
+  clock: &mut Clock,
+) -> anyhow::Result<()> {
+  let client = XrpcWssClient::builder()
+    .xrpc_uri(xrpc_uri.clone())
+    .params(subscribe_repos::ParametersData {
+      cursor: *last_cursor,
+    })
+    .build();
+  let connection = client.connect().await?;
+  let mut subscription = Repositories::builder()
+    .connection(connection)
+    .handler(Firehose::default())
+    .build();
+
+  while let Some(payload) = subscription.next().await {
+    let payload = match payload {
+      Ok(payload) => payload,
+      Err(e) if e.is_retryable() => return Err(e.into()),
+      Err(e) => {
+        // The cursor can't be resumed from, so the next connection starts from the live stream.
+        *last_cursor = None;
+        return Err(e.into());
+      }
+    };
+    if let ProcessedPayload { seq: Some(seq), .. } = payload {
+      *last_cursor = Some(seq);
+    }
+    for event in Event::from_payload(payload, clock)? {
+      server.publish(event)?;
+    }
+  }
+  Ok(())
+}
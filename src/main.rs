@@ -1,148 +1,750 @@
-use anyhow::bail;
-use atrium_api::com::atproto::sync::subscribe_repos::{self, InfoData};
+//! A command line client for the `ATProto` Firehose, built with the `cli` feature.
+//!
+//! - `tail` prints the events of a relay as they arrive.
+//! - `record` records the raw frames of a relay into an archive.
+//! - `replay` prints the events of an archive.
+//! - `stats` counts the events of a relay or an archive, printing a summary periodically.
+//!
+//! When the connection to the relay is dropped, it reconnects from the last seq.
+//! With `--cursor-file`, the last seq is also saved to resume from it on the next run.
+//!
+//! The exit code is `0` when the stream ended or was interrupted, `1` after a fatal subscription
+//! error (like `FutureCursor` or an invalid frame), `2` for invalid arguments, and `3` after an IO error.
+
+use std::{
+  cmp::Reverse,
+  collections::BTreeMap,
+  fmt::Display,
+  fs,
+  io::{self, BufWriter, Stdout, Write},
+  path::PathBuf,
+  process::ExitCode,
+  time::{Duration, Instant},
+};
+
+use atrium_api::{com::atproto::sync::subscribe_repos, types::string::Did};
+use chrono::Utc;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use firehose_client::{
   atrium_xrpc_wss::{
     client::{WssClient, XrpcUri},
     subscriptions::{
-      repositories::{ProcessedData, Repositories},
-      ProcessedPayload, SubscriptionError,
+      frames::Frame,
+      repositories::{self, read_frame, HandledData, ProcessedData, Repositories},
+      ProcessedPayload, Retryable, SubscriptionError,
     },
   },
   atrium_xrpc_wss_client::{
+    archive::{ArchiveRecord, Recorder, Replay},
+    jetstream::{self, Clock, Event},
+    json::{record_to_json, Dialect},
     subscriptions::repositories::{
+      binary_frames,
+      filter::Filter,
       firehose::Firehose,
-      type_defs::{Operation, ProcessedCommitData},
+      type_defs::{
+        Operation, ProcessedAccountData, ProcessedCommitData, ProcessedIdentityData,
+        ProcessedInfoData,
+      },
     },
     Error, XrpcWssClient,
   },
 };
 use futures::StreamExt;
-use tokio_tungstenite::tungstenite;
+use tokio::sync::mpsc;
 
-/// This example demonstrates how to connect to the ATProto Firehose.
-#[tokio::main]
-async fn main() {
-  // Define the XrpcUri for the subscription.
-  let xrpc_uri = XrpcUri::new("bsky.network", subscribe_repos::NSID);
-
-  // Caching the last cursor is important.
-  // The API has a backfilling mechanism that allows you to resume from where you stopped.
-  let mut last_cursor = Some(1);
-  drop(connect(&mut last_cursor, &xrpc_uri).await);
-}
-
-/// Connects to `ATProto` to receive real-time data.
-async fn connect(
-  last_cursor: &mut Option<i64>,
-  xrpc_uri: &XrpcUri<'_>,
-) -> Result<(), anyhow::Error> {
-  // Define the query parameters. In this case, just the cursor.
-  let params = subscribe_repos::ParametersData {
-    cursor: *last_cursor,
-  };
+type Payload = ProcessedPayload<HandledData<Firehose>>;
 
-  // Build a new XRPC WSS Client.
-  let client = XrpcWssClient::builder()
-    .xrpc_uri(xrpc_uri.clone())
-    .params(params)
-    .build();
+/// The delay before reconnecting the first time, which is doubled after each failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_mins(1);
+/// How often the cursor file is written.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+/// How many frames `record` queues for the blocking thread writing them.
+const QUEUE_SIZE: usize = 1024;
 
-  // And then we connect to the API.
-  let connection = match client.connect().await {
-    Ok(connection) => connection,
-    Err(Error::Connection(tungstenite::Error::Http(response))) => {
-      // According to the API documentation, the following status codes are expected and should be treated accordingly:
-      // 405 Method Not Allowed: Returned to client for non-GET HTTP requests to a stream endpoint.
-      // 426 Upgrade Required: Returned to client if Upgrade header is not included in a request to a stream endpoint.
-      // 429 Too Many Requests: Frequently used for rate-limiting. Client may try again after a delay. Support for the Retry-After header is encouraged.
-      // 500 Internal Server Error: Client may try again after a delay
-      // 501 Not Implemented: Service does not implement WebSockets or streams, at least for this endpoint. Client should not try again.
-      // 502 Bad Gateway, 503 Service Unavailable, 504 Gateway Timeout: Client may try again after a delay.
-      // https://atproto.com/specs/event-stream
-      bail!("Status Code was: {response:?}")
-    }
-    Err(e) => bail!(e),
-  };
+/// A command line client for the `ATProto` Firehose.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
 
-  // Builds a new subscription from the connection, using handler provided
-  // by atrium-xrpc-wss-client, the `Firehose`.
-  let mut subscription = Repositories::builder()
-    .connection(connection)
-    .handler(Firehose)
-    .build();
+#[derive(Subcommand)]
+enum Command {
+  /// Prints the events of a relay as they arrive.
+  Tail {
+    #[command(flatten)]
+    relay: RelayArgs,
+    #[command(flatten)]
+    filter: FilterArgs,
+    /// The output format.
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
+  },
+  /// Records the raw frames of a relay into an archive.
+  Record {
+    #[command(flatten)]
+    relay: RelayArgs,
+    /// The directory of the archive.
+    #[arg(long, short)]
+    output: PathBuf,
+    /// Compresses the segments with zstd, which requires the `zstd` feature.
+    #[arg(long)]
+    compress: bool,
+    /// The size in bytes after which a new segment is started.
+    #[arg(long)]
+    max_segment_size: Option<u64>,
+  },
+  /// Prints the events of an archive.
+  Replay {
+    #[command(flatten)]
+    archive: ArchiveArgs,
+    #[command(flatten)]
+    filter: FilterArgs,
+    /// The output format.
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
+  },
+  /// Counts the events of a relay, or of an archive, printing a summary periodically.
+  Stats {
+    #[command(flatten)]
+    relay: RelayArgs,
+    /// Counts the events of this archive instead of a relay.
+    #[arg(long, conflicts_with_all = ["cursor", "cursor_file"])]
+    archive: Option<PathBuf>,
+    #[command(flatten)]
+    filter: FilterArgs,
+    /// The seconds between summaries.
+    #[arg(long, default_value_t = 10)]
+    interval: u64,
+  },
+}
 
-  // Receive payloads by calling `StreamExt::next()`.
-  while let Some(payload) = subscription.next().await {
-    let data = match payload {
-      Ok(ProcessedPayload { seq, data }) => {
-        if let Some(seq) = seq {
-          *last_cursor = Some(seq);
+#[derive(Args)]
+struct RelayArgs {
+  /// The host of the relay.
+  #[arg(long, default_value = "bsky.network")]
+  relay: String,
+  /// The seq to start from. Defaults to the one in the cursor file, if any, or to the live stream.
+  #[arg(long)]
+  cursor: Option<i64>,
+  /// A file where the last seq is saved, to resume from it on the next run.
+  #[arg(long)]
+  cursor_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ArchiveArgs {
+  /// A segment file, or a directory of segments.
+  path: PathBuf,
+  /// Skips the frames before this seq.
+  #[arg(long)]
+  from_seq: Option<i64>,
+  /// Paces the frames by their receive times, scaled by this factor. Defaults to no pacing.
+  #[arg(long)]
+  speed: Option<f64>,
+}
+
+#[derive(Args)]
+struct FilterArgs {
+  /// Only keeps the operations on this collection, which can contain `*` wildcards. Can be repeated.
+  #[arg(long = "collection", value_name = "NSID")]
+  collections: Vec<String>,
+  /// Only keeps the events of this repository. Can be repeated.
+  #[arg(long = "did", value_name = "DID")]
+  dids: Vec<Did>,
+}
+
+impl FilterArgs {
+  /// Builds the handler, which skips the commits left without operations when filtering collections.
+  fn firehose(self) -> Firehose {
+    let Self { collections, dids } = self;
+    let drop_empty_commits = !collections.is_empty();
+    Firehose::new(
+      Filter::builder()
+        .collections(collections)
+        .maybe_allowed_dids((!dids.is_empty()).then(|| dids.into_iter().collect()))
+        .drop_empty_commits(drop_empty_commits)
+        .build(),
+    )
+  }
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum Format {
+  /// Human readable lines.
+  #[default]
+  Pretty,
+  /// One JSON object per payload.
+  Json,
+  /// One Jetstream event per line.
+  Jetstream,
+}
+
+/// An error that ends the program.
+#[derive(Debug, thiserror::Error)]
+enum Fatal {
+  #[error("Connection failed: {0}")]
+  Connection(#[source] Box<Error>),
+  #[error("Subscription failed: {0}")]
+  Subscription(#[from] SubscriptionError<repositories::Error>),
+  #[error("Conversion failed: {0}")]
+  Conversion(#[from] jetstream::Error),
+  #[error("IO error: {0}")]
+  Io(#[from] io::Error),
+}
+
+impl From<Error> for Fatal {
+  fn from(e: Error) -> Self {
+    Self::Connection(Box::new(e))
+  }
+}
+
+impl Fatal {
+  const fn exit_code(&self) -> u8 {
+    match self {
+      Self::Connection(_) | Self::Subscription(_) | Self::Conversion(_) => 1,
+      Self::Io(_) => 3,
+    }
+  }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+  let Cli { command } = Cli::parse();
+  let result = tokio::select! {
+    result = run(command) => result,
+    // The cursor file and the archive are saved when dropped, so interrupting is fine.
+    _ = tokio::signal::ctrl_c() => Ok(()),
+  };
+  match result {
+    Ok(()) => ExitCode::SUCCESS,
+    // The output was closed, like when piped into `head`.
+    Err(Fatal::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("Error: {e}");
+      ExitCode::from(e.exit_code())
+    }
+  }
+}
+
+#[expect(
+  tail_expr_drop_order,
+  reason = "The output is flushed after every payload, so it doesn't matter when it's dropped."
+)]
+async fn run(command: Command) -> Result<(), Fatal> {
+  match command {
+    Command::Tail {
+      relay,
+      filter,
+      format,
+    } => {
+      let mut output = Output::new(format);
+      follow(&relay, filter.firehose(), |payload| output.write(payload)).await
+    }
+    Command::Record {
+      relay,
+      output,
+      compress,
+      max_segment_size,
+    } => {
+      let recorder = Recorder::builder()
+        .directory(output)
+        .compress(compress)
+        .maybe_max_segment_size(max_segment_size)
+        .build()?;
+      record(&relay, recorder).await
+    }
+    Command::Replay {
+      archive,
+      filter,
+      format,
+    } => {
+      let mut output = Output::new(format);
+      replay(archive, filter.firehose(), |payload| output.write(payload)).await
+    }
+    Command::Stats {
+      relay,
+      archive,
+      filter,
+      interval,
+    } => {
+      let mut stats = Stats::new(Duration::from_secs(interval));
+      let on_payload = |payload: Payload| {
+        stats.count(&payload);
+        Ok(())
+      };
+      match archive {
+        Some(path) => {
+          let archive = ArchiveArgs {
+            path,
+            from_seq: None,
+            speed: None,
+          };
+          replay(archive, filter.firehose(), on_payload).await
         }
-        data
+        None => follow(&relay, filter.firehose(), on_payload).await,
       }
-      Err(SubscriptionError::Abort(reason)) => {
-        // This could mean multiple things, all of which are critical errors that require
-        // immediate termination of connection.
-        eprintln!("Aborted: {reason}");
-        *last_cursor = None;
-        break;
+    }
+  }
+}
+
+/// Follows the relay, calling `on_payload` for each payload.
+///
+/// After retryable errors, it reconnects from the last seq, waiting longer after each failed attempt.
+#[expect(
+  tail_expr_drop_order,
+  reason = "The payloads are written before the next one is received, whatever the order they are dropped in."
+)]
+async fn follow(
+  relay: &RelayArgs,
+  firehose: Firehose,
+  mut on_payload: impl FnMut(Payload) -> Result<(), Fatal>,
+) -> Result<(), Fatal> {
+  let xrpc_uri = XrpcUri::new(&relay.relay, subscribe_repos::NSID);
+  let mut cursor = CursorFile::open(relay)?;
+  let mut backoff = Backoff::default();
+  loop {
+    let client = XrpcWssClient::builder()
+      .xrpc_uri(xrpc_uri.clone())
+      .params(subscribe_repos::ParametersData { cursor: cursor.seq })
+      .build();
+    let connection = match client.connect().await {
+      Ok(connection) => connection,
+      Err(Error::Connection(e)) => {
+        backoff.wait(e).await;
+        continue;
       }
-      Err(e) => {
-        // Errors such as `FutureCursor` and `ConsumerTooSlow` can be dealt with here.
-        eprintln!("{e:?}");
-        *last_cursor = None;
-        break;
+      Err(e) => return Err(e.into()),
+    };
+
+    let mut subscription = Repositories::builder()
+      .connection(connection)
+      .handler(firehose.clone())
+      .build();
+    let reason = loop {
+      match subscription.next().await {
+        Some(Ok(payload)) => {
+          backoff.reset();
+          warn_data_loss(&payload);
+          let seq = payload.seq;
+          on_payload(payload)?;
+          if let Some(seq) = seq {
+            cursor.set(seq);
+          }
+          if cursor.is_due() {
+            cursor.save()?;
+          }
+        }
+        Some(Err(e)) if e.is_retryable() => break e.to_string(),
+        Some(Err(e)) => return Err(e.into()),
+        None => break String::from("closed by the relay"),
       }
     };
+    backoff.wait(reason).await;
+  }
+}
 
-    match data {
-      ProcessedData::Commit(data) => beauty_print_commit(data),
-      ProcessedData::Info(InfoData { message, name }) => {
-        println!("Received info. Message: {message:?}; Name: {name}.");
+/// Records the raw frames of the relay, reconnecting like [`follow`].
+///
+/// The frames are recorded as received, before being checked, so the archive also keeps the
+/// frame that ended the connection.
+async fn record(relay: &RelayArgs, mut recorder: Recorder) -> Result<(), Fatal> {
+  let xrpc_uri = XrpcUri::new(&relay.relay, subscribe_repos::NSID);
+  let mut cursor = CursorFile::open(relay)?;
+  let mut backoff = Backoff::default();
+  loop {
+    let client = XrpcWssClient::builder()
+      .xrpc_uri(xrpc_uri.clone())
+      .params(subscribe_repos::ParametersData { cursor: cursor.seq })
+      .build();
+    let connection = match client.connect().await {
+      Ok(connection) => connection,
+      Err(Error::Connection(e)) => {
+        backoff.wait(e).await;
+        continue;
       }
-      _ => { /* Ignored */ }
+      Err(e) => return Err(e.into()),
+    };
+
+    // The recorder and the cursor are handed back once the frames of this connection are written.
+    let (sender, mut receiver) = mpsc::channel(QUEUE_SIZE);
+    let writer = tokio::task::spawn_blocking(move || {
+      let result = write_records(&mut recorder, &mut cursor, &mut receiver);
+      (recorder, cursor, result)
+    });
+
+    let mut frames = binary_frames(connection);
+    let reason = loop {
+      let frame = match frames.next().await {
+        Some(Ok(frame)) => frame,
+        Some(Err(e)) => break Ok(e.to_string()),
+        None => break Ok(String::from("closed by the relay")),
+      };
+      backoff.reset();
+      let received_at = Utc::now().timestamp_micros();
+      let (seq, end) = match read_frame(frame.clone()) {
+        Ok(Some((t, data))) => ((Frame::Message { t, data }).seq(), None),
+        Ok(None) => (None, None),
+        Err(e) if e.is_retryable() => (None, Some(Ok(e.to_string()))),
+        Err(e) => (None, Some(Err(e))),
+      };
+      let record = ArchiveRecord {
+        received_at,
+        seq,
+        frame,
+      };
+      // The writer only stops early after an error, which is returned below.
+      if sender.send(record).await.is_err() {
+        break Ok(String::new());
+      }
+      if let Some(end) = end {
+        break end;
+      }
+    };
+    drop(sender);
+    let written;
+    (recorder, cursor, written) = writer.await.map_err(io::Error::other)?;
+    written?;
+    backoff.wait(reason?).await;
+  }
+}
+
+/// Writes the records sent by [`record`], moving the cursor along.
+#[expect(
+  tail_expr_drop_order,
+  reason = "The frames are recorded before the next one is received, so freeing them later changes nothing."
+)]
+fn write_records(
+  recorder: &mut Recorder,
+  cursor: &mut CursorFile,
+  receiver: &mut mpsc::Receiver<ArchiveRecord>,
+) -> io::Result<()> {
+  while let Some(record) = receiver.blocking_recv() {
+    recorder.record(&record)?;
+    if let Some(seq) = record.seq {
+      cursor.set(seq);
+    }
+    if cursor.is_due() {
+      // The frames are flushed first, so the saved seq is never ahead of the archive.
+      recorder.flush()?;
+      cursor.save()?;
+    }
+  }
+  recorder.flush()
+}
+
+/// Replays the archive, calling `on_payload` for each payload.
+#[expect(
+  tail_expr_drop_order,
+  reason = "Each payload is written out before the archive is read further, so the drop order has no effect."
+)]
+async fn replay(
+  archive: ArchiveArgs,
+  firehose: Firehose,
+  mut on_payload: impl FnMut(Payload) -> Result<(), Fatal>,
+) -> Result<(), Fatal> {
+  let ArchiveArgs {
+    path,
+    from_seq,
+    speed,
+  } = archive;
+  let connection = Replay::builder()
+    .path(path)
+    .maybe_from_seq(from_seq)
+    .maybe_speed(speed)
+    .build()?
+    .into_stream();
+  let mut subscription = Repositories::builder()
+    .connection(connection)
+    .handler(firehose)
+    .build();
+  while let Some(payload) = subscription.next().await {
+    let payload = payload.map_err(|e| match e {
+      // The only transport errors of a replay are the ones reading the archive.
+      SubscriptionError::Transport(e) => Fatal::Io(io::Error::other(e)),
+      e => e.into(),
+    })?;
+    warn_data_loss(&payload);
+    on_payload(payload)?;
+  }
+  Ok(())
+}
+
+/// Warns that the relay skipped events, when the cursor was older than its backfill window.
+fn warn_data_loss(payload: &Payload) {
+  if let ProcessedData::Info(ProcessedInfoData { name, message }) = &payload.data {
+    if name.is_data_loss() {
+      eprintln!("Warning: the cursor is outdated, so events were skipped. Message: {message:?}");
+    }
+  }
+}
+
+/// The delay before reconnecting, doubled after each failed attempt.
+struct Backoff(Duration);
+
+impl Default for Backoff {
+  fn default() -> Self {
+    Self(MIN_BACKOFF)
+  }
+}
+
+impl Backoff {
+  async fn wait(&mut self, reason: impl Display) {
+    eprintln!(
+      "Disconnected ({reason}), reconnecting in {}s",
+      self.0.as_secs()
+    );
+    tokio::time::sleep(self.0).await;
+    self.0 = (self.0 * 2).min(MAX_BACKOFF);
+  }
+
+  /// Resets the delay, once the connection works again.
+  const fn reset(&mut self) {
+    self.0 = MIN_BACKOFF;
+  }
+}
+
+/// The last seq, which is written to the cursor file, if any.
+///
+/// It's also written when dropped, so the last seq isn't lost when interrupted.
+struct CursorFile {
+  path: Option<PathBuf>,
+  seq: Option<i64>,
+  saved: Option<i64>,
+  saved_at: Instant,
+}
+
+impl CursorFile {
+  /// Starts from the `--cursor`, or from the seq saved in the cursor file, if it exists.
+  fn open(relay: &RelayArgs) -> io::Result<Self> {
+    let seq = match (relay.cursor, &relay.cursor_file) {
+      (Some(cursor), _) => Some(cursor),
+      (None, Some(path)) => match fs::read_to_string(path) {
+        Ok(seq) => Some(
+          seq
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+      },
+      (None, None) => None,
     };
+    Ok(Self {
+      path: relay.cursor_file.clone(),
+      seq,
+      saved: seq,
+      saved_at: Instant::now(),
+    })
+  }
+
+  const fn set(&mut self, seq: i64) {
+    self.seq = Some(seq);
+  }
+
+  /// Returns `true` if there's a new seq, and the file wasn't written for a while.
+  fn is_due(&self) -> bool {
+    self.path.is_some() && self.seq != self.saved && self.saved_at.elapsed() >= SAVE_INTERVAL
+  }
+
+  /// Writes the seq to a temporary file first, so the cursor file is never left half written.
+  fn save(&mut self) -> io::Result<()> {
+    let (Some(path), Some(seq)) = (&self.path, self.seq) else {
+      return Ok(());
+    };
+    if self.saved != Some(seq) {
+      let tmp = path.with_extension("tmp");
+      fs::write(&tmp, format!("{seq}\n"))?;
+      fs::rename(tmp, path)?;
+      self.saved = Some(seq);
+    }
+    self.saved_at = Instant::now();
+    Ok(())
+  }
+}
+
+impl Drop for CursorFile {
+  fn drop(&mut self) {
+    if let Err(e) = self.save() {
+      eprintln!("Failed to save the cursor: {e}");
+    }
+  }
+}
+
+/// Writes the payloads to the standard output, in the chosen format.
+struct Output {
+  format: Format,
+  out: BufWriter<Stdout>,
+  /// The clock of the Jetstream events.
+  clock: Clock,
+}
+
+impl Output {
+  fn new(format: Format) -> Self {
+    Self {
+      format,
+      out: BufWriter::new(io::stdout()),
+      clock: Clock::default(),
+    }
+  }
+
+  fn write(&mut self, payload: Payload) -> Result<(), Fatal> {
+    match self.format {
+      Format::Pretty => write_pretty(&mut self.out, payload)?,
+      Format::Json => write_json(&mut self.out, &payload)?,
+      Format::Jetstream => {
+        for event in Event::from_payload(payload, &mut self.clock)? {
+          write_json(&mut self.out, &event)?;
+        }
+      }
+    }
+    // Flushed after every payload, so they are shown as soon as they arrive.
+    self.out.flush()?;
+    Ok(())
   }
+}
 
+fn write_json(out: &mut impl Write, value: &impl serde::Serialize) -> io::Result<()> {
+  serde_json::to_writer(&mut *out, value)?;
+  writeln!(out)
+}
+
+fn write_pretty(out: &mut impl Write, payload: Payload) -> io::Result<()> {
+  let ProcessedPayload { seq, data } = payload;
+  let seq = seq.map_or_else(|| String::from("-"), |seq| seq.to_string());
+  match data {
+    ProcessedData::Commit(commit) => write_commit(out, &seq, commit),
+    ProcessedData::Identity(ProcessedIdentityData { did, handle, .. }) => writeln!(
+      out,
+      "[{seq}] IDENTITY {} handle: {}",
+      did.as_str(),
+      handle.as_ref().map_or("-", AsRef::as_ref)
+    ),
+    ProcessedData::Account(ProcessedAccountData {
+      did,
+      active,
+      status,
+      ..
+    }) => writeln!(
+      out,
+      "[{seq}] ACCOUNT {} active: {active}, status: {}",
+      did.as_str(),
+      status.as_deref().unwrap_or("-")
+    ),
+    ProcessedData::Info(ProcessedInfoData { name, message }) => writeln!(
+      out,
+      "[{seq}] INFO {} {}",
+      String::from(name),
+      message.as_deref().unwrap_or_default()
+    ),
+    _ => Ok(()),
+  }
+}
+
+fn write_commit(out: &mut impl Write, seq: &str, commit: ProcessedCommitData) -> io::Result<()> {
+  let ProcessedCommitData { repo, ops, .. } = commit;
+  let Some(ops) = ops else {
+    // The operations aren't sent when the commit is flagged as "too big".
+    return writeln!(out, "[{seq}] COMMIT {} (too big)", repo.as_str());
+  };
+  for Operation {
+    action,
+    path,
+    record,
+    ..
+  } in ops
+  {
+    writeln!(
+      out,
+      "[{seq}] {} {} {path}",
+      action.to_uppercase(),
+      repo.as_str()
+    )?;
+    if let Some(record) = record {
+      match record_to_json(&record, Dialect::Atproto) {
+        Ok(json) => writeln!(out, "  {json}")?,
+        Err(e) => writeln!(out, "  (invalid record: {e})")?,
+      }
+    }
+  }
   Ok(())
 }
 
-fn beauty_print_commit(data: ProcessedCommitData) {
-  let ProcessedCommitData {
-    repo, commit, ops, ..
-  } = data;
-  if let Some(ops) = ops {
-    for r in ops {
-      let Operation {
-        action,
-        path,
-        record,
-      } = r;
-      let print = format!(
-        "\n\n\n#################################  {}  ##################################\n\
-        - Repository (User DID): {}\n\
-        - Commit CID: {}\n\
-        - Path: {path}\n\
-        - Flagged as \"too big\"? ",
-        action.to_uppercase(),
-        repo.as_str(),
-        commit.0,
-      );
-      // Record is only `None` when the commit was flagged as "too big".
-      if let Some(record) = record {
-        println!(
-          "{}No\n\
-          //-------------------------------- Record Info -------------------------------//\n\n\
-          {:?}",
-          print, record
-        );
-      } else {
-        println!(
-          "{}Yes\n\
-          //---------------------------------------------------------------------------//\n\n",
-          print
-        );
+/// Counts the payloads, printing a summary every `interval`, and once more when dropped.
+struct Stats {
+  interval: Duration,
+  started_at: Instant,
+  printed_at: Instant,
+  total: u64,
+  kinds: BTreeMap<&'static str, u64>,
+  collections: BTreeMap<String, u64>,
+}
+
+impl Stats {
+  fn new(interval: Duration) -> Self {
+    Self {
+      interval,
+      started_at: Instant::now(),
+      printed_at: Instant::now(),
+      total: 0,
+      kinds: BTreeMap::new(),
+      collections: BTreeMap::new(),
+    }
+  }
+
+  fn count(&mut self, payload: &Payload) {
+    let kind = match &payload.data {
+      ProcessedData::Commit(commit) => {
+        for op in commit.ops.iter().flatten() {
+          let collection = op.path.split_once('/').map_or(&*op.path, |(c, _)| c);
+          *self.collections.entry(collection.to_owned()).or_default() += 1;
+        }
+        "commit"
       }
+      ProcessedData::Identity(_) => "identity",
+      ProcessedData::Account(_) => "account",
+      ProcessedData::Handle(_) => "handle",
+      ProcessedData::Migrate(_) => "migrate",
+      ProcessedData::Tombstone(_) => "tombstone",
+      ProcessedData::Info(_) => "info",
+    };
+    *self.kinds.entry(kind).or_default() += 1;
+    self.total += 1;
+
+    if self.printed_at.elapsed() >= self.interval {
+      self.print();
+    }
+  }
+
+  #[expect(
+    clippy::cast_precision_loss,
+    reason = "The rate is only shown with one decimal."
+  )]
+  fn print(&mut self) {
+    let elapsed = self.started_at.elapsed().as_secs_f64();
+    println!(
+      "{} payloads in {elapsed:.0}s ({:.1}/s)",
+      self.total,
+      self.total as f64 / elapsed.max(1.0)
+    );
+    for (kind, count) in &self.kinds {
+      println!("  {kind:<12} {count}");
     }
+    let mut collections = self.collections.iter().collect::<Vec<_>>();
+    collections.sort_by_key(|&(_, count)| Reverse(count));
+    for (collection, count) in collections {
+      println!("  {collection:<40} {count}");
+    }
+    self.printed_at = Instant::now();
+  }
+}
+
+impl Drop for Stats {
+  fn drop(&mut self) {
+    self.print();
   }
 }